@@ -0,0 +1,318 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use crate::models::DownloadRequest;
+use crate::paths::{library_metadata_dir, library_thumbnails_dir, library_videos_dir};
+use crate::files::{find_downloaded_video_file, find_info_json};
+use crate::thumbnails::find_existing_thumbnail;
+use crate::tooling::{resolve_ffmpeg, resolve_override};
+use crate::AUDIO_FORMATS;
+
+/// 音声のみで保存する場合の出力形式を返す。動画として保存する場合は None。
+/// 旧来の quality = "audio" は m4a として扱う。対応していない形式はエラーにする。
+pub(crate) fn resolve_audio_format(quality: Option<&str>, audio_format: Option<&str>) -> Result<Option<&'static str>, String> {
+    let requested = audio_format.map(|value| value.trim().to_lowercase());
+    if let Some(requested) = requested.filter(|value| !value.is_empty()) {
+        return AUDIO_FORMATS
+            .iter()
+            .copied()
+            .find(|format| *format == requested)
+            .map(Some)
+            .ok_or_else(|| format!("対応していない音声形式です: {}（{}）", requested, AUDIO_FORMATS.join(" / ")));
+    }
+    Ok((quality == Some("audio")).then_some("m4a"))
+}
+
+/// 音声のみで保存する場合の -f 引数
+pub(crate) fn audio_format_selector(audio_format: &str) -> &'static str {
+    match audio_format {
+        "m4a" => "bestaudio[ext=m4a]/bestaudio/best",
+        "opus" => "bestaudio[acodec=opus]/bestaudio/best",
+        _ => "bestaudio/best",
+    }
+}
+
+/// 音声抽出とタグ埋め込みのための yt-dlp 引数
+pub(crate) fn build_audio_args(audio_format: &str) -> Vec<String> {
+    [
+        "-f",
+        audio_format_selector(audio_format),
+        "--extract-audio",
+        "--audio-format",
+        audio_format,
+        "--audio-quality",
+        "0",
+        "--embed-metadata",
+        // 既定ではアーティストに投稿者名が入るため、チャンネル名で上書きする
+        "--parse-metadata",
+        "%(channel,uploader)s:%(meta_artist)s",
+        "--parse-metadata",
+        "%(upload_date)s:%(meta_date)s",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect()
+}
+
+pub(crate) fn image_mime_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .as_deref()
+    {
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        Some("gif") => "image/gif",
+        _ => "image/jpeg",
+    }
+}
+
+const BASE64_TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_TABLE[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Ogg (opus) のカバー画像は FLAC の PICTURE ブロックを base64 にした
+/// METADATA_BLOCK_PICTURE タグで持つ。画像サイズは 0 (不明) としてよい。
+pub(crate) fn flac_picture_block(mime: &str, data: &[u8]) -> Vec<u8> {
+    let mut block = Vec::with_capacity(32 + mime.len() + data.len());
+    block.extend_from_slice(&3u32.to_be_bytes()); // 表紙
+    block.extend_from_slice(&(mime.len() as u32).to_be_bytes());
+    block.extend_from_slice(mime.as_bytes());
+    block.extend_from_slice(&0u32.to_be_bytes()); // 説明文なし
+    for _ in 0..4 {
+        block.extend_from_slice(&0u32.to_be_bytes()); // 幅・高さ・色深度・色数
+    }
+    block.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    block.extend_from_slice(data);
+    block
+}
+
+/// ffmetadata 形式では = ; # \ と改行をエスケープする
+pub(crate) fn escape_ffmetadata(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// opus 用の ffmetadata ファイルの内容。-map_metadata で既存タグを置き換えるため、
+/// 埋め込み済みのタイトル・チャンネル・投稿日も書き直す。
+pub(crate) fn build_opus_ffmetadata(info: &serde_json::Value, mime: &str, image: &[u8]) -> String {
+    let text = |key: &str| info.get(key).and_then(|v| v.as_str()).filter(|s| !s.is_empty());
+    let mut lines = vec![";FFMETADATA1".to_string()];
+    let tags = [
+        ("title", text("title")),
+        ("artist", text("channel").or_else(|| text("uploader"))),
+        ("date", text("upload_date")),
+    ];
+    for (key, value) in tags {
+        if let Some(value) = value {
+            lines.push(format!("{}={}", key, escape_ffmetadata(value)));
+        }
+    }
+    lines.push(format!(
+        "METADATA_BLOCK_PICTURE={}",
+        escape_ffmetadata(&base64_encode(&flac_picture_block(mime, image)))
+    ));
+    lines.join("\n") + "\n"
+}
+
+/// カバー画像を埋め込む ffmpeg 引数。
+/// m4a / mp3 は画像を attached_pic として追加し、opus はメタデータファイル経由で埋め込む。
+pub(crate) fn build_cover_art_args(
+    audio_format: &str,
+    input: &Path,
+    cover: &Path,
+    opus_metadata: Option<&Path>,
+    output: &Path,
+) -> Vec<String> {
+    let mut args: Vec<String> = vec!["-y".into(), "-v".into(), "error".into(), "-i".into()];
+    args.push(input.to_string_lossy().to_string());
+    if audio_format == "opus" {
+        if let Some(metadata) = opus_metadata {
+            args.extend(["-f".into(), "ffmetadata".into(), "-i".into()]);
+            args.push(metadata.to_string_lossy().to_string());
+            args.extend(["-map".into(), "0:a".into(), "-map_metadata".into(), "1".into()]);
+        }
+    } else {
+        args.push("-i".into());
+        args.push(cover.to_string_lossy().to_string());
+        args.extend([
+            "-map".into(),
+            "0:a".into(),
+            "-map".into(),
+            "1:v".into(),
+            "-disposition:v:0".into(),
+            "attached_pic".into(),
+        ]);
+        if audio_format == "mp3" {
+            args.extend(["-id3v2_version".into(), "3".into()]);
+        }
+    }
+    args.extend(["-c".into(), "copy".into()]);
+    args.push(output.to_string_lossy().to_string());
+    args
+}
+
+pub(crate) fn temp_path(path: &Path, suffix: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}.{}", name, suffix))
+}
+
+/// サムネイルキャッシュの画像を音声ファイルにカバー画像として埋め込む。
+/// キャッシュが無い場合は何もしない。
+pub(crate) fn embed_cover_art(request: &DownloadRequest, audio_format: &str) -> Result<(), String> {
+    let Some(cover) = find_existing_thumbnail(&library_thumbnails_dir(&request.output_dir), &request.id) else {
+        return Ok(());
+    };
+    let audio = find_downloaded_video_file(&library_videos_dir(&request.output_dir), &request.id)
+        .ok_or_else(|| "音声ファイルが見つかりません。".to_string())?;
+    let extension = audio
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or(audio_format)
+        .to_string();
+    // 拡張子で出力形式が決まるため、一時ファイルも同じ拡張子にする
+    let output = temp_path(&audio, &format!("cover.{}", extension));
+
+    let metadata_path = if audio_format == "opus" {
+        let info = find_info_json(&library_metadata_dir(&request.output_dir), &request.id)
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+            .unwrap_or(serde_json::Value::Null);
+        let image = fs::read(&cover).map_err(|e| format!("サムネイルの読み込みに失敗しました: {}", e))?;
+        let path = temp_path(&audio, "ffmeta.txt");
+        fs::write(&path, build_opus_ffmetadata(&info, image_mime_type(&cover), &image))
+            .map_err(|e| format!("メタデータファイルの作成に失敗しました: {}", e))?;
+        Some(path)
+    } else {
+        None
+    };
+
+    let ffmpeg = resolve_override(request.ffmpeg_path.clone()).unwrap_or_else(resolve_ffmpeg);
+    let mut command = Command::new(ffmpeg);
+    #[cfg(windows)]
+    command.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    let result = command
+        .args(build_cover_art_args(audio_format, &audio, &cover, metadata_path.as_deref(), &output))
+        .output();
+    if let Some(path) = &metadata_path {
+        let _ = fs::remove_file(path);
+    }
+    let output_status = result.map_err(|e| format!("ffmpegの起動に失敗しました: {}", e))?;
+    if !output_status.status.success() {
+        let _ = fs::remove_file(&output);
+        return Err(format!(
+            "カバー画像の埋め込みに失敗しました: {}",
+            String::from_utf8_lossy(&output_status.stderr).trim()
+        ));
+    }
+    fs::rename(&output, &audio).map_err(|e| {
+        let _ = fs::remove_file(&output);
+        format!("音声ファイルの置き換えに失敗しました: {}", e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // =========================================================
+    // resolve_audio_format / build_audio_args
+    // =========================================================
+
+    #[test]
+    fn audio_format_selection() {
+        assert_eq!(resolve_audio_format(Some("audio"), None), Ok(Some("m4a")));
+        assert_eq!(resolve_audio_format(Some("1080p"), Some(" OPUS ")), Ok(Some("opus")));
+        assert_eq!(resolve_audio_format(None, Some("mp3")), Ok(Some("mp3")));
+        assert!(resolve_audio_format(None, Some("flac")).is_err_and(|err| err.contains("flac")));
+        assert_eq!(resolve_audio_format(Some("best"), None), Ok(None));
+        assert_eq!(resolve_audio_format(Some("audio"), Some("")), Ok(Some("m4a")));
+    }
+
+    #[test]
+    fn audio_args_extract_and_tag() {
+        let args = build_audio_args("opus");
+        let joined = args.join(" ");
+        assert!(joined.contains("--extract-audio --audio-format opus"));
+        assert!(joined.contains("bestaudio[acodec=opus]"));
+        assert!(args.contains(&"--embed-metadata".to_string()));
+        assert!(args.contains(&"%(channel,uploader)s:%(meta_artist)s".to_string()));
+        assert!(!args.contains(&"--merge-output-format".to_string()));
+    }
+
+    // =========================================================
+    // cover art
+    // =========================================================
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn picture_block_layout() {
+        let block = flac_picture_block("image/jpeg", &[1, 2, 3]);
+        assert_eq!(&block[0..4], &[0, 0, 0, 3]);
+        assert_eq!(&block[4..8], &[0, 0, 0, 10]);
+        assert_eq!(&block[8..18], b"image/jpeg");
+        assert_eq!(block.len(), 4 + 4 + 10 + 4 + 16 + 4 + 3);
+        assert_eq!(&block[block.len() - 3..], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn opus_ffmetadata_escapes_values() {
+        let info = json!({ "title": "A=B; #1", "channel": "Ch\\name", "upload_date": "20240115" });
+        let content = build_opus_ffmetadata(&info, "image/jpeg", b"x");
+        assert!(content.starts_with(";FFMETADATA1\n"));
+        assert!(content.contains("title=A\\=B\\; \\#1\n"));
+        assert!(content.contains("artist=Ch\\\\name\n"));
+        assert!(content.contains("date=20240115\n"));
+        assert!(content.contains("METADATA_BLOCK_PICTURE="));
+    }
+
+    #[test]
+    fn cover_args_per_format() {
+        let input = Path::new("/v/a [id].mp3");
+        let cover = Path::new("/t/id.jpg");
+        let output = Path::new("/v/a [id].mp3.cover.mp3");
+        let mp3 = build_cover_art_args("mp3", input, cover, None, output);
+        assert!(mp3.join(" ").contains("-map 1:v -disposition:v:0 attached_pic -id3v2_version 3"));
+        assert_eq!(mp3.last().unwrap(), "/v/a [id].mp3.cover.mp3");
+
+        let opus = build_cover_art_args("opus", input, cover, Some(Path::new("/v/meta.txt")), output);
+        let joined = opus.join(" ");
+        assert!(joined.contains("-f ffmetadata -i /v/meta.txt -map 0:a -map_metadata 1"));
+        assert!(!joined.contains("/t/id.jpg"));
+    }
+}
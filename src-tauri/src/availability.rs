@@ -0,0 +1,600 @@
+use std::collections::HashSet;
+use std::fs;
+use std::process::Command;
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State};
+use crate::models::{
+    AvailabilityRecord, AvailabilityState, AvailabilityStatus, AvailabilityTransition, FailureKind, NetworkSettings,
+    OnlyCopyDetected, PersistedAvailability, PersistedSettings, VersionedAvailability,
+};
+use crate::failure::classify_failure;
+use crate::journal::now_ms;
+use crate::network::{apply_network_args, effective_network_settings};
+use crate::paths::{atomic_write, availability_file_path, videos_file_path, write_error_log};
+use crate::runner::{LineAction, ProcessRunner, RunOptions, YtDlpRunner};
+use crate::state::{parse_versioned_videos, read_settings, write_settings};
+use crate::tooling::{apply_cookies_args, resolve_override, resolve_yt_dlp};
+use crate::{
+    AVAILABILITY_CHECKS_PER_TICK, AVAILABILITY_CHECK_SPACING_SECS, AVAILABILITY_CHECK_TIMEOUT_SECS,
+    AVAILABILITY_RECHECK_INTERVAL_SECS, AVAILABILITY_SCHEMA_VERSION, AVAILABILITY_WATCH_TICK_SECS,
+};
+
+/// 確認対象になるライブラリの動画（ダウンロード済みのもの）
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct LibraryVideo {
+    pub id: String,
+    pub url: String,
+    pub title: Option<String>,
+}
+
+impl AvailabilityStatus {
+    /// YouTube 上から見られなくなり、手元の保存分が唯一のコピーになる状態か
+    pub(crate) fn is_gone(self) -> bool {
+        matches!(self, AvailabilityStatus::Private | AvailabilityStatus::Deleted)
+    }
+}
+
+pub(crate) fn parse_versioned_availability(content: &str) -> PersistedAvailability {
+    if let Ok(wrapper) = serde_json::from_str::<VersionedAvailability>(content) {
+        if wrapper.version <= AVAILABILITY_SCHEMA_VERSION {
+            return wrapper.data;
+        }
+        return PersistedAvailability::default();
+    }
+    serde_json::from_str::<PersistedAvailability>(content).unwrap_or_default()
+}
+
+/// videos.json の内容からダウンロード済みの動画を取り出す
+pub(crate) fn downloaded_library_videos(videos: &[serde_json::Value]) -> Vec<LibraryVideo> {
+    let text = |video: &serde_json::Value, key: &str| {
+        video
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+    videos
+        .iter()
+        .filter(|video| video.get("downloadStatus").and_then(|v| v.as_str()) == Some("downloaded"))
+        .filter_map(|video| {
+            let id = text(video, "id")?;
+            let url = text(video, "webpageUrl")
+                .or_else(|| text(video, "sourceUrl"))
+                .unwrap_or_else(|| format!("https://www.youtube.com/watch?v={}", id));
+            Some(LibraryVideo {
+                id,
+                url,
+                title: text(video, "title"),
+            })
+        })
+        .collect()
+}
+
+fn read_library_videos(app: &AppHandle) -> Vec<LibraryVideo> {
+    let videos = videos_file_path(app)
+        .ok()
+        .filter(|path| path.exists())
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|content| parse_versioned_videos(&content).videos)
+        .unwrap_or_default();
+    downloaded_library_videos(&videos)
+}
+
+/// 確認が必要な動画を、一度も確認していないもの・前回の確認が古いものから順に返す。
+/// 削除済みと判定した動画は戻ることがないため対象にしない。
+pub(crate) fn due_availability_videos(
+    library: &[LibraryVideo],
+    records: &[AvailabilityRecord],
+    now_ms: u64,
+    limit: usize,
+) -> Vec<LibraryVideo> {
+    let interval_ms = AVAILABILITY_RECHECK_INTERVAL_SECS * 1000;
+    let mut due: Vec<(u64, &LibraryVideo)> = library
+        .iter()
+        .filter_map(|video| {
+            let record = records.iter().find(|record| record.video_id == video.id);
+            if record.is_some_and(|record| record.status == Some(AvailabilityStatus::Deleted)) {
+                return None;
+            }
+            let checked = record.and_then(|record| record.last_checked_ms).unwrap_or(0);
+            if checked != 0 && checked.saturating_add(interval_ms) > now_ms {
+                return None;
+            }
+            Some((checked, video))
+        })
+        .collect();
+    due.sort_by_key(|(checked, _)| *checked);
+    due.into_iter().take(limit).map(|(_, video)| video.clone()).collect()
+}
+
+/// yt-dlp の "availability" の値を公開状態に変換する。
+/// 値が取れなかった "NA" や、ログインが必要なだけの "needs_auth" は公開状態を判定できないため None。
+fn status_from_field(value: &str) -> Option<AvailabilityStatus> {
+    match value.trim() {
+        "public" => Some(AvailabilityStatus::Public),
+        "unlisted" => Some(AvailabilityStatus::Unlisted),
+        "private" => Some(AvailabilityStatus::Private),
+        "subscriber_only" | "premium_only" => Some(AvailabilityStatus::MembersOnly),
+        _ => None,
+    }
+}
+
+/// 取得に失敗したときのエラーから公開状態を判定する。通信エラーなど判定できないものは None。
+fn status_from_failure(kind: FailureKind) -> Option<AvailabilityStatus> {
+    match kind {
+        FailureKind::Private => Some(AvailabilityStatus::Private),
+        FailureKind::Deleted | FailureKind::AccountTerminated => Some(AvailabilityStatus::Deleted),
+        FailureKind::MembersOnly => Some(AvailabilityStatus::MembersOnly),
+        // 年齢制限は動画自体は公開されている
+        FailureKind::AgeRestricted => Some(AvailabilityStatus::Public),
+        _ => None,
+    }
+}
+
+/// 公開状態だけを出力させる軽量な確認コマンド
+pub(crate) fn availability_check_command(
+    yt_dlp: &str,
+    url: &str,
+    settings: &PersistedSettings,
+    network: &NetworkSettings,
+) -> Command {
+    let mut command = Command::new(yt_dlp);
+    #[cfg(windows)]
+    command.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    command
+        .arg("--skip-download")
+        .arg("--no-playlist")
+        .arg("--no-warnings")
+        .arg("--ignore-no-formats-error")
+        .arg("--print")
+        .arg("availability");
+    apply_cookies_args(
+        &mut command,
+        settings.cookies_source.as_deref(),
+        settings.cookies_file.as_deref(),
+        settings.cookies_browser.as_deref(),
+    );
+    if let Some(remote) = &settings.remote_components {
+        if !remote.trim().is_empty() {
+            command.arg("--remote-components").arg(remote);
+        }
+    }
+    apply_network_args(&mut command, network);
+    command.arg(url);
+    command
+}
+
+/// 確認コマンドを実行して公開状態を返す。判定できなかった場合は Err。
+pub(crate) fn probe_availability(runner: &dyn YtDlpRunner, command: Command) -> Result<AvailabilityStatus, String> {
+    let options = RunOptions {
+        timeout: Some(Duration::from_secs(AVAILABILITY_CHECK_TIMEOUT_SECS)),
+        process: None,
+    };
+    let output = runner.run(command, &options, &mut |_, _| LineAction::Continue)?;
+    if output.timed_out {
+        return Err("公開状態の確認がタイムアウトしました。".to_string());
+    }
+    if output.success {
+        if let Some(status) = output.stdout.lines().rev().find_map(status_from_field) {
+            return Ok(status);
+        }
+    }
+    let kind = classify_failure(&output.stdout, &output.stderr);
+    status_from_failure(kind).ok_or_else(|| {
+        if output.stderr.trim().is_empty() {
+            format!("公開状態を判定できませんでした（{}）。", kind.label())
+        } else {
+            output.stderr.trim().to_string()
+        }
+    })
+}
+
+/// 確認結果を記録に反映する。手元の保存分が唯一のコピーになった場合は true。
+pub(crate) fn apply_availability_result(
+    record: &mut AvailabilityRecord,
+    result: Result<AvailabilityStatus, String>,
+    now_ms: u64,
+) -> bool {
+    record.last_checked_ms = Some(now_ms);
+    let status = match result {
+        Ok(status) => status,
+        Err(err) => {
+            record.last_error = Some(err);
+            return false;
+        }
+    };
+    record.last_error = None;
+    if let Some(previous) = record.status.filter(|previous| *previous != status) {
+        record.transitions.push(AvailabilityTransition {
+            from: previous,
+            to: status,
+            at_ms: now_ms,
+        });
+    }
+    record.status = Some(status);
+    if !status.is_gone() {
+        record.only_copy_since_ms = None;
+        return false;
+    }
+    if record.only_copy_since_ms.is_some() {
+        return false;
+    }
+    record.only_copy_since_ms = Some(now_ms);
+    true
+}
+
+fn persist_availability(app: &AppHandle, records: &[AvailabilityRecord]) {
+    let Ok(path) = availability_file_path(app) else {
+        return;
+    };
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let wrapper = VersionedAvailability {
+        version: AVAILABILITY_SCHEMA_VERSION,
+        data: PersistedAvailability {
+            records: records.to_vec(),
+        },
+    };
+    if let Ok(content) = serde_json::to_string_pretty(&wrapper) {
+        let _ = atomic_write(&path, content.as_bytes());
+    }
+}
+
+fn snapshot(state: &AvailabilityState) -> Vec<AvailabilityRecord> {
+    state.records.lock().map(|records| records.clone()).unwrap_or_default()
+}
+
+fn commit_availability(app: &AppHandle, state: &AvailabilityState) -> Vec<AvailabilityRecord> {
+    let records = snapshot(state);
+    persist_availability(app, &records);
+    let _ = app.emit("availability-updated", records.clone());
+    records
+}
+
+/// 1本分の公開状態を確認して記録する。確認中の場合は何もしない。
+fn check_video_availability(app: &AppHandle, video: &LibraryVideo) {
+    let state = app.state::<AvailabilityState>();
+    match state.checking.lock() {
+        Ok(mut checking) => {
+            if !checking.insert(video.id.clone()) {
+                return;
+            }
+        }
+        Err(_) => return,
+    }
+
+    let settings = read_settings(app);
+    let yt_dlp = resolve_override(settings.yt_dlp_path.clone()).unwrap_or_else(resolve_yt_dlp);
+    let network = effective_network_settings(&settings);
+    let command = availability_check_command(&yt_dlp, &video.url, &settings, &network);
+    let result = probe_availability(&ProcessRunner, command);
+    let now = now_ms();
+
+    let mut only_copy = None;
+    if let Ok(mut records) = state.records.lock() {
+        let index = match records.iter().position(|record| record.video_id == video.id) {
+            Some(index) => index,
+            None => {
+                records.push(AvailabilityRecord {
+                    video_id: video.id.clone(),
+                    status: None,
+                    last_checked_ms: None,
+                    last_error: None,
+                    transitions: Vec::new(),
+                    only_copy_since_ms: None,
+                });
+                records.len() - 1
+            }
+        };
+        let record = &mut records[index];
+        if apply_availability_result(record, result, now) {
+            only_copy = record.status;
+        }
+    }
+    commit_availability(app, &state);
+    if let Ok(mut checking) = state.checking.lock() {
+        checking.remove(&video.id);
+    }
+
+    if let Some(status) = only_copy {
+        let _ = app.emit(
+            "only-copy-detected",
+            OnlyCopyDetected {
+                video_id: video.id.clone(),
+                title: video.title.clone(),
+                status,
+                at_ms: now,
+            },
+        );
+    }
+}
+
+/// ライブラリから消えた動画の記録を捨て、確認が必要な動画を少しずつ確認する
+fn run_availability_tick(app: &AppHandle) {
+    // 定期的に yt-dlp を起動するため、設定で有効にした場合だけ確認する
+    if !read_settings(app).availability_watch.unwrap_or(false) {
+        return;
+    }
+    let library = read_library_videos(app);
+    let state = app.state::<AvailabilityState>();
+    let library_ids: HashSet<&str> = library.iter().map(|video| video.id.as_str()).collect();
+    let pruned = match state.records.lock() {
+        Ok(mut records) => {
+            let before = records.len();
+            records.retain(|record| library_ids.contains(record.video_id.as_str()));
+            records.len() != before
+        }
+        Err(_) => false,
+    };
+    if pruned {
+        commit_availability(app, &state);
+    }
+
+    let due = due_availability_videos(&library, &snapshot(&state), now_ms(), AVAILABILITY_CHECKS_PER_TICK);
+    for (index, video) in due.iter().enumerate() {
+        if index > 0 {
+            std::thread::sleep(Duration::from_secs(AVAILABILITY_CHECK_SPACING_SECS));
+        }
+        check_video_availability(app, video);
+    }
+}
+
+/// 起動時に確認結果を復元し、保存済み動画の公開状態を定期的に確認するスレッドを起動する
+pub(crate) fn start_availability_watch(app: &AppHandle) {
+    let state = app.state::<AvailabilityState>();
+    let persisted = availability_file_path(app)
+        .ok()
+        .filter(|path| path.exists())
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|content| parse_versioned_availability(&content))
+        .unwrap_or_default();
+    if let Ok(mut records) = state.records.lock() {
+        *records = persisted.records;
+    }
+
+    let app = app.clone();
+    std::thread::spawn(move || loop {
+        // 起動直後の通信と重ならないよう、最初の確認は1周期待ってから行う
+        std::thread::sleep(Duration::from_secs(AVAILABILITY_WATCH_TICK_SECS));
+        run_availability_tick(&app);
+    });
+}
+
+#[tauri::command]
+pub fn get_availability_records(state: State<AvailabilityState>) -> Result<Vec<AvailabilityRecord>, String> {
+    Ok(snapshot(&state))
+}
+
+/// 巡回を待たずに1本だけ確認する。結果は availability-updated / only-copy-detected で通知する。
+#[tauri::command]
+pub fn check_availability_now(app: AppHandle, video_id: String) -> Result<(), String> {
+    let video = read_library_videos(&app)
+        .into_iter()
+        .find(|video| video.id == video_id)
+        .ok_or_else(|| "ダウンロード済みの動画が見つかりませんでした。".to_string())?;
+    std::thread::spawn(move || check_video_availability(&app, &video));
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_availability_watch(app: AppHandle, enabled: bool) -> Result<(), String> {
+    let mut settings = read_settings(&app);
+    settings.availability_watch = Some(enabled);
+    write_settings(&app, settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::fake::{FakeRun, FakeRunner};
+    use serde_json::json;
+
+    fn record(status: Option<AvailabilityStatus>, last_checked_ms: Option<u64>) -> AvailabilityRecord {
+        AvailabilityRecord {
+            video_id: "abc".to_string(),
+            status,
+            last_checked_ms,
+            last_error: None,
+            transitions: Vec::new(),
+            only_copy_since_ms: None,
+        }
+    }
+
+    fn library_video(id: &str) -> LibraryVideo {
+        LibraryVideo {
+            id: id.to_string(),
+            url: format!("https://www.youtube.com/watch?v={}", id),
+            title: None,
+        }
+    }
+
+    fn probe(run: FakeRun) -> Result<AvailabilityStatus, String> {
+        probe_availability(&FakeRunner::new(vec![run]), Command::new("yt-dlp"))
+    }
+
+    // =========================================================
+    // probe_availability (FakeRunner)
+    // =========================================================
+
+    #[test]
+    fn probe_reads_printed_availability() {
+        assert_eq!(probe(FakeRun::ok("public\n")), Ok(AvailabilityStatus::Public));
+        assert_eq!(probe(FakeRun::ok("unlisted\n")), Ok(AvailabilityStatus::Unlisted));
+        assert_eq!(probe(FakeRun::ok("subscriber_only\n")), Ok(AvailabilityStatus::MembersOnly));
+    }
+
+    #[test]
+    fn probe_treats_missing_availability_as_inconclusive() {
+        assert!(probe(FakeRun::ok("NA\n")).is_err());
+        assert!(probe(FakeRun::ok("needs_auth\n")).is_err());
+    }
+
+    #[test]
+    fn inconclusive_probe_keeps_previous_status() {
+        let mut item = record(Some(AvailabilityStatus::Unlisted), Some(1));
+        assert!(!apply_availability_result(&mut item, probe(FakeRun::ok("NA\n")), 10));
+        assert_eq!(item.status, Some(AvailabilityStatus::Unlisted));
+        assert_eq!(item.last_checked_ms, Some(10));
+        assert!(item.last_error.is_some());
+    }
+
+    #[test]
+    fn probe_classifies_failures() {
+        assert_eq!(
+            probe(FakeRun::failed("ERROR: [youtube] abc: Private video. Sign in if you've been granted access to this video")),
+            Ok(AvailabilityStatus::Private)
+        );
+        assert_eq!(
+            probe(FakeRun::failed("ERROR: [youtube] abc: Video unavailable. This video has been removed by the uploader")),
+            Ok(AvailabilityStatus::Deleted)
+        );
+        assert_eq!(
+            probe(FakeRun::failed("ERROR: [youtube] abc: Video unavailable. This video is no longer available because the YouTube account associated with this video has been terminated.")),
+            Ok(AvailabilityStatus::Deleted)
+        );
+    }
+
+    #[test]
+    fn probe_network_error_is_inconclusive() {
+        let result = probe(FakeRun::failed("ERROR: [youtube] abc: Unable to download webpage: <urlopen error timed out>"));
+        assert!(result.is_err_and(|err| err.contains("Unable to download webpage")));
+        let result = probe(FakeRun {
+            timed_out: true,
+            ..Default::default()
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn check_command_prints_only_availability() {
+        let command = availability_check_command(
+            "yt-dlp",
+            "https://www.youtube.com/watch?v=abc",
+            &PersistedSettings::default(),
+            &NetworkSettings::default(),
+        );
+        let args: Vec<String> = command.get_args().map(|arg| arg.to_string_lossy().to_string()).collect();
+        assert!(args.windows(2).any(|pair| pair == ["--print", "availability"]));
+        assert!(args.contains(&"--skip-download".to_string()));
+        assert_eq!(args.last().map(String::as_str), Some("https://www.youtube.com/watch?v=abc"));
+    }
+
+    // =========================================================
+    // apply_availability_result
+    // =========================================================
+
+    #[test]
+    fn transition_to_private_flags_only_copy_once() {
+        let mut rec = record(Some(AvailabilityStatus::Public), Some(1));
+        assert!(apply_availability_result(&mut rec, Ok(AvailabilityStatus::Private), 100));
+        assert_eq!(
+            rec.transitions,
+            vec![AvailabilityTransition {
+                from: AvailabilityStatus::Public,
+                to: AvailabilityStatus::Private,
+                at_ms: 100,
+            }]
+        );
+        assert_eq!(rec.only_copy_since_ms, Some(100));
+
+        // 非公開から削除に変わっても通知は1回だけ
+        assert!(!apply_availability_result(&mut rec, Ok(AvailabilityStatus::Deleted), 200));
+        assert_eq!(rec.transitions.len(), 2);
+        assert_eq!(rec.only_copy_since_ms, Some(100));
+    }
+
+    #[test]
+    fn first_check_records_no_transition() {
+        let mut rec = record(None, None);
+        assert!(!apply_availability_result(&mut rec, Ok(AvailabilityStatus::Unlisted), 50));
+        assert!(rec.transitions.is_empty());
+        assert_eq!(rec.status, Some(AvailabilityStatus::Unlisted));
+
+        let mut rec = record(None, None);
+        assert!(apply_availability_result(&mut rec, Ok(AvailabilityStatus::Deleted), 50));
+    }
+
+    #[test]
+    fn back_online_clears_only_copy() {
+        let mut rec = record(Some(AvailabilityStatus::Private), Some(1));
+        rec.only_copy_since_ms = Some(1);
+        assert!(!apply_availability_result(&mut rec, Ok(AvailabilityStatus::Public), 10));
+        assert_eq!(rec.only_copy_since_ms, None);
+    }
+
+    #[test]
+    fn inconclusive_check_keeps_status() {
+        let mut rec = record(Some(AvailabilityStatus::Public), Some(1));
+        assert!(!apply_availability_result(&mut rec, Err("network".to_string()), 10));
+        assert_eq!(rec.status, Some(AvailabilityStatus::Public));
+        assert_eq!(rec.last_error.as_deref(), Some("network"));
+        assert_eq!(rec.last_checked_ms, Some(10));
+        assert!(rec.transitions.is_empty());
+    }
+
+    // =========================================================
+    // due_availability_videos / downloaded_library_videos
+    // =========================================================
+
+    #[test]
+    fn due_orders_by_oldest_check_and_skips_deleted() {
+        let interval_ms = AVAILABILITY_RECHECK_INTERVAL_SECS * 1000;
+        let now = interval_ms * 3;
+        let library = vec![library_video("recent"), library_video("old"), library_video("never"), library_video("gone")];
+        let mut records = vec![
+            record(Some(AvailabilityStatus::Public), Some(now - 1)),
+            record(Some(AvailabilityStatus::Public), Some(now - interval_ms)),
+            record(Some(AvailabilityStatus::Deleted), Some(1)),
+        ];
+        records[0].video_id = "recent".to_string();
+        records[1].video_id = "old".to_string();
+        records[2].video_id = "gone".to_string();
+        let due: Vec<String> = due_availability_videos(&library, &records, now, 5)
+            .into_iter()
+            .map(|video| video.id)
+            .collect();
+        assert_eq!(due, vec!["never", "old"]);
+        assert_eq!(due_availability_videos(&library, &records, now, 1).len(), 1);
+    }
+
+    #[test]
+    fn library_videos_only_downloaded() {
+        let videos = vec![
+            json!({ "id": "a", "title": "A", "downloadStatus": "downloaded", "webpageUrl": "https://youtu.be/a" }),
+            json!({ "id": "b", "downloadStatus": "pending", "sourceUrl": "https://youtu.be/b" }),
+            json!({ "id": "c", "downloadStatus": "downloaded", "sourceUrl": "https://www.youtube.com/watch?v=c" }),
+            json!({ "id": "d", "downloadStatus": "downloaded" }),
+        ];
+        let library = downloaded_library_videos(&videos);
+        assert_eq!(library.len(), 3);
+        assert_eq!(library[0].url, "https://youtu.be/a");
+        assert_eq!(library[0].title.as_deref(), Some("A"));
+        assert_eq!(library[1].url, "https://www.youtube.com/watch?v=c");
+        assert_eq!(library[2].url, "https://www.youtube.com/watch?v=d");
+    }
+
+    // =========================================================
+    // parse_versioned_availability
+    // =========================================================
+
+    #[test]
+    fn parse_availability_roundtrip_and_future_version() {
+        let content = serde_json::to_string(&json!({
+            "version": 1,
+            "data": { "records": [
+                { "videoId": "abc", "status": "private",
+                  "transitions": [{ "from": "public", "to": "private", "atMs": 5 }],
+                  "onlyCopySinceMs": 5 }
+            ] }
+        }))
+        .unwrap();
+        let parsed = parse_versioned_availability(&content);
+        assert_eq!(parsed.records[0].status, Some(AvailabilityStatus::Private));
+        assert_eq!(parsed.records[0].transitions[0].from, AvailabilityStatus::Public);
+        assert!(parse_versioned_availability(r#"{"version":99,"data":{"records":[]}}"#).records.is_empty());
+        assert!(parse_versioned_availability("not json").records.is_empty());
+    }
+}
@@ -1,19 +1,19 @@
-use std::io::{BufRead, BufReader};
 use std::{fs, path::{Path, PathBuf}};
-use std::process::{Command, Stdio};
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
-#[cfg(windows)]
-use std::os::windows::process::CommandExt;
+use std::process::Command;
 use std::time::Duration;
-use tauri::{AppHandle, Emitter};
-use crate::models::{CommentItem, CommentRun, CommentEmoji, CommentsFinished};
+use tauri::{AppHandle, Emitter, State};
+use crate::models::{CommentItem, CommentRun, CommentEmoji, CommentsFinished, FailureKind, JobKind, JobProcessState, JournalEntry, NetworkSettings, RetryPolicy};
+use crate::failure::classify_failure;
+use crate::retry::{effective_retry_policy, jitter_sample, plan_retry_with};
+use crate::runner::{job_process_key, run_job, LineAction, OutputStream, ProcessRunner, RunOptions, RunOutput, Verdict, YtDlpJob};
+use crate::journal::{now_ms, record_job};
 use crate::paths::{library_metadata_dir, library_comments_dir, collect_files_recursive, write_error_log};
 use crate::metadata::parse_video_metadata_value;
 use crate::tooling::{apply_cookies_args, resolve_yt_dlp, resolve_override, resolve_ffmpeg};
+use crate::network::{apply_network_args, effective_network_settings};
 use crate::files::{find_info_json, is_live_chat_file, comments_file_exists};
-use crate::{YTDLP_NONE_DECODE_ERROR, YTDLP_NONE_DECODE_RETRY_MAX, YTDLP_NONE_DECODE_RETRY_SLEEP_MS,
-            YTDLP_TITLE_WARNING, YTDLP_WARNING_RETRY_MAX, YTDLP_WARNING_RETRY_SLEEP_MS};
+use crate::state::read_settings;
+use crate::{YTDLP_NONE_DECODE_ERROR, YTDLP_NONE_DECODE_RETRY_MAX, YTDLP_NONE_DECODE_RETRY_SLEEP_MS};
 
 pub(crate) fn find_comments_file(dir: &Path, id: &str) -> Option<PathBuf> {
     let mut candidates: Vec<PathBuf> = Vec::new();
@@ -192,7 +192,7 @@ fn parse_comments_lines(content: &str) -> Vec<CommentItem> {
     out
 }
 
-fn parse_live_chat_content(content: &str) -> Vec<CommentItem> {
+pub(crate) fn parse_live_chat_content(content: &str) -> Vec<CommentItem> {
     let mut out = Vec::new();
     if let Ok(value) = serde_json::from_str::<serde_json::Value>(content) {
         if let Some(arr) = value.as_array() {
@@ -457,9 +457,90 @@ fn parse_comment_item(value: &serde_json::Value) -> Option<CommentItem> {
     })
 }
 
+/// コメント付き info.json を書き出すジョブ
+struct CommentsJob<'a> {
+    yt_dlp: String,
+    url: String,
+    output_path: String,
+    ffmpeg_location: Option<String>,
+    cookies_source: Option<String>,
+    cookies_file: Option<String>,
+    cookies_browser: Option<String>,
+    remote_components: Option<String>,
+    network: NetworkSettings,
+    policy: RetryPolicy,
+    emit: &'a mut dyn FnMut(&str),
+}
+
+impl YtDlpJob for CommentsJob<'_> {
+    fn command(&mut self) -> Command {
+        let mut command = Command::new(&self.yt_dlp);
+        command
+            .arg("--no-playlist")
+            .arg("--newline")
+            .arg("--progress")
+            .arg("--skip-download")
+            .arg("--write-info-json")
+            .arg("-o")
+            .arg(&self.output_path);
+        if let Some(location) = &self.ffmpeg_location {
+            command.arg("--ffmpeg-location").arg(location);
+        }
+        apply_cookies_args(
+            &mut command,
+            self.cookies_source.as_deref(),
+            self.cookies_file.as_deref(),
+            self.cookies_browser.as_deref(),
+        );
+        if let Some(remote) = &self.remote_components {
+            if !remote.trim().is_empty() {
+                command.arg("--remote-components").arg(remote);
+            }
+        }
+        apply_network_args(&mut command, &self.network);
+        command.arg(&self.url);
+        command
+    }
+
+    fn on_line(&mut self, _stream: OutputStream, line: &str) -> LineAction {
+        (self.emit)(line);
+        LineAction::Continue
+    }
+
+    /// コメント取得中の NoneType デコードエラーは一時的なことが多いため、少し待って取り直す
+    fn inspect(&mut self, attempt: u32, output: &RunOutput) -> Verdict {
+        let none_decode = !output.success
+            && !output.cancelled
+            && (attempt as usize) < YTDLP_NONE_DECODE_RETRY_MAX
+            && (output.stderr.contains(YTDLP_NONE_DECODE_ERROR)
+                || output.stdout.contains(YTDLP_NONE_DECODE_ERROR))
+            && (output.stderr.contains("decode") || output.stdout.contains("decode"));
+        if none_decode {
+            return Verdict::Retry(
+                Duration::from_millis(YTDLP_NONE_DECODE_RETRY_SLEEP_MS),
+                format!(
+                    "一時的なエラーを検知したためリトライします ({}/{})",
+                    attempt,
+                    YTDLP_NONE_DECODE_RETRY_MAX
+                ),
+            );
+        }
+        Verdict::Default
+    }
+
+    fn plan_failure_retry(&mut self, kind: FailureKind, retries_done: u32) -> Option<(Duration, String)> {
+        plan_retry_with(&self.policy, kind, retries_done, jitter_sample())
+    }
+
+    fn notify(&mut self, message: &str) {
+        (self.emit)(message);
+    }
+}
+
 #[tauri::command]
 pub fn start_comments_download(
     app: AppHandle,
+    state: State<JobProcessState>,
     id: String,
     url: String,
     output_dir: String,
@@ -478,191 +559,67 @@ pub fn start_comments_download(
     if let Err(err) = fs::create_dir_all(&output_dir_path) {
         return Err(format!("保存先フォルダの作成に失敗しました: {}", err));
     }
-    let yt_dlp = resolve_override(yt_dlp_path).unwrap_or_else(resolve_yt_dlp);
-    let ffmpeg_location = resolve_override(ffmpeg_path).or_else(|| Some(resolve_ffmpeg()));
+    let settings = read_settings(&app);
+    let state = state.inner().clone();
+    let key = job_process_key(JobKind::Comments, &id);
+    let active = state.begin(&key);
 
     std::thread::spawn(move || {
-        let mut last_stdout = String::new();
-        let mut last_stderr = String::new();
-        let mut last_success = false;
-
-        for attempt in 1..=YTDLP_WARNING_RETRY_MAX {
-            let warning_seen = Arc::new(AtomicBool::new(false));
-            let mut command = Command::new(&yt_dlp);
-            #[cfg(windows)]
-            command.creation_flags(0x08000000); // CREATE_NO_WINDOW
-            command
-                .arg("--no-playlist")
-                .arg("--newline")
-                .arg("--progress")
-                .arg("--skip-download")
-                .arg("--write-info-json")
-                .arg("-o")
-                .arg(&output_path);
-            if let Some(location) = &ffmpeg_location {
-                command.arg("--ffmpeg-location").arg(location);
-            }
-            apply_cookies_args(
-                &mut command,
-                cookies_source.as_deref(),
-                cookies_file.as_deref(),
-                cookies_browser.as_deref(),
+        let _active = active;
+        let started_at_ms = now_ms();
+        let mut emit = |line: &str| {
+            let _ = app.emit(
+                "comments-progress",
+                serde_json::json!({ "id": id, "line": line }),
             );
-            if let Some(remote) = &remote_components {
-                if !remote.trim().is_empty() {
-                    command.arg("--remote-components").arg(remote);
-                }
-            }
-            command.arg(&url).stdout(Stdio::piped()).stderr(Stdio::piped());
-
-            let mut child = match command.spawn() {
-                Ok(child) => child,
-                Err(err) => {
-                    let _ = write_error_log(
-                        &app,
-                        "comments_download",
-                        &id,
-                        "",
-                        &format!("yt-dlpの起動に失敗しました: {}", err),
-                    );
-                    let _ = app.emit(
-                        "comments-finished",
-                        CommentsFinished {
-                            id,
-                            success: false,
-                            stdout: "".to_string(),
-                            stderr: format!("yt-dlpの起動に失敗しました: {}", err),
-                            metadata: None,
-                            has_live_chat: None,
-                        },
-                    );
-                    return;
-                }
-            };
-
-            let stdout_acc = Arc::new(Mutex::new(String::new()));
-            let stderr_acc = Arc::new(Mutex::new(String::new()));
-
-            if let Some(stdout) = child.stdout.take() {
-                let app_clone = app.clone();
-                let id_clone = id.clone();
-                let stdout_acc_clone = stdout_acc.clone();
-                let warning_seen_clone = warning_seen.clone();
-                std::thread::spawn(move || {
-                    let reader = BufReader::new(stdout);
-                    for line in reader.lines().flatten() {
-                        if line.contains(YTDLP_TITLE_WARNING) {
-                            warning_seen_clone.store(true, Ordering::Relaxed);
-                        }
-                        if let Ok(mut buf) = stdout_acc_clone.lock() {
-                            buf.push_str(&line);
-                            buf.push('\n');
-                        }
-                        let _ = app_clone.emit(
-                            "comments-progress",
-                            serde_json::json!({ "id": id_clone, "line": line }),
-                        );
-                    }
-                });
-            }
-
-            if let Some(stderr) = child.stderr.take() {
-                let app_clone = app.clone();
-                let id_clone = id.clone();
-                let stderr_acc_clone = stderr_acc.clone();
-                let warning_seen_clone = warning_seen.clone();
-                std::thread::spawn(move || {
-                    let reader = BufReader::new(stderr);
-                    for line in reader.lines().flatten() {
-                        if line.contains(YTDLP_TITLE_WARNING) {
-                            warning_seen_clone.store(true, Ordering::Relaxed);
-                        }
-                        if let Ok(mut buf) = stderr_acc_clone.lock() {
-                            buf.push_str(&line);
-                            buf.push('\n');
-                        }
-                        let _ = app_clone.emit(
-                            "comments-progress",
-                            serde_json::json!({ "id": id_clone, "line": line }),
-                        );
-                    }
-                });
-            }
-
-            let output = match child.wait() {
-                Ok(status) => status,
-                Err(err) => {
-                    let _ = write_error_log(
-                        &app,
-                        "comments_download",
-                        &id,
-                        "",
-                        &format!("yt-dlpの実行に失敗しました: {}", err),
-                    );
-                    let _ = app.emit(
-                        "comments-finished",
-                        CommentsFinished {
-                            id,
-                            success: false,
-                            stdout: "".to_string(),
-                            stderr: format!("yt-dlpの実行に失敗しました: {}", err),
-                            metadata: None,
-                            has_live_chat: None,
-                        },
-                    );
-                    return;
-                }
-            };
-
-            let stdout = stdout_acc.lock().map(|s| s.clone()).unwrap_or_default();
-            let stderr = stderr_acc.lock().map(|s| s.clone()).unwrap_or_default();
-
-            last_stdout = stdout;
-            last_stderr = stderr;
-            last_success = output.success();
-
-            let warning_retry = warning_seen.load(Ordering::Relaxed);
-            if warning_retry && attempt < YTDLP_WARNING_RETRY_MAX {
+        };
+        let mut job = CommentsJob {
+            yt_dlp: resolve_override(yt_dlp_path).unwrap_or_else(resolve_yt_dlp),
+            url,
+            output_path,
+            ffmpeg_location: resolve_override(ffmpeg_path).or_else(|| Some(resolve_ffmpeg())),
+            cookies_source,
+            cookies_file,
+            cookies_browser,
+            remote_components,
+            network: effective_network_settings(&settings),
+            policy: effective_retry_policy(&settings),
+            emit: &mut emit,
+        };
+        let options = RunOptions {
+            timeout: None,
+            process: Some(state.handle(&key)),
+        };
+        let run = match run_job(&ProcessRunner, &options, &mut job) {
+            Ok(run) => run,
+            Err(failure) => {
+                let _ = write_error_log(&app, "comments_download", &id, "", &failure.message);
                 let _ = app.emit(
-                    "comments-progress",
-                    serde_json::json!({
-                        "id": id.clone(),
-                        "line": format!(
-                            "警告を検知したためリトライします ({}/{})",
-                            attempt,
-                            YTDLP_WARNING_RETRY_MAX
-                        )
-                    }),
+                    "comments-finished",
+                    CommentsFinished {
+                        id,
+                        success: false,
+                        stdout: "".to_string(),
+                        stderr: failure.message,
+                        cancelled: false,
+                        metadata: None,
+                        has_live_chat: None,
+                        failure_kind: Some(FailureKind::Unknown),
+                    },
                 );
-                std::thread::sleep(Duration::from_millis(YTDLP_WARNING_RETRY_SLEEP_MS));
-                continue;
-            }
-
-            let none_decode_retry = !last_success
-                && attempt < YTDLP_NONE_DECODE_RETRY_MAX
-                && (last_stderr.contains(YTDLP_NONE_DECODE_ERROR)
-                    || last_stdout.contains(YTDLP_NONE_DECODE_ERROR))
-                && (last_stderr.contains("decode") || last_stdout.contains("decode"));
-            if none_decode_retry {
-                let _ = app.emit(
-                    "metadata-progress",
-                    serde_json::json!({
-                        "id": id.clone(),
-                        "line": format!(
-                            "一時的なエラーを検知したためリトライします ({}/{})",
-                            attempt,
-                            YTDLP_NONE_DECODE_RETRY_MAX
-                        )
-                    }),
-                );
-                std::thread::sleep(Duration::from_millis(YTDLP_NONE_DECODE_RETRY_SLEEP_MS));
-                continue;
+                return;
             }
-
-            break;
-        }
-
-        if !last_success {
+        };
+        let attempts = run.attempts;
+        let RunOutput {
+            success: last_success,
+            stdout: last_stdout,
+            stderr: last_stderr,
+            cancelled,
+            ..
+        } = run.output;
+
+        if !last_success && !cancelled {
             let _ = write_error_log(&app, "comments_download", &id, &last_stdout, &last_stderr);
         }
 
@@ -681,6 +638,23 @@ pub fn start_comments_download(
             has_live_chat = comments_file_exists(id.clone(), output_dir.clone()).ok();
         }
 
+        let failure_kind = (!last_success && !cancelled).then(|| classify_failure(&last_stdout, &last_stderr));
+        record_job(
+            &app,
+            JournalEntry {
+                kind: JobKind::Comments,
+                id: id.clone(),
+                started_at_ms,
+                finished_at_ms: now_ms(),
+                success: last_success,
+                cancelled,
+                bytes: None,
+                format_id: None,
+                container: None,
+                attempts,
+                failure_kind,
+            },
+        );
         let _ = app.emit(
             "comments-finished",
             CommentsFinished {
@@ -688,8 +662,10 @@ pub fn start_comments_download(
                 success: last_success,
                 stdout: last_stdout,
                 stderr: last_stderr,
+                cancelled,
                 metadata,
                 has_live_chat,
+                failure_kind,
             },
         );
     });
@@ -697,6 +673,12 @@ pub fn start_comments_download(
     Ok(())
 }
 
+/// 実行中のコメント取得を止める。終了時に cancelled 付きで comments-finished が送られる。
+#[tauri::command]
+pub fn stop_comments_download(state: State<JobProcessState>, id: String) -> Result<(), String> {
+    state.stop(&job_process_key(JobKind::Comments, &id))
+}
+
 #[tauri::command]
 pub fn get_comments(
     id: String,
@@ -1144,4 +1126,78 @@ mod tests {
         let result = find_comments_file(Path::new("/nonexistent/dir"), "any");
         assert!(result.is_none());
     }
+
+    // =========================================================
+    // CommentsJob (FakeRunner)
+    // =========================================================
+
+    use crate::runner::fake::{FakeRun, FakeRunner};
+    use crate::runner::run_job;
+    use crate::YTDLP_NONE_DECODE_RETRY_SLEEP_MS;
+
+    const COMMENTS_NONE_DECODE: &str = "\
+[youtube] Downloading comment API JSON page 3 (200/~1500)
+ERROR: 'NoneType' object has no attribute 'decode'";
+    const COMMENTS_OK: &str = "\
+[youtube] Downloading comment API JSON page 1 (0/~1500)
+[youtube] Extracted 1500 comments
+[info] Writing video metadata as JSON to: /lib/metadata/ch/title [abc].info.json";
+
+    fn comments_job(emit: &mut dyn FnMut(&str)) -> CommentsJob<'_> {
+        CommentsJob {
+            yt_dlp: "yt-dlp".to_string(),
+            url: "https://www.youtube.com/watch?v=abc".to_string(),
+            output_path: "/lib/metadata/out.%(ext)s".to_string(),
+            ffmpeg_location: None,
+            cookies_source: None,
+            cookies_file: None,
+            cookies_browser: None,
+            remote_components: Some(" ".to_string()),
+            network: NetworkSettings::default(),
+            policy: RetryPolicy {
+                jitter_ratio: 0.0,
+                ..Default::default()
+            },
+            emit,
+        }
+    }
+
+    #[test]
+    fn comments_job_retries_none_decode_error() {
+        let runner = FakeRunner::new(vec![
+            FakeRun { stdout: COMMENTS_NONE_DECODE.into(), ..Default::default() },
+            FakeRun::ok(COMMENTS_OK),
+        ]);
+        let mut lines = Vec::new();
+        let mut emit = |line: &str| lines.push(line.to_string());
+        let mut job = comments_job(&mut emit);
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert!(run.output.success);
+        assert_eq!(run.attempts, 2);
+        assert!(lines.contains(&format!(
+            "一時的なエラーを検知したためリトライします (1/{})",
+            YTDLP_NONE_DECODE_RETRY_MAX
+        )));
+        assert_eq!(
+            *runner.sleeps.lock().unwrap(),
+            vec![Duration::from_millis(YTDLP_NONE_DECODE_RETRY_SLEEP_MS)]
+        );
+        let args = runner.command_args(1);
+        assert!(args.contains(&"--write-info-json".to_string()));
+        assert!(!args.contains(&"--remote-components".to_string()));
+    }
+
+    #[test]
+    fn comments_job_gives_up_after_none_decode_limit() {
+        let runner = FakeRunner::new(vec![
+            FakeRun { stdout: COMMENTS_NONE_DECODE.into(), ..Default::default() },
+            FakeRun { stdout: COMMENTS_NONE_DECODE.into(), ..Default::default() },
+        ]);
+        let mut emit = |_: &str| {};
+        let mut job = comments_job(&mut emit);
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert!(!run.output.success);
+        assert_eq!(run.attempts as usize, YTDLP_NONE_DECODE_RETRY_MAX);
+    }
 }
+
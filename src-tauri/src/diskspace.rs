@@ -0,0 +1,509 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+#[cfg(not(windows))]
+use std::process::Command;
+use serde_json::Value;
+use tauri::{AppHandle, Emitter};
+use crate::audio::audio_format_selector;
+use crate::download::{audio_only_format, resolve_format};
+use crate::files::find_info_json;
+use crate::models::{
+    DiskSpaceCheckMode, DiskSpaceSettings, DiskSpaceShortage, DiskSpaceWarning, DownloadRequest,
+    DownloadStartError, FailureKind, PersistedSettings,
+};
+use crate::paths::{library_metadata_dir, library_videos_dir, write_error_log};
+use crate::state::{read_settings, write_settings};
+use crate::DEFAULT_DISK_SPACE_MARGIN_MB;
+
+const BYTES_PER_MB: u64 = 1024 * 1024;
+
+impl From<String> for DownloadStartError {
+    fn from(message: String) -> Self {
+        DownloadStartError::Other { message }
+    }
+}
+
+impl fmt::Display for DownloadStartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadStartError::InsufficientDiskSpace(shortage) => write!(
+                f,
+                "保存先の空き容量が不足しています (必要: {} MB + 余裕 {} MB, 空き: {} MB)",
+                shortage.required_bytes.div_ceil(BYTES_PER_MB),
+                shortage.margin_bytes / BYTES_PER_MB,
+                shortage.available_bytes / BYTES_PER_MB
+            ),
+            DownloadStartError::Other { message } => f.write_str(message),
+        }
+    }
+}
+
+impl DownloadStartError {
+    pub(crate) fn failure_kind(&self) -> FailureKind {
+        match self {
+            DownloadStartError::InsufficientDiskSpace(_) => FailureKind::InsufficientDiskSpace,
+            DownloadStartError::Other { .. } => FailureKind::Unknown,
+        }
+    }
+}
+
+pub(crate) fn effective_disk_space_settings(settings: &PersistedSettings) -> DiskSpaceSettings {
+    DiskSpaceSettings {
+        mode: settings.disk_space_check.unwrap_or_default(),
+        margin_mb: settings.disk_space_margin_mb.unwrap_or(DEFAULT_DISK_SPACE_MARGIN_MB),
+    }
+}
+
+fn format_size(format: &Value) -> Option<u64> {
+    ["filesize", "filesize_approx"].iter().find_map(|key| {
+        format
+            .get(key)
+            .and_then(Value::as_f64)
+            .filter(|size| *size > 0.0)
+            .map(|size| size as u64)
+    })
+}
+
+fn has_stream(format: &Value, key: &str) -> bool {
+    format.get(key).and_then(Value::as_str).is_some_and(|codec| codec != "none")
+}
+
+/// -f の指定を区切り文字で分ける。[] と引用符の中では区切らない。
+fn split_selector(selector: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut start = 0;
+    for (index, ch) in selector.char_indices() {
+        match (quote, ch) {
+            (Some(open), _) if ch == open => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(ch),
+            (None, '[') => depth += 1,
+            (None, ']') => depth = depth.saturating_sub(1),
+            (None, _) if ch == separator && depth == 0 => {
+                parts.push(selector[start..index].trim());
+                start = index + ch.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(selector[start..].trim());
+    parts
+}
+
+/// yt-dlp の正規表現フィルタ (~=) のうち、^ $ . ? * だけを解釈する簡易版
+fn regex_matches(pattern: &str, text: &str) -> bool {
+    fn char_matches(pattern: char, text: &[char]) -> bool {
+        text.first().is_some_and(|ch| pattern == '.' || pattern == *ch)
+    }
+    fn match_here(pattern: &[char], text: &[char]) -> bool {
+        match pattern {
+            [] => true,
+            ['$'] => text.is_empty(),
+            [ch, '?', rest @ ..] => {
+                (char_matches(*ch, text) && match_here(rest, &text[1..])) || match_here(rest, text)
+            }
+            [ch, '*', rest @ ..] => {
+                let mut index = 0;
+                loop {
+                    if match_here(rest, &text[index..]) {
+                        return true;
+                    }
+                    if !char_matches(*ch, &text[index..]) {
+                        return false;
+                    }
+                    index += 1;
+                }
+            }
+            [ch, rest @ ..] => char_matches(*ch, text) && match_here(rest, &text[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match pattern.split_first() {
+        Some(('^', rest)) => match_here(rest, &text),
+        _ => (0..=text.len()).any(|start| match_here(&pattern, &text[start..])),
+    }
+}
+
+const FILTER_OPERATORS: [&str; 10] = ["^=", "$=", "*=", "~=", "!=", "<=", ">=", "=", "<", ">"];
+
+/// "[height<=?1080]" の中身 1 つを形式に当てはめる
+fn matches_format_filter(format: &Value, filter: &str) -> bool {
+    let Some(split) = filter.find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_')) else {
+        return false;
+    };
+    let (key, rest) = filter.split_at(split);
+    let (negate, rest) = match rest.strip_prefix('!').filter(|rest| !rest.starts_with('=')) {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let Some(operator) = FILTER_OPERATORS.iter().copied().find(|op| rest.starts_with(op)) else {
+        return false;
+    };
+    let rest = &rest[operator.len()..];
+    let (optional, value) = match rest.strip_prefix('?') {
+        Some(value) => (true, value),
+        None => (false, rest),
+    };
+    let value = value.trim().trim_matches(|ch| ch == '\'' || ch == '"');
+    let Some(actual) = format.get(key).filter(|actual| !actual.is_null()) else {
+        return optional;
+    };
+    let matched = match (actual.as_f64(), value.parse::<f64>().ok()) {
+        (Some(actual), Some(expected)) => match operator {
+            "=" => actual == expected,
+            "!=" => actual != expected,
+            "<=" => actual <= expected,
+            ">=" => actual >= expected,
+            "<" => actual < expected,
+            ">" => actual > expected,
+            _ => false,
+        },
+        _ => {
+            let actual = actual.as_str().map(str::to_string).unwrap_or_else(|| actual.to_string());
+            match operator {
+                "=" => actual == value,
+                "!=" => actual != value,
+                "^=" => actual.starts_with(value),
+                "$=" => actual.ends_with(value),
+                "*=" => actual.contains(value),
+                "~=" => regex_matches(value, &actual),
+                _ => false,
+            }
+        }
+    };
+    matched != negate
+}
+
+/// "bestvideo[height<=1080]" のような 1 つの指定に合う形式を formats から選ぶ。
+/// formats は yt-dlp の並び (低品質 → 高品質) のまま渡す。
+fn pick_format<'a>(formats: &'a [Value], part: &str) -> Option<&'a Value> {
+    let (name, filters) = part.split_at(part.find('[').unwrap_or(part.len()));
+    let filters: Vec<&str> = filters
+        .split(']')
+        .filter_map(|filter| filter.trim().strip_prefix('['))
+        .collect();
+    let (best, accepts): (bool, fn(&Value) -> bool) = match name {
+        "b" | "best" => (true, |f| has_stream(f, "vcodec") && has_stream(f, "acodec")),
+        "w" | "worst" => (false, |f| has_stream(f, "vcodec") && has_stream(f, "acodec")),
+        "bv" | "bestvideo" => (true, |f| has_stream(f, "vcodec") && !has_stream(f, "acodec")),
+        "wv" | "worstvideo" => (false, |f| has_stream(f, "vcodec") && !has_stream(f, "acodec")),
+        "ba" | "bestaudio" => (true, |f| has_stream(f, "acodec") && !has_stream(f, "vcodec")),
+        "wa" | "worstaudio" => (false, |f| has_stream(f, "acodec") && !has_stream(f, "vcodec")),
+        "bv*" | "bestvideo*" => (true, |f| has_stream(f, "vcodec")),
+        "ba*" | "bestaudio*" => (true, |f| has_stream(f, "acodec")),
+        "b*" | "best*" => (true, |f| has_stream(f, "vcodec") || has_stream(f, "acodec")),
+        format_id => {
+            return formats.iter().rev().find(|format| {
+                format.get("format_id").and_then(Value::as_str) == Some(format_id)
+                    && filters.iter().all(|filter| matches_format_filter(format, filter))
+            });
+        }
+    };
+    let mut candidates = formats
+        .iter()
+        .filter(|format| accepts(format) && filters.iter().all(|filter| matches_format_filter(format, filter)));
+    if best {
+        candidates.next_back()
+    } else {
+        candidates.next()
+    }
+}
+
+/// ダウンロード時と同じ -f 指定で info.json の formats から形式を選び、ダウンロードサイズを推定する。
+/// 解釈できない指定・合う形式が無い・サイズが分からない形式が含まれる場合は None。
+pub(crate) fn estimate_required_bytes(info: &Value, selector: &str) -> Option<u64> {
+    let formats = info.get("formats").and_then(Value::as_array)?;
+    if selector.contains(['(', ')', ',']) {
+        return None;
+    }
+    // "/" で区切った候補を先頭から試し、"+" で結合する形式がすべて揃った最初の候補を使う
+    let selected = split_selector(selector, '/').into_iter().find_map(|alternative| {
+        split_selector(alternative, '+')
+            .into_iter()
+            .map(|part| pick_format(formats, part))
+            .collect::<Option<Vec<&Value>>>()
+    })?;
+    selected.into_iter().map(format_size).sum()
+}
+
+/// `df -Pk` の出力から空き容量 (バイト) を取り出す。
+/// ファイルシステム名やマウント先に空白が含まれても崩れないよう、使用率 (NN%) の直前の列を読む。
+pub(crate) fn parse_df_available(output: &str) -> Option<u64> {
+    let line = output.lines().skip(1).filter(|line| !line.trim().is_empty()).last()?;
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let capacity = fields.iter().position(|field| field.ends_with('%'))?;
+    let available_kb = fields.get(capacity.checked_sub(1)?)?.parse::<u64>().ok()?;
+    Some(available_kb.saturating_mul(1024))
+}
+
+#[cfg(not(windows))]
+fn query_available_space(path: &Path) -> Result<u64, String> {
+    let output = Command::new("df")
+        .arg("-Pk")
+        .arg(path)
+        .output()
+        .map_err(|e| format!("空き容量の取得に失敗しました: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "空き容量の取得に失敗しました: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    parse_df_available(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| "空き容量の取得に失敗しました: df の出力を解析できませんでした".to_string())
+}
+
+#[cfg(windows)]
+fn query_available_space(path: &Path) -> Result<u64, String> {
+    use std::os::windows::ffi::OsStrExt;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory_name: *const u16,
+            free_bytes_available_to_caller: *mut u64,
+            total_number_of_bytes: *mut u64,
+            total_number_of_free_bytes: *mut u64,
+        ) -> i32;
+    }
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available: u64 = 0;
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            wide.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(format!(
+            "空き容量の取得に失敗しました: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(available)
+}
+
+/// 保存先がまだ無い場合は存在する親フォルダのボリュームで調べる
+fn available_space(path: &Path) -> Result<u64, String> {
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .ok_or_else(|| format!("空き容量の取得に失敗しました: {} が見つかりません", path.display()))?;
+    query_available_space(existing)
+}
+
+pub(crate) fn check_disk_space(
+    required_bytes: u64,
+    available_bytes: u64,
+    margin_bytes: u64,
+) -> Result<(), DiskSpaceShortage> {
+    if required_bytes.saturating_add(margin_bytes) <= available_bytes {
+        return Ok(());
+    }
+    Err(DiskSpaceShortage {
+        required_bytes,
+        available_bytes,
+        margin_bytes,
+    })
+}
+
+/// yt-dlp を起動する前に保存先の空き容量を確認する。
+/// メタデータ未取得・サイズ不明・ライブ録画・空き容量を取得できない場合は確認せずに開始する。
+pub(crate) fn preflight_disk_space(
+    app: &AppHandle,
+    request: &DownloadRequest,
+) -> Result<(), DownloadStartError> {
+    let persisted = read_settings(app);
+    let settings = effective_disk_space_settings(&persisted);
+    if settings.mode == DiskSpaceCheckMode::Off || request.is_live.unwrap_or(false) {
+        return Ok(());
+    }
+    let Some(info) = find_info_json(&library_metadata_dir(&request.output_dir), &request.id)
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+    else {
+        return Ok(());
+    };
+    let selector = match audio_only_format(request) {
+        Some(audio_format) => audio_format_selector(audio_format).to_string(),
+        None => resolve_format(&persisted, request.format_preset.as_deref(), request.quality.as_deref()).0,
+    };
+    let Some(required_bytes) = estimate_required_bytes(&info, &selector) else {
+        return Ok(());
+    };
+    let available_bytes = match available_space(&library_videos_dir(&request.output_dir)) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            let _ = write_error_log(app, "disk_space", &request.id, "", &err);
+            return Ok(());
+        }
+    };
+    let margin_bytes = settings.margin_mb.saturating_mul(BYTES_PER_MB);
+    match check_disk_space(required_bytes, available_bytes, margin_bytes) {
+        Ok(()) => Ok(()),
+        Err(shortage) if settings.mode == DiskSpaceCheckMode::Warn => {
+            let _ = app.emit(
+                "download-disk-space-warning",
+                DiskSpaceWarning {
+                    id: request.id.clone(),
+                    shortage,
+                },
+            );
+            Ok(())
+        }
+        Err(shortage) => Err(DownloadStartError::InsufficientDiskSpace(shortage)),
+    }
+}
+
+#[tauri::command]
+pub fn get_disk_space_settings(app: AppHandle) -> Result<DiskSpaceSettings, String> {
+    Ok(effective_disk_space_settings(&read_settings(&app)))
+}
+
+#[tauri::command]
+pub fn save_disk_space_settings(app: AppHandle, settings: DiskSpaceSettings) -> Result<(), String> {
+    let mut persisted = read_settings(&app);
+    persisted.disk_space_check = Some(settings.mode);
+    persisted.disk_space_margin_mb = Some(settings.margin_mb);
+    write_settings(&app, persisted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // =========================================================
+    // estimate_required_bytes
+    // =========================================================
+
+    fn info() -> Value {
+        json!({
+            "id": "abc",
+            "formats": [
+                { "format_id": "140", "ext": "m4a", "vcodec": "none", "acodec": "mp4a.40.2", "filesize": 200_000 },
+                { "format_id": "251", "ext": "webm", "vcodec": "none", "acodec": "opus", "filesize": 180_000 },
+                { "format_id": "18", "ext": "mp4", "vcodec": "avc1.42001E", "acodec": "mp4a.40.2", "height": 360, "filesize_approx": 500_000 },
+                { "format_id": "136", "ext": "mp4", "vcodec": "avc1.4d401f", "acodec": "none", "height": 720, "filesize": 600_000 },
+                { "format_id": "247", "ext": "webm", "vcodec": "vp9", "acodec": "none", "height": 720, "filesize": 550_000 },
+                { "format_id": "137", "ext": "mp4", "vcodec": "avc1.640028", "acodec": "none", "height": 1080, "filesize": 1_000_000 },
+                { "format_id": "248", "ext": "webm", "vcodec": "vp09.00.40.08", "acodec": "none", "height": 1080, "filesize": null }
+            ],
+            "requested_formats": [
+                { "format_id": "248", "vcodec": "vp9", "filesize": 999 }
+            ]
+        })
+    }
+
+    #[test]
+    fn estimate_follows_download_selector() {
+        let selector = "bestvideo[ext=mp4][vcodec^=avc1]+bestaudio[ext=m4a]/best[ext=mp4][vcodec^=avc1]";
+        assert_eq!(estimate_required_bytes(&info(), selector), Some(1_200_000));
+        let selector = "bestvideo[height<=720][ext=mp4][vcodec^=avc1]+bestaudio[ext=m4a]/best[height<=720]";
+        assert_eq!(estimate_required_bytes(&info(), selector), Some(800_000));
+        assert_eq!(estimate_required_bytes(&info(), "136+251"), Some(780_000));
+    }
+
+    #[test]
+    fn estimate_uses_later_alternative() {
+        let selector = "bestvideo[height<=240]+bestaudio/best[height<=?480]";
+        assert_eq!(estimate_required_bytes(&info(), selector), Some(500_000));
+        assert_eq!(estimate_required_bytes(&info(), "bestvideo[height<=240]+bestaudio"), None);
+    }
+
+    #[test]
+    fn estimate_audio_selector() {
+        assert_eq!(estimate_required_bytes(&info(), "bestaudio[ext=m4a]/bestaudio/best"), Some(200_000));
+        assert_eq!(estimate_required_bytes(&info(), "bestaudio[acodec=opus]/bestaudio/best"), Some(180_000));
+    }
+
+    #[test]
+    fn estimate_regex_filter() {
+        let selector = "bestvideo[height<=720][vcodec~='^vp0?9']+bestaudio[ext=webm]/bestaudio";
+        assert_eq!(estimate_required_bytes(&info(), selector), Some(730_000));
+        assert!(regex_matches("^vp0?9", "vp09.00.40.08"));
+        assert!(regex_matches("^vp0?9", "vp9"));
+        assert!(!regex_matches("^vp0?9", "avc1"));
+        assert!(regex_matches("avc.*28$", "avc1.640028"));
+    }
+
+    #[test]
+    fn estimate_unknown_size() {
+        // 選ばれた形式のサイズが分からない場合は推定しない
+        assert_eq!(estimate_required_bytes(&info(), "bestvideo[height<=1080]+bestaudio"), None);
+        assert_eq!(estimate_required_bytes(&json!({ "id": "abc" }), "best"), None);
+        assert_eq!(estimate_required_bytes(&info(), "(bv+ba/b)"), None);
+    }
+
+    // =========================================================
+    // parse_df_available
+    // =========================================================
+
+    #[test]
+    fn df_output_parsed() {
+        let output = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n\
+                      /dev/sda1        102400000  51200000  46080000      53% /\n";
+        assert_eq!(parse_df_available(output), Some(46_080_000 * 1024));
+    }
+
+    #[test]
+    fn df_output_with_spaces_in_names() {
+        let output = "Filesystem 1024-blocks Used Available Capacity Mounted on\n\
+                      //nas/My Share 2000 500 1500 25% /Volumes/My Share\n";
+        assert_eq!(parse_df_available(output), Some(1_500 * 1024));
+    }
+
+    #[test]
+    fn df_output_invalid() {
+        assert_eq!(parse_df_available(""), None);
+        assert_eq!(parse_df_available("Filesystem 1024-blocks Used Available Capacity Mounted on\n"), None);
+        assert_eq!(parse_df_available("header\n/dev/sda1 abc 53% /\n"), None);
+    }
+
+    // =========================================================
+    // check_disk_space / settings
+    // =========================================================
+
+    #[test]
+    fn check_includes_margin() {
+        assert!(check_disk_space(100, 150, 50).is_ok());
+        let shortage = check_disk_space(100, 149, 50).unwrap_err();
+        assert_eq!(shortage.required_bytes, 100);
+        assert_eq!(shortage.available_bytes, 149);
+        assert_eq!(shortage.margin_bytes, 50);
+        assert!(check_disk_space(u64::MAX, u64::MAX - 1, 1).is_err());
+    }
+
+    #[test]
+    fn settings_defaults() {
+        let settings = effective_disk_space_settings(&PersistedSettings::default());
+        assert_eq!(settings.mode, DiskSpaceCheckMode::Reject);
+        assert_eq!(settings.margin_mb, DEFAULT_DISK_SPACE_MARGIN_MB);
+    }
+
+    #[test]
+    fn start_error_serialization() {
+        let error = DownloadStartError::InsufficientDiskSpace(DiskSpaceShortage {
+            required_bytes: 3 * BYTES_PER_MB,
+            available_bytes: 2 * BYTES_PER_MB,
+            margin_bytes: BYTES_PER_MB,
+        });
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["kind"], "insufficientDiskSpace");
+        assert_eq!(json["requiredBytes"], 3 * BYTES_PER_MB);
+        assert_eq!(json["marginBytes"], BYTES_PER_MB);
+        assert_eq!(error.failure_kind(), FailureKind::InsufficientDiskSpace);
+        assert!(error.to_string().contains("必要: 3 MB"));
+
+        let other = DownloadStartError::from("失敗".to_string());
+        assert_eq!(serde_json::to_value(&other).unwrap()["message"], "失敗");
+        assert_eq!(other.to_string(), "失敗");
+    }
+}
@@ -1,16 +1,26 @@
-use std::io::{BufRead, BufReader};
 use std::fs;
-use std::process::{Command, Stdio};
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
-#[cfg(windows)]
-use std::os::windows::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
 use std::time::Duration;
 use tauri::{AppHandle, Emitter, State};
-use crate::models::{DownloadProcessState, DownloadFinished};
-use crate::paths::{library_videos_dir, write_error_log};
+use crate::models::{DownloadProcessState, DownloadFinished, FailureKind, DownloadRequest, DownloadPhase, DownloadProgress, JobKind, JournalEntry, FormatPreset, PersistedSettings, DownloadStartError, RetryPolicy};
+use crate::paths::{library_metadata_dir, library_videos_dir, write_error_log};
+use crate::files::{check_media_integrity, find_downloaded_video_file, find_info_json, probe_media};
+use crate::metadata::parse_video_metadata_value;
 use crate::tooling::{apply_cookies_args, resolve_yt_dlp, resolve_override, resolve_ffmpeg};
-use crate::{YTDLP_TITLE_WARNING, YTDLP_WARNING_RETRY_MAX, YTDLP_WARNING_RETRY_SLEEP_MS};
+use crate::audio::{build_audio_args, embed_cover_art, resolve_audio_format};
+use crate::tagging::build_embed_args;
+use crate::network::{apply_network_args, effective_network_settings, network_settings};
+use crate::failure::classify_failure;
+use crate::diskspace::preflight_disk_space;
+use crate::journal::{now_ms, parse_selected_format, record_job};
+use crate::retry::{effective_retry_policy, jitter_sample, plan_retry_with};
+use crate::runner::{run_job, LineAction, OutputStream, ProcessHandle, ProcessRunner, RunOptions, RunOutput, YtDlpJob, YtDlpRunner};
+use crate::queue::{on_download_finished, on_download_verified, release_paused_download, start_or_queue_download};
+use crate::scheduler::{on_recording_finished, on_recording_started};
+use crate::livechat::start_live_chat_capture;
+use crate::state::read_settings;
+use crate::{LIVE_WAIT_FOR_VIDEO_SECS, YTDLP_POSTPROCESSOR_TAGS};
 
 fn quality_to_format(quality: Option<&str>) -> String {
     match quality {
@@ -23,10 +33,243 @@ fn quality_to_format(quality: Option<&str>) -> String {
     }
 }
 
+fn codec_filter(codec: &str) -> Option<&'static str> {
+    match codec.trim().to_lowercase().as_str() {
+        "h264" | "avc" | "avc1" => Some("[vcodec^=avc1]"),
+        "vp9" => Some("[vcodec~='^vp0?9']"),
+        "av1" | "av01" => Some("[vcodec^=av01]"),
+        _ => None,
+    }
+}
+
+/// プリセットから yt-dlp の -f 引数を組み立てる。
+/// 条件に合う形式が無い場合に備え、コーデック・HDR条件を外した候補を後ろに続ける。
+pub(crate) fn preset_to_format(preset: &FormatPreset) -> String {
+    if let Some(raw) = preset.format.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+        return raw.to_string();
+    }
+
+    let mut limits = String::new();
+    if let Some(height) = preset.max_height {
+        limits.push_str(&format!("[height<={}]", height));
+    }
+    if let Some(fps) = preset.max_fps {
+        limits.push_str(&format!("[fps<={}]", fps));
+    }
+
+    let mut preferred = limits.clone();
+    if let Some(filter) = preset.codec.as_deref().and_then(codec_filter) {
+        preferred.push_str(filter);
+    }
+    match preset.hdr {
+        Some(true) => preferred.push_str("[dynamic_range!=SDR]"),
+        Some(false) => preferred.push_str("[dynamic_range=SDR]"),
+        None => {}
+    }
+
+    let audio = match preset_container(preset) {
+        "mp4" => "bestaudio[ext=m4a]/bestaudio",
+        "webm" => "bestaudio[ext=webm]/bestaudio",
+        _ => "bestaudio",
+    };
+    let mut candidates = vec![format!("bestvideo{}+{}", preferred, audio)];
+    if preferred != limits {
+        candidates.push(format!("bestvideo{}+bestaudio", limits));
+    }
+    candidates.push(format!("best{}", limits));
+    candidates.join("/")
+}
+
+pub(crate) fn preset_container(preset: &FormatPreset) -> &str {
+    match preset.container.as_deref().map(str::trim) {
+        Some(container) if !container.is_empty() => container,
+        _ => "mp4",
+    }
+}
+
+/// 動画ごとの指定 → 既定プリセット の順にプリセットを探す
+pub(crate) fn resolve_preset<'a>(
+    settings: &'a PersistedSettings,
+    preset_name: Option<&str>,
+) -> Option<&'a FormatPreset> {
+    preset_name
+        .or(settings.default_format_preset.as_deref())
+        .and_then(|name| settings.format_presets.iter().find(|preset| preset.name == name))
+}
+
+/// 動画ごとの指定 → 既定プリセット → 従来の画質指定 の順に -f 引数とコンテナを決める
+pub(crate) fn resolve_format(
+    settings: &PersistedSettings,
+    preset_name: Option<&str>,
+    quality: Option<&str>,
+) -> (String, String) {
+    match resolve_preset(settings, preset_name) {
+        Some(preset) => (preset_to_format(preset), preset_container(preset).to_string()),
+        None => (quality_to_format(quality), "mp4".to_string()),
+    }
+}
+
+/// "123.45MiB" や "~ 1.2GiB" などのサイズ表記をバイト数に変換する
+fn parse_size_bytes(value: &str) -> Option<f64> {
+    let value = value.trim().trim_start_matches('~').trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let multiplier = match unit.trim() {
+        "B" | "" => 1.0,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        "KB" | "kB" => 1000.0,
+        "MB" => 1000.0 * 1000.0,
+        "GB" => 1000.0 * 1000.0 * 1000.0,
+        "TB" => 1000.0 * 1000.0 * 1000.0 * 1000.0,
+        _ => return None,
+    };
+    Some(number * multiplier)
+}
+
+/// "00:32" や "01:02:03" を秒数に変換する
+fn parse_clock_seconds(value: &str) -> Option<u64> {
+    let mut total: u64 = 0;
+    for part in value.split(':') {
+        let part: u64 = part.parse().ok()?;
+        total = total * 60 + part;
+    }
+    Some(total)
+}
+
+fn parse_download_body(body: &str, progress: &mut DownloadProgress) {
+    let tokens: Vec<&str> = body.split_whitespace().collect();
+    let mut index = 0;
+    while index < tokens.len() {
+        let token = tokens[index];
+        let next = tokens.get(index + 1).copied();
+        match token {
+            "of" => {
+                if next == Some("~") {
+                    progress.total_bytes_estimated = true;
+                    index += 1;
+                } else if next.is_some_and(|value| value.starts_with('~')) {
+                    progress.total_bytes_estimated = true;
+                }
+                if let Some(size) = tokens.get(index + 1).and_then(|value| parse_size_bytes(value)) {
+                    progress.total_bytes = Some(size as u64);
+                }
+                index += 1;
+            }
+            "at" => {
+                if let Some(speed) = next
+                    .and_then(|value| value.strip_suffix("/s"))
+                    .and_then(parse_size_bytes)
+                {
+                    progress.speed_bytes_per_sec = Some(speed);
+                }
+                index += 1;
+            }
+            "ETA" => {
+                progress.eta_seconds = next.and_then(parse_clock_seconds);
+                index += 1;
+            }
+            "in" => {
+                index += 1;
+            }
+            "(frag" => {
+                if let Some((current, count)) = next
+                    .map(|value| value.trim_end_matches(')'))
+                    .and_then(|value| value.split_once('/'))
+                {
+                    progress.fragment_index = current.parse().ok();
+                    progress.fragment_count = count.parse().ok();
+                }
+                index += 1;
+            }
+            _ => {
+                if let Some(percent) = token.strip_suffix('%').and_then(|value| value.parse::<f64>().ok()) {
+                    progress.percent = Some(percent);
+                } else if index == 0 {
+                    // ライブ配信など総サイズ不明のときは取得済みサイズが先頭に出る
+                    if let Some(size) = parse_size_bytes(token) {
+                        progress.downloaded_bytes = Some(size as u64);
+                    }
+                }
+            }
+        }
+        index += 1;
+    }
+
+    if progress.downloaded_bytes.is_none() {
+        if let (Some(percent), Some(total)) = (progress.percent, progress.total_bytes) {
+            progress.downloaded_bytes = Some((total as f64 * percent / 100.0).round() as u64);
+        }
+    }
+}
+
+/// yt-dlpの出力1行を進捗情報に変換する。元の行は `line` に保持する。
+pub(crate) fn parse_download_progress(id: &str, line: &str) -> DownloadProgress {
+    let mut progress = DownloadProgress {
+        id: id.to_string(),
+        line: Some(line.to_string()),
+        ..Default::default()
+    };
+    let Some((tag, body)) = line
+        .trim()
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+    else {
+        return progress;
+    };
+    match tag {
+        "download" => {
+            progress.phase = Some(DownloadPhase::Downloading);
+            parse_download_body(body, &mut progress);
+        }
+        "Merger" => {
+            progress.phase = Some(DownloadPhase::Merging);
+            progress.postprocessor = Some(tag.to_string());
+        }
+        _ if tag.starts_with("Fixup") || YTDLP_POSTPROCESSOR_TAGS.contains(&tag) => {
+            progress.phase = Some(DownloadPhase::PostProcessing);
+            progress.postprocessor = Some(tag.to_string());
+        }
+        _ => {}
+    }
+    progress
+}
+
+/// yt-dlp の出力から、今回のダウンロードで最終的に書き出されたファイルのパスを取り出す。
+/// 既存ファイルを再利用しただけの場合は None。
+pub(crate) fn parse_produced_media_path(stdout: &str) -> Option<PathBuf> {
+    let mut produced = None;
+    for line in stdout.lines() {
+        let Some((tag, body)) = line
+            .trim()
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once(']'))
+        else {
+            continue;
+        };
+        let body = body.trim();
+        if tag == "download" && body.ends_with("has already been downloaded") {
+            produced = None;
+        } else if let Some((_, path)) = body.rsplit_once("Destination: ") {
+            produced = Some(path.trim());
+        } else if let Some((_, path)) = body.split_once("Merging formats into ").filter(|_| tag == "Merger") {
+            produced = Some(path.trim().trim_matches('"'));
+        } else if let Some((_, path)) = body.rsplit_once("\" to \"").filter(|_| tag == "MoveFiles") {
+            produced = Some(path.trim().trim_matches('"'));
+        }
+    }
+    produced.filter(|path| !path.is_empty()).map(PathBuf::from)
+}
+
+/// 同時実行数の上限に空きがあればすぐに開始し、なければダウンロードキューで待たせる
 #[tauri::command]
 pub fn start_download(
     app: AppHandle,
-    state: State<DownloadProcessState>,
     id: String,
     url: String,
     output_dir: String,
@@ -38,359 +281,470 @@ pub fn start_download(
     ffmpeg_path: Option<String>,
     quality: Option<String>,
     is_live: Option<bool>,
-) -> Result<(), String> {
-    let output_dir_path = library_videos_dir(&output_dir);
-    let output_path = output_dir_path
-        .join("%(uploader_id)s/%(title)s [%(id)s].%(ext)s")
-        .to_string_lossy()
-        .to_string();
+    format_preset: Option<String>,
+    audio_format: Option<String>,
+) -> Result<(), DownloadStartError> {
+    start_or_queue_download(
+        &app,
+        DownloadRequest {
+            id,
+            url,
+            output_dir,
+            cookies_file,
+            cookies_source,
+            cookies_browser,
+            remote_components,
+            yt_dlp_path,
+            ffmpeg_path,
+            quality,
+            is_live,
+            format_preset,
+            audio_format,
+            resume: None,
+        },
+    )
+}
+
+/// ダウンロードをバックグラウンドスレッドで開始する。
+/// 終了時（成功・失敗・中断いずれも）にキューへ完了を通知する。
+/// 空き容量が足りない場合は設定に応じて開始前にエラーを返す。
+pub(crate) fn spawn_download(
+    app: AppHandle,
+    state: DownloadProcessState,
+    request: DownloadRequest,
+) -> Result<(), DownloadStartError> {
+    // 未対応の音声形式を動画として保存してしまわないよう、開始前に弾く
+    resolve_audio_format(request.quality.as_deref(), request.audio_format.as_deref())?;
+    let output_dir_path = library_videos_dir(&request.output_dir);
     if let Err(err) = fs::create_dir_all(&output_dir_path) {
-        return Err(format!("保存先フォルダの作成に失敗しました: {}", err));
+        return Err(format!("保存先フォルダの作成に失敗しました: {}", err).into());
     }
-    let yt_dlp = resolve_override(yt_dlp_path).unwrap_or_else(resolve_yt_dlp);
-    let ffmpeg_location = resolve_override(ffmpeg_path).or_else(|| Some(resolve_ffmpeg()));
-    let format_str = quality_to_format(quality.as_deref());
-    let state = state.inner().clone();
+    preflight_disk_space(&app, &request)?;
 
+    on_recording_started(&app, &request.id);
     std::thread::spawn(move || {
-        let mut last_stdout = String::new();
-        let mut last_stderr = String::new();
-        let mut last_success = false;
-        let mut last_cancelled = false;
-
-        for attempt in 1..=YTDLP_WARNING_RETRY_MAX {
-            let warning_seen = Arc::new(AtomicBool::new(false));
-            let mut command = Command::new(&yt_dlp);
-            #[cfg(windows)]
-            command.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        // ライブ録画ではチャットが後から公開されない場合に備え、並行して取得する
+        let chat_capture = if request.is_live.unwrap_or(false) {
+            match start_live_chat_capture(&request, &network_settings(&app)) {
+                Ok(capture) => Some(capture),
+                Err(err) => {
+                    let _ = write_error_log(&app, "live_chat_capture", &request.id, "", &err);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let started_at_ms = now_ms();
+        let outcome = run_download(&app, &state, &ProcessRunner, &request);
+        if let Some(capture) = chat_capture {
+            capture.finish(&app);
+        }
+        // 一時停止した録画は再開できるため、予約は録画中のまま残す
+        if !outcome.paused {
+            on_recording_finished(&app, &request.id, outcome.success);
+        }
+        record_download(&app, &request, started_at_ms, &outcome);
+        on_download_finished(&app, &request.id);
+        on_download_verified(&app, request, outcome.verified);
+    });
+
+    Ok(())
+}
+
+/// run_download の結果。履歴の記録と再キューの判定に使う。
+struct DownloadOutcome {
+    success: bool,
+    cancelled: bool,
+    /// 一時停止で止めた場合（再開できるため失敗とは扱わない）
+    paused: bool,
+    verified: Option<bool>,
+    attempts: u32,
+    format_id: Option<String>,
+    failure_kind: Option<FailureKind>,
+}
+
+impl DownloadOutcome {
+    /// yt-dlp の起動・制御に失敗して途中で終了した場合
+    fn aborted(attempts: u32) -> Self {
+        Self {
+            success: false,
+            cancelled: false,
+            paused: false,
+            verified: None,
+            attempts,
+            format_id: None,
+            failure_kind: Some(FailureKind::Unknown),
+        }
+    }
+}
+
+fn record_download(app: &AppHandle, request: &DownloadRequest, started_at_ms: u64, outcome: &DownloadOutcome) {
+    let file = if outcome.success {
+        find_downloaded_video_file(&library_videos_dir(&request.output_dir), &request.id)
+    } else {
+        None
+    };
+    record_job(
+        app,
+        JournalEntry {
+            kind: JobKind::Video,
+            id: request.id.clone(),
+            started_at_ms,
+            finished_at_ms: now_ms(),
+            success: outcome.success,
+            cancelled: outcome.cancelled,
+            bytes: file.as_ref().and_then(|path| fs::metadata(path).ok()).map(|m| m.len()),
+            format_id: outcome.format_id.clone(),
+            container: file
+                .as_ref()
+                .and_then(|path| path.extension())
+                .and_then(|ext| ext.to_str())
+                .map(|ext| ext.to_lowercase()),
+            attempts: outcome.attempts,
+            failure_kind: outcome.failure_kind,
+        },
+    );
+}
+
+/// 今回のダウンロードで書き出したファイルを ffprobe で調べる。
+/// ファイルが特定できない・ffprobe が失敗したなど検証できない場合は None。
+/// ストリームの欠落や長さの不一致で壊れていると判断したファイルだけを削除する。
+fn verify_downloaded_media(app: &AppHandle, request: &DownloadRequest, stdout: &str) -> Option<Result<(), String>> {
+    let path = parse_produced_media_path(stdout).filter(|path| path.is_file())?;
+    // ffprobe 自体の失敗（未対応のコーデックなど）はファイルの破損とは限らないため検証を見送る
+    let info = probe_media(path.to_string_lossy().to_string(), read_settings(app).ffprobe_path).ok()?;
+    // ライブ録画は info.json の長さが録画範囲と一致しないため、ストリームの有無のみ確認する
+    let expected_duration = if request.is_live.unwrap_or(false) {
+        None
+    } else {
+        find_info_json(&library_metadata_dir(&request.output_dir), &request.id)
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+            .and_then(|value| parse_video_metadata_value(&value).duration_sec)
+    };
+    let require_video = audio_only_format(request).is_none();
+    let result = check_media_integrity(&info, expected_duration, require_video);
+    if result.is_err() {
+        let _ = fs::remove_file(&path);
+    }
+    Some(result)
+}
+
+/// 音声のみで保存する場合の形式。未対応の形式は spawn_download で弾いているため None として扱う。
+pub(crate) fn audio_only_format(request: &DownloadRequest) -> Option<&'static str> {
+    resolve_audio_format(request.quality.as_deref(), request.audio_format.as_deref())
+        .ok()
+        .flatten()
+}
+
+/// 動画1本分の yt-dlp コマンドを組み立てる
+pub(crate) fn download_command(request: &DownloadRequest, settings: &PersistedSettings) -> Command {
+    let DownloadRequest {
+        url,
+        output_dir,
+        cookies_file,
+        cookies_source,
+        cookies_browser,
+        remote_components,
+        yt_dlp_path,
+        ffmpeg_path,
+        quality,
+        is_live,
+        format_preset,
+        resume,
+        ..
+    } = request;
+    let output_path = library_videos_dir(output_dir)
+        .join("%(uploader_id)s/%(title)s [%(id)s].%(ext)s")
+        .to_string_lossy()
+        .to_string();
+    let yt_dlp = resolve_override(yt_dlp_path.clone()).unwrap_or_else(resolve_yt_dlp);
+    let ffmpeg_location = resolve_override(ffmpeg_path.clone()).or_else(|| Some(resolve_ffmpeg()));
+
+    let mut command = Command::new(&yt_dlp);
+    command
+        .arg("--no-playlist")
+        .arg("--newline")
+        .arg("--progress")
+        .arg("--sleep-subtitles")
+        .arg("5")
+        .arg("--sleep-requests")
+        .arg("0.75")
+        .arg("--sleep-interval")
+        .arg("10")
+        .arg("--max-sleep-interval")
+        .arg("20")
+        .arg("-o")
+        .arg(&output_path);
+    match audio_only_format(request) {
+        Some(audio_format) => {
+            command.args(build_audio_args(audio_format));
+        }
+        None => {
+            let (format_str, merge_format) = resolve_format(
+                settings,
+                format_preset.as_deref(),
+                quality.as_deref(),
+            );
             command
-                .arg("--no-playlist")
-                .arg("--newline")
-                .arg("--progress")
-                .arg("--sleep-subtitles")
-                .arg("5")
-                .arg("--sleep-requests")
-                .arg("0.75")
-                .arg("--sleep-interval")
-                .arg("10")
-                .arg("--max-sleep-interval")
-                .arg("20")
                 .arg("-f")
                 .arg(&format_str)
                 .arg("--merge-output-format")
-                .arg("mp4")
-                .arg("-o")
-                .arg(&output_path);
-            
-            // Live recording mode options
-            if is_live.unwrap_or(false) {
-                command
-                    .arg("--live-from-start")
-                    .arg("--wait-for-video")
-                    .arg("60");
-            }
-            
-            if let Some(location) = &ffmpeg_location {
-                command.arg("--ffmpeg-location").arg(location);
-            }
-            apply_cookies_args(
-                &mut command,
-                cookies_source.as_deref(),
-                cookies_file.as_deref(),
-                cookies_browser.as_deref(),
-            );
-            if let Some(remote) = &remote_components {
-                if !remote.trim().is_empty() {
-                    command.arg("--remote-components").arg(remote);
-                }
+                .arg(&merge_format);
+            if let Some(options) = resolve_preset(settings, format_preset.as_deref())
+                .and_then(|preset| preset.embed.as_ref())
+            {
+                command.args(build_embed_args(options));
             }
-            command.arg(&url).stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+    }
 
-            let child = match command.spawn() {
-                Ok(child) => child,
-                Err(err) => {
-                    let _ = write_error_log(
-                        &app,
-                        "video_download",
-                        &id,
-                        "",
-                        &format!("yt-dlpの起動に失敗しました: {}", err),
-                    );
-                    let _ = app.emit(
-                        "download-finished",
-                        DownloadFinished {
-                            id: id.clone(),
-                            success: false,
-                            stdout: "".to_string(),
-                            stderr: format!("yt-dlpの起動に失敗しました: {}", err),
-                            cancelled: false,
-                            is_private: false,
-                            is_deleted: false,
-                        },
-                    );
-                    return;
-                }
-            };
+    if resume.unwrap_or(false) {
+        command.arg("--continue");
+    }
+    
+    // Live recording mode options
+    if is_live.unwrap_or(false) {
+        command
+            .arg("--live-from-start")
+            .arg("--wait-for-video")
+            .arg(LIVE_WAIT_FOR_VIDEO_SECS.to_string());
+    }
+    
+    if let Some(location) = &ffmpeg_location {
+        command.arg("--ffmpeg-location").arg(location);
+    }
+    apply_cookies_args(
+        &mut command,
+        cookies_source.as_deref(),
+        cookies_file.as_deref(),
+        cookies_browser.as_deref(),
+    );
+    if let Some(remote) = &remote_components {
+        if !remote.trim().is_empty() {
+            command.arg("--remote-components").arg(remote);
+        }
+    }
+    apply_network_args(&mut command, &effective_network_settings(settings));
+    command.arg(url);
+    command
+}
 
-            let child = Arc::new(Mutex::new(child));
-            if let Ok(mut map) = state.children.lock() {
-                map.insert(id.clone(), child.clone());
-            }
+/// 動画ダウンロードのジョブ。出力行は進捗として通知する。
+struct DownloadJob<'a> {
+    request: &'a DownloadRequest,
+    settings: &'a PersistedSettings,
+    policy: RetryPolicy,
+    emit: &'a mut dyn FnMut(DownloadProgress),
+}
 
-            let stdout_acc = Arc::new(Mutex::new(String::new()));
-            let stderr_acc = Arc::new(Mutex::new(String::new()));
-
-            let stdout = {
-                let mut guard = match child.lock() {
-                    Ok(guard) => guard,
-                    Err(err) => {
-                        let _ = app.emit(
-                            "download-finished",
-                            DownloadFinished {
-                                id: id.clone(),
-                                success: false,
-                                stdout: "".to_string(),
-                                stderr: format!("yt-dlpの制御に失敗しました: {}", err),
-                                cancelled: false,
-                                is_private: false,
-                                is_deleted: false,
-                            },
-                        );
-                        let _ = write_error_log(
-                            &app,
-                            "video_download",
-                            &id,
-                            "",
-                            &format!("yt-dlpの制御に失敗しました: {}", err),
-                        );
-                        return;
-                    }
-                };
-                guard.stdout.take()
-            };
-
-            if let Some(stdout) = stdout {
-                let app_clone = app.clone();
-                let id_clone = id.clone();
-                let stdout_acc_clone = stdout_acc.clone();
-                let warning_seen_clone = warning_seen.clone();
-                std::thread::spawn(move || {
-                    let reader = BufReader::new(stdout);
-                    for line in reader.lines().flatten() {
-                        if line.contains(YTDLP_TITLE_WARNING) {
-                            warning_seen_clone.store(true, Ordering::Relaxed);
-                        }
-                        if let Ok(mut buf) = stdout_acc_clone.lock() {
-                            buf.push_str(&line);
-                            buf.push('\n');
-                        }
-                        let _ = app_clone.emit(
-                            "download-progress",
-                            serde_json::json!({ "id": id_clone, "line": line }),
-                        );
-                    }
-                });
-            }
+impl YtDlpJob for DownloadJob<'_> {
+    fn command(&mut self) -> Command {
+        download_command(self.request, self.settings)
+    }
 
-            let stderr = {
-                let mut guard = match child.lock() {
-                    Ok(guard) => guard,
-                    Err(err) => {
-                        let _ = app.emit(
-                            "download-finished",
-                            DownloadFinished {
-                                id: id.clone(),
-                                success: false,
-                                stdout: "".to_string(),
-                                stderr: format!("yt-dlpの制御に失敗しました: {}", err),
-                                cancelled: false,
-                                is_private: false,
-                                is_deleted: false,
-                            },
-                        );
-                        let _ = write_error_log(
-                            &app,
-                            "video_download",
-                            &id,
-                            "",
-                            &format!("yt-dlpの制御に失敗しました: {}", err),
-                        );
-                        return;
-                    }
-                };
-                guard.stderr.take()
-            };
-
-            if let Some(stderr) = stderr {
-                let app_clone = app.clone();
-                let id_clone = id.clone();
-                let stderr_acc_clone = stderr_acc.clone();
-                let warning_seen_clone = warning_seen.clone();
-                std::thread::spawn(move || {
-                    let reader = BufReader::new(stderr);
-                    for line in reader.lines().flatten() {
-                        if line.contains(YTDLP_TITLE_WARNING) {
-                            warning_seen_clone.store(true, Ordering::Relaxed);
-                        }
-                        if let Ok(mut buf) = stderr_acc_clone.lock() {
-                            buf.push_str(&line);
-                            buf.push('\n');
-                        }
-                        let _ = app_clone.emit(
-                            "download-progress",
-                            serde_json::json!({ "id": id_clone, "line": line }),
-                        );
-                    }
-                });
-            }
+    fn on_line(&mut self, _stream: OutputStream, line: &str) -> LineAction {
+        (self.emit)(parse_download_progress(&self.request.id, line));
+        LineAction::Continue
+    }
 
-            let output = loop {
-                let status = {
-                    let mut guard = match child.lock() {
-                        Ok(guard) => guard,
-                        Err(err) => {
-                            let _ = app.emit(
-                                "download-finished",
-                                DownloadFinished {
-                                    id: id.clone(),
-                                    success: false,
-                                    stdout: "".to_string(),
-                                    stderr: format!("yt-dlpの制御に失敗しました: {}", err),
-                                    cancelled: false,
-                                    is_private: false,
-                                    is_deleted: false,
-                                },
-                            );
-                            let _ = write_error_log(
-                                &app,
-                                "video_download",
-                                &id,
-                                "",
-                                &format!("yt-dlpの制御に失敗しました: {}", err),
-                            );
-                            return;
-                        }
-                    };
-                    guard.try_wait()
-                };
-                match status {
-                    Ok(Some(status)) => break status,
-                    Ok(None) => {
-                        std::thread::sleep(Duration::from_millis(200));
-                        continue;
-                    }
-                    Err(err) => {
-                        let _ = app.emit(
-                            "download-finished",
-                            DownloadFinished {
-                                id: id.clone(),
-                                success: false,
-                                stdout: "".to_string(),
-                                stderr: format!("yt-dlpの実行に失敗しました: {}", err),
-                                cancelled: false,
-                                is_private: false,
-                                is_deleted: false,
-                            },
-                        );
-                        let _ = write_error_log(
-                            &app,
-                            "video_download",
-                            &id,
-                            "",
-                            &format!("yt-dlpの実行に失敗しました: {}", err),
-                        );
-                        return;
-                    }
-                }
-            };
+    fn plan_failure_retry(&mut self, kind: FailureKind, retries_done: u32) -> Option<(Duration, String)> {
+        plan_retry_with(&self.policy, kind, retries_done, jitter_sample())
+    }
 
-            let stdout = stdout_acc.lock().map(|s| s.clone()).unwrap_or_default();
-            let stderr = stderr_acc.lock().map(|s| s.clone()).unwrap_or_default();
-            if let Ok(mut map) = state.children.lock() {
-                map.remove(&id);
-            }
-            let cancelled = match state.cancelled.lock() {
-                Ok(mut set) => set.remove(&id),
-                Err(_) => false,
-            };
-
-            last_stdout = stdout;
-            last_stderr = stderr;
-            last_success = output.success();
-            last_cancelled = cancelled;
-
-            let warning_retry = warning_seen.load(Ordering::Relaxed);
-            if warning_retry && !cancelled && attempt < YTDLP_WARNING_RETRY_MAX {
-                let _ = app.emit(
-                    "download-progress",
-                    serde_json::json!({
-                        "id": id.clone(),
-                        "line": format!(
-                            "警告を検知したためリトライします ({}/{})",
-                            attempt,
-                            YTDLP_WARNING_RETRY_MAX
-                        )
-                    }),
-                );
-                std::thread::sleep(Duration::from_millis(YTDLP_WARNING_RETRY_SLEEP_MS));
-                continue;
-            }
+    fn notify(&mut self, message: &str) {
+        (self.emit)(DownloadProgress {
+            id: self.request.id.clone(),
+            line: Some(message.to_string()),
+            ..Default::default()
+        });
+    }
+}
 
-            break;
+/// yt-dlp を実行して結果イベントを送る。成功したかどうかと検証結果を返す。
+fn run_download(
+    app: &AppHandle,
+    state: &DownloadProcessState,
+    runner: &dyn YtDlpRunner,
+    request: &DownloadRequest,
+) -> DownloadOutcome {
+    let id = &request.id;
+    let _active = ActiveDownload::begin(state, id);
+    let settings = read_settings(app);
+    let audio_only = audio_only_format(request);
+
+    let mut emit = |progress: DownloadProgress| {
+        let _ = app.emit("download-progress", progress);
+    };
+    let mut job = DownloadJob {
+        request,
+        settings: &settings,
+        policy: effective_retry_policy(&settings),
+        emit: &mut emit,
+    };
+    let options = RunOptions {
+        timeout: None,
+        process: Some(ProcessHandle {
+            key: id,
+            children: &state.children,
+            cancelled: &state.cancelled,
+        }),
+    };
+    let run = match run_job(runner, &options, &mut job) {
+        Ok(run) => run,
+        Err(failure) => {
+            let _ = write_error_log(app, "video_download", id, "", &failure.message);
+            let _ = app.emit(
+                "download-finished",
+                DownloadFinished {
+                    id: id.clone(),
+                    success: false,
+                    stdout: "".to_string(),
+                    stderr: failure.message,
+                    cancelled: false,
+                    is_private: false,
+                    is_deleted: false,
+                    paused: false,
+                    verified: None,
+                    failure_kind: Some(FailureKind::Unknown),
+                },
+            );
+            return DownloadOutcome::aborted(failure.attempts);
         }
+    };
+    let attempts = run.attempts;
+    let RunOutput {
+        success: mut last_success,
+        stdout: last_stdout,
+        stderr: mut last_stderr,
+        cancelled: last_cancelled,
+        ..
+    } = run.output;
+    let paused = match state.pausing.lock() {
+        Ok(mut set) => set.remove(id),
+        Err(_) => false,
+    };
+    if paused && last_cancelled {
+        if let Ok(mut map) = state.paused.lock() {
+            let mut resume_request = request.clone();
+            resume_request.resume = Some(true);
+            map.insert(id.clone(), resume_request);
+        }
+    }
 
-        if !last_success && !last_cancelled {
-            let _ = write_error_log(&app, "video_download", &id, &last_stdout, &last_stderr);
+    if let Some(audio_format) = audio_only.filter(|_| last_success && !last_cancelled) {
+        if let Err(err) = embed_cover_art(request, audio_format) {
+            let _ = write_error_log(app, "audio_cover_art", id, "", &err);
         }
+    }
 
-        // 非公開動画の検出
-        let is_private = if !last_success && !last_cancelled {
-            let combined = format!("{} {}", &last_stderr, &last_stdout).to_lowercase();
-            combined.contains("video is private") || combined.contains("private video")
-        } else {
-            false
-        };
+    // 終了コードが 0 でもマージが途中で切れていることがあるため、中身を確認する
+    let verified = if last_success && !last_cancelled {
+        verify_downloaded_media(app, request, &last_stdout).map(|result| match result {
+            Ok(()) => true,
+            Err(err) => {
+                last_success = false;
+                last_stderr.push_str(&format!("ダウンロードしたファイルの検証に失敗しました: {}\n", err));
+                false
+            }
+        })
+    } else {
+        None
+    };
 
-        // 削除済み動画の検出
-        let is_deleted = if !last_success && !last_cancelled {
-            let combined = format!("{} {}", &last_stderr, &last_stdout).to_lowercase();
-            combined.contains("has been removed") || combined.contains("account associated with this video has been terminated")
-        } else {
-            false
-        };
+    if !last_success && !last_cancelled {
+        let _ = write_error_log(app, "video_download", id, &last_stdout, &last_stderr);
+    }
 
-        let _ = app.emit(
-            "download-finished",
-            DownloadFinished {
-                id,
-                success: last_success,
-                stdout: last_stdout,
-                stderr: last_stderr,
-                cancelled: last_cancelled,
-                is_private,
-                is_deleted,
-            },
-        );
-    });
+    let failure_kind = if !last_success && !last_cancelled {
+        Some(classify_failure(&last_stdout, &last_stderr))
+    } else {
+        None
+    };
+    // 検証失敗は yt-dlp 自体は成功しているため、非公開・削除済みとは扱わない
+    let is_private = verified.is_none() && failure_kind.is_some_and(FailureKind::is_private);
+    let is_deleted = verified.is_none() && failure_kind.is_some_and(FailureKind::is_deleted);
 
-    Ok(())
+    let _ = app.emit(
+        "download-finished",
+        DownloadFinished {
+            id: id.clone(),
+            success: last_success,
+            stdout: last_stdout.clone(),
+            stderr: last_stderr,
+            cancelled: last_cancelled,
+            is_private,
+            is_deleted,
+            paused: paused && last_cancelled,
+            verified,
+            failure_kind,
+        },
+    );
+
+    DownloadOutcome {
+        success: last_success,
+        cancelled: last_cancelled,
+        paused: paused && last_cancelled,
+        verified,
+        attempts,
+        format_id: parse_selected_format(&last_stdout),
+        failure_kind,
+    }
 }
 
-#[tauri::command]
-pub fn stop_download(state: State<DownloadProcessState>, id: String) -> Result<(), String> {
+/// run_download の実行中であることを示す。破棄すると登録と未処理の停止要求が解除される。
+struct ActiveDownload<'a> {
+    state: &'a DownloadProcessState,
+    id: &'a str,
+}
+
+impl<'a> ActiveDownload<'a> {
+    fn begin(state: &'a DownloadProcessState, id: &'a str) -> Self {
+        if let Ok(mut set) = state.active.lock() {
+            set.insert(id.to_string());
+        }
+        Self { state, id }
+    }
+}
+
+impl Drop for ActiveDownload<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut set) = self.state.active.lock() {
+            set.remove(self.id);
+        }
+        if let Ok(mut set) = self.state.cancelled.lock() {
+            set.remove(self.id);
+        }
+    }
+}
+
+fn kill_download(state: &DownloadProcessState, id: &str, pause: bool) -> Result<(), String> {
     let child = match state.children.lock() {
-        Ok(map) => map.get(&id).cloned(),
+        Ok(map) => map.get(id).cloned(),
         Err(err) => return Err(format!("停止処理に失敗しました: {}", err)),
     };
-
-    let Some(child) = child else {
-        return Err("停止対象のダウンロードが見つかりませんでした。".to_string());
+    let active = match state.active.lock() {
+        Ok(set) => set.contains(id),
+        Err(err) => return Err(format!("停止処理に失敗しました: {}", err)),
     };
+    if child.is_none() && !active {
+        return Err("停止対象のダウンロードが見つかりませんでした。".to_string());
+    }
 
     if let Ok(mut set) = state.cancelled.lock() {
-        set.insert(id);
+        set.insert(id.to_string());
+    }
+    if pause {
+        if let Ok(mut set) = state.pausing.lock() {
+            set.insert(id.to_string());
+        }
     }
 
+    // 再試行の待機中は子プロセスがいないため、待機側が停止要求を検知して終了する
+    let Some(child) = child else {
+        return Ok(());
+    };
     let mut guard = match child.lock() {
         Ok(guard) => guard,
         Err(err) => return Err(format!("停止処理に失敗しました: {}", err)),
@@ -403,6 +757,61 @@ pub fn stop_download(state: State<DownloadProcessState>, id: String) -> Result<(
     Ok(())
 }
 
+#[tauri::command]
+pub fn stop_download(app: AppHandle, state: State<DownloadProcessState>, id: String) -> Result<(), String> {
+    // 一時停止中のジョブは再開情報を破棄して停止扱いにする
+    let discarded = match state.paused.lock() {
+        Ok(mut map) => map.remove(&id).is_some(),
+        Err(err) => return Err(format!("停止処理に失敗しました: {}", err)),
+    };
+    if discarded {
+        release_paused_download(&app, &id, false);
+        on_recording_finished(&app, &id, false);
+        return Ok(());
+    }
+    kill_download(state.inner(), &id, false)
+}
+
+/// 途中ファイル（.part/断片）を残したままプロセスを止め、再開できる状態にする
+#[tauri::command]
+pub fn pause_download(state: State<DownloadProcessState>, id: String) -> Result<(), String> {
+    kill_download(state.inner(), &id, true)
+}
+
+#[tauri::command]
+pub fn resume_download(
+    app: AppHandle,
+    state: State<DownloadProcessState>,
+    id: String,
+) -> Result<(), DownloadStartError> {
+    let request = match state.paused.lock() {
+        Ok(mut map) => map.remove(&id),
+        Err(err) => return Err(format!("再開処理に失敗しました: {}", err).into()),
+    };
+    let Some(request) = request else {
+        return Err("再開対象のダウンロードが見つかりませんでした。".to_string().into());
+    };
+    release_paused_download(&app, &id, true);
+    // 空き容量不足などで開始できなかった場合は一時停止中のまま残す
+    spawn_download(app.clone(), state.inner().clone(), request.clone()).inspect_err(|_| {
+        if let Ok(mut map) = state.paused.lock() {
+            map.insert(id.clone(), request);
+        }
+        on_download_finished(&app, &id);
+    })
+}
+
+#[tauri::command]
+pub fn get_paused_downloads(state: State<DownloadProcessState>) -> Result<Vec<String>, String> {
+    let map = state
+        .paused
+        .lock()
+        .map_err(|e| format!("一時停止中のダウンロード取得に失敗しました: {}", e))?;
+    let mut ids: Vec<String> = map.keys().cloned().collect();
+    ids.sort();
+    Ok(ids)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -455,4 +864,343 @@ mod tests {
         // Falls through to default
         assert!(fmt.contains("bestvideo"));
     }
+
+    // =========================================================
+    // D-2b. parse_download_progress
+    // =========================================================
+
+    const MIB: f64 = 1024.0 * 1024.0;
+
+    #[test]
+    fn progress_basic_line() {
+        let p = parse_download_progress("v1", "[download]  45.3% of  123.45MiB at    2.34MiB/s ETA 00:32");
+        assert_eq!(p.phase, Some(DownloadPhase::Downloading));
+        assert_eq!(p.percent, Some(45.3));
+        assert_eq!(p.total_bytes, Some((123.45 * MIB) as u64));
+        assert!(!p.total_bytes_estimated);
+        assert_eq!(p.speed_bytes_per_sec, Some(2.34 * MIB));
+        assert_eq!(p.eta_seconds, Some(32));
+        let downloaded = p.downloaded_bytes.unwrap() as f64;
+        assert!((downloaded - 123.45 * MIB * 0.453).abs() < 1.0);
+    }
+
+    #[test]
+    fn progress_fragment_with_estimate() {
+        let p = parse_download_progress(
+            "v1",
+            "[download]  12.0% of ~  1.50GiB at  5.00MiB/s ETA 01:02:03 (frag 12/150)",
+        );
+        assert!(p.total_bytes_estimated);
+        assert_eq!(p.total_bytes, Some((1.5 * 1024.0 * MIB) as u64));
+        assert_eq!(p.eta_seconds, Some(3723));
+        assert_eq!(p.fragment_index, Some(12));
+        assert_eq!(p.fragment_count, Some(150));
+    }
+
+    #[test]
+    fn progress_completed_line() {
+        let p = parse_download_progress("v1", "[download] 100% of   10.00MiB in 00:00:52 at 196.92KiB/s");
+        assert_eq!(p.percent, Some(100.0));
+        assert_eq!(p.downloaded_bytes, Some((10.0 * MIB) as u64));
+        assert_eq!(p.speed_bytes_per_sec, Some(196.92 * 1024.0));
+        assert_eq!(p.eta_seconds, None);
+    }
+
+    #[test]
+    fn progress_unknown_speed_and_eta() {
+        let p = parse_download_progress("v1", "[download]   0.0% of   10.00MiB at  Unknown B/s ETA Unknown");
+        assert_eq!(p.percent, Some(0.0));
+        assert_eq!(p.speed_bytes_per_sec, None);
+        assert_eq!(p.eta_seconds, None);
+    }
+
+    #[test]
+    fn progress_live_without_total() {
+        let p = parse_download_progress("v1", "[download]   12.34MiB at  1.23MiB/s (00:00:10)");
+        assert_eq!(p.percent, None);
+        assert_eq!(p.total_bytes, None);
+        assert_eq!(p.downloaded_bytes, Some((12.34 * MIB) as u64));
+        assert_eq!(p.speed_bytes_per_sec, Some(1.23 * MIB));
+    }
+
+    #[test]
+    fn progress_destination_line() {
+        let p = parse_download_progress("v1", "[download] Destination: /lib/videos/ch/title [v1].f137.mp4");
+        assert_eq!(p.phase, Some(DownloadPhase::Downloading));
+        assert_eq!(p.percent, None);
+        assert_eq!(p.downloaded_bytes, None);
+    }
+
+    #[test]
+    fn progress_merger_line() {
+        let p = parse_download_progress("v1", "[Merger] Merging formats into \"/lib/videos/ch/title [v1].mp4\"");
+        assert_eq!(p.phase, Some(DownloadPhase::Merging));
+        assert_eq!(p.postprocessor.as_deref(), Some("Merger"));
+    }
+
+    #[test]
+    fn progress_postprocessor_lines() {
+        let p = parse_download_progress("v1", "[FixupM3u8] Fixing MPEG-TS in MP4 container of \"a.mp4\"");
+        assert_eq!(p.phase, Some(DownloadPhase::PostProcessing));
+        assert_eq!(p.postprocessor.as_deref(), Some("FixupM3u8"));
+        let p = parse_download_progress("v1", "[EmbedThumbnail] ffmpeg: Adding thumbnail to \"a.mp4\"");
+        assert_eq!(p.phase, Some(DownloadPhase::PostProcessing));
+    }
+
+    #[test]
+    fn progress_unrelated_line_keeps_raw() {
+        let line = "[youtube] v1: Downloading webpage";
+        let p = parse_download_progress("v1", line);
+        assert_eq!(p.phase, None);
+        assert_eq!(p.line.as_deref(), Some(line));
+        assert_eq!(p.id, "v1");
+    }
+
+    #[test]
+    fn progress_serializes_camel_case_with_line() {
+        let p = parse_download_progress("v1", "[download]  50.0% of 2.00KiB at 1.00KiB/s ETA 00:01");
+        let json = serde_json::to_value(&p).unwrap();
+        assert_eq!(json["line"], "[download]  50.0% of 2.00KiB at 1.00KiB/s ETA 00:01");
+        assert_eq!(json["phase"], "downloading");
+        assert_eq!(json["downloadedBytes"], 1024);
+        assert_eq!(json["totalBytes"], 2048);
+    }
+
+    #[test]
+    fn produced_path_prefers_last_written_file() {
+        let merged = "\
+[download] Destination: /lib/videos/ch/title [v1].f137.mp4
+[download] Destination: /lib/videos/ch/title [v1].f140.m4a
+[Merger] Merging formats into \"/lib/videos/ch/title [v1].mp4\"
+Deleting original file /lib/videos/ch/title [v1].f137.mp4 (pass -k to keep)";
+        assert_eq!(parse_produced_media_path(merged), Some(PathBuf::from("/lib/videos/ch/title [v1].mp4")));
+
+        let audio = "\
+[download] Destination: /lib/videos/ch/title [v1].webm
+[ExtractAudio] Destination: /lib/videos/ch/title [v1].mp3";
+        assert_eq!(parse_produced_media_path(audio), Some(PathBuf::from("/lib/videos/ch/title [v1].mp3")));
+
+        let moved = "[MoveFiles] Moving file \"/tmp/title [v1].mp4\" to \"/lib/videos/ch/title [v1].mp4\"";
+        assert_eq!(parse_produced_media_path(moved), Some(PathBuf::from("/lib/videos/ch/title [v1].mp4")));
+    }
+
+    #[test]
+    fn produced_path_none_when_nothing_written() {
+        assert_eq!(
+            parse_produced_media_path("[download] /lib/videos/ch/title [v1].mp4 has already been downloaded"),
+            None
+        );
+        assert_eq!(parse_produced_media_path("[youtube] Extracting URL: https://www.youtube.com/watch?v=v1"), None);
+    }
+
+    // =========================================================
+    // D-2d. format presets
+    // =========================================================
+
+    fn preset(name: &str) -> FormatPreset {
+        FormatPreset {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn preset_raw_format_wins() {
+        let mut p = preset("raw");
+        p.format = Some(" 137+140 ".to_string());
+        p.max_height = Some(720);
+        assert_eq!(preset_to_format(&p), "137+140");
+    }
+
+    #[test]
+    fn preset_4k60_vp9() {
+        let mut p = preset("4k");
+        p.codec = Some("vp9".to_string());
+        p.max_height = Some(2160);
+        p.max_fps = Some(60);
+        p.container = Some("mkv".to_string());
+        let fmt = preset_to_format(&p);
+        assert!(fmt.starts_with("bestvideo[height<=2160][fps<=60][vcodec~='^vp0?9']+bestaudio/"));
+        assert!(fmt.contains("/bestvideo[height<=2160][fps<=60]+bestaudio/"));
+        assert!(fmt.ends_with("/best[height<=2160][fps<=60]"));
+        assert_eq!(preset_container(&p), "mkv");
+    }
+
+    #[test]
+    fn preset_hdr_av1() {
+        let mut p = preset("hdr");
+        p.codec = Some("AV1".to_string());
+        p.hdr = Some(true);
+        let fmt = preset_to_format(&p);
+        assert!(fmt.starts_with("bestvideo[vcodec^=av01][dynamic_range!=SDR]+bestaudio[ext=m4a]/bestaudio/"));
+        assert_eq!(preset_container(&p), "mp4");
+    }
+
+    #[test]
+    fn preset_without_filters_has_no_duplicate_fallback() {
+        let fmt = preset_to_format(&preset("plain"));
+        assert_eq!(fmt, "bestvideo+bestaudio[ext=m4a]/bestaudio/best");
+    }
+
+    #[test]
+    fn resolve_format_prefers_video_override() {
+        let mut a = preset("a");
+        a.format = Some("fmt-a".to_string());
+        let mut b = preset("b");
+        b.format = Some("fmt-b".to_string());
+        b.container = Some("webm".to_string());
+        let settings = PersistedSettings {
+            format_presets: vec![a, b],
+            default_format_preset: Some("a".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(resolve_format(&settings, Some("b"), None), ("fmt-b".to_string(), "webm".to_string()));
+        assert_eq!(resolve_format(&settings, None, None), ("fmt-a".to_string(), "mp4".to_string()));
+    }
+
+    #[test]
+    fn resolve_format_falls_back_to_quality() {
+        let settings = PersistedSettings::default();
+        let (fmt, container) = resolve_format(&settings, Some("missing"), Some("720p"));
+        assert_eq!(fmt, quality_to_format(Some("720p")));
+        assert_eq!(container, "mp4");
+    }
+
+    #[test]
+    fn preset_embed_options_deserialize() {
+        let preset: FormatPreset = serde_json::from_str(r#"{"name":"a"}"#).unwrap();
+        assert_eq!(preset.embed, None);
+        let preset: FormatPreset =
+            serde_json::from_str(r#"{"name":"a","embed":{"title":true,"chapters":true}}"#).unwrap();
+        let embed = preset.embed.unwrap();
+        assert!(embed.title && embed.chapters);
+        assert!(!embed.thumbnail);
+    }
+
+    // =========================================================
+    // D-2c. pause / kill
+    // =========================================================
+
+    #[test]
+    fn kill_missing_download_does_not_mark_pausing() {
+        let state = DownloadProcessState::default();
+        assert!(kill_download(&state, "missing", true).is_err());
+        assert!(state.pausing.lock().unwrap().is_empty());
+        assert!(state.cancelled.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn kill_download_waiting_for_retry_marks_cancelled() {
+        let state = DownloadProcessState::default();
+        let active = ActiveDownload::begin(&state, "abc");
+        assert!(kill_download(&state, "abc", true).is_ok());
+        assert!(state.cancelled.lock().unwrap().contains("abc"));
+        assert!(state.pausing.lock().unwrap().contains("abc"));
+        drop(active);
+        assert!(state.active.lock().unwrap().is_empty());
+        assert!(state.cancelled.lock().unwrap().is_empty());
+    }
+
+    // =========================================================
+    // D-2d. download job flow (FakeRunner)
+    // =========================================================
+
+    use crate::runner::fake::{FakeRun, FakeRunner};
+
+    const DOWNLOAD_OK: &str = "\
+[youtube] Extracting URL: https://www.youtube.com/watch?v=abc
+[info] abc: Downloading 1 format(s): 137+140
+[download] Destination: /lib/videos/ch/title [abc].f137.mp4
+[download]  50.0% of   10.00MiB at    2.00MiB/s ETA 00:02
+[download] 100% of   10.00MiB in 00:00:05 at 2.00MiB/s
+[Merger] Merging formats into \"/lib/videos/ch/title [abc].mp4\"
+";
+    const DOWNLOAD_RATE_LIMITED: &str =
+        "ERROR: [youtube] abc: This content isn't available, try again later. Your account has been rate-limited by YouTube for up to an hour.";
+
+    fn request() -> DownloadRequest {
+        DownloadRequest {
+            id: "abc".to_string(),
+            url: "https://www.youtube.com/watch?v=abc".to_string(),
+            output_dir: "/lib".to_string(),
+            cookies_file: None,
+            cookies_source: None,
+            cookies_browser: None,
+            remote_components: None,
+            yt_dlp_path: Some("yt-dlp".to_string()),
+            ffmpeg_path: Some("ffmpeg".to_string()),
+            quality: Some("720p".to_string()),
+            is_live: None,
+            format_preset: None,
+            audio_format: None,
+            resume: None,
+        }
+    }
+
+    fn run_download_job(runner: &FakeRunner, request: &DownloadRequest) -> (u32, RunOutput, Vec<DownloadProgress>) {
+        let settings = PersistedSettings::default();
+        let mut events = Vec::new();
+        let mut emit = |progress: DownloadProgress| events.push(progress);
+        let mut job = DownloadJob {
+            request,
+            settings: &settings,
+            policy: RetryPolicy {
+                jitter_ratio: 0.0,
+                ..Default::default()
+            },
+            emit: &mut emit,
+        };
+        let run = run_job(runner, &RunOptions::default(), &mut job).unwrap();
+        (run.attempts, run.output, events)
+    }
+
+    #[test]
+    fn download_job_emits_progress_per_line() {
+        let runner = FakeRunner::new(vec![FakeRun::ok(DOWNLOAD_OK)]);
+        let (attempts, output, events) = run_download_job(&runner, &request());
+        assert_eq!(attempts, 1);
+        assert!(output.success);
+        assert_eq!(events.len(), 6);
+        assert_eq!(events[3].percent, Some(50.0));
+        assert_eq!(events[5].phase, Some(DownloadPhase::Merging));
+        assert_eq!(parse_selected_format(&output.stdout).as_deref(), Some("137+140"));
+
+        let args = runner.command_args(0);
+        assert_eq!(args.first().map(String::as_str), Some("--no-playlist"));
+        assert_eq!(args.last().map(String::as_str), Some("https://www.youtube.com/watch?v=abc"));
+        assert!(args.contains(&"--ffmpeg-location".to_string()));
+        assert!(!args.contains(&"--continue".to_string()));
+    }
+
+    #[test]
+    fn download_job_retries_rate_limit_with_notice() {
+        let runner = FakeRunner::new(vec![
+            FakeRun::failed(DOWNLOAD_RATE_LIMITED),
+            FakeRun::ok(DOWNLOAD_OK),
+        ]);
+        let (attempts, output, events) = run_download_job(&runner, &request());
+        assert_eq!(attempts, 2);
+        assert!(output.success);
+        let notice = events.iter().find_map(|e| e.line.as_deref().filter(|l| l.contains("リトライ")));
+        assert!(notice.is_some_and(|line| line.starts_with("アクセス制限を検知したため")));
+        assert_eq!(runner.sleeps.lock().unwrap().len(), 1);
+        assert_eq!(runner.run_count(), 2);
+    }
+
+    #[test]
+    fn download_command_for_resumed_live_audio() {
+        let mut request = request();
+        request.resume = Some(true);
+        request.is_live = Some(true);
+        request.quality = Some("audio".to_string());
+        request.audio_format = Some("m4a".to_string());
+        let args: Vec<String> = download_command(&request, &PersistedSettings::default())
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        assert!(args.contains(&"--continue".to_string()));
+        assert!(args.contains(&"--live-from-start".to_string()));
+        assert!(args.contains(&"--extract-audio".to_string()));
+        assert!(!args.contains(&"--merge-output-format".to_string()));
+    }
 }
@@ -0,0 +1,252 @@
+use crate::models::FailureKind;
+
+/// 判定順に並べた (種類, 小文字化したエラー文に含まれる語句) の表。
+/// アカウント停止のエラー文は削除済みの語句 ("this video is no longer available") も含むため先に判定する。
+const FAILURE_PATTERNS: &[(FailureKind, &[&str])] = &[
+    (
+        FailureKind::AccountTerminated,
+        &["account associated with this video has been terminated"],
+    ),
+    (
+        FailureKind::Deleted,
+        &["has been removed", "video has been deleted", "this video is no longer available"],
+    ),
+    (FailureKind::Private, &["video is private", "private video"]),
+    (
+        FailureKind::MembersOnly,
+        &["members-only", "members only", "available to this channel's members"],
+    ),
+    (
+        FailureKind::AgeRestricted,
+        &["confirm your age", "age-restricted", "inappropriate for some users"],
+    ),
+    (
+        FailureKind::GeoBlocked,
+        &[
+            "not available in your country",
+            "not made this video available in your country",
+            "blocked it in your country",
+            "not available from your location",
+            "geo restriction",
+            "geo-restricted",
+        ],
+    ),
+    (
+        FailureKind::CookiesInvalid,
+        &[
+            "cookies are no longer valid",
+            "could not find chrome cookies database",
+            "could not find firefox cookies database",
+            "could not copy chrome cookie database",
+            "failed to decrypt with dpapi",
+            "failed to load cookies",
+            "not a bot",
+        ],
+    ),
+    (
+        FailureKind::RateLimited,
+        &["http error 429", "too many requests", "rate-limited", "rate limited"],
+    ),
+    (
+        FailureKind::FormatUnavailable,
+        &["requested format is not available", "no video formats found"],
+    ),
+    (
+        FailureKind::InsufficientDiskSpace,
+        &["no space left on device", "not enough space on the disk"],
+    ),
+    (
+        FailureKind::Network,
+        &[
+            "unable to download webpage",
+            "temporary failure in name resolution",
+            "getaddrinfo failed",
+            "failed to resolve",
+            "connection reset",
+            "connection refused",
+            "connection aborted",
+            "network is unreachable",
+            "timed out",
+            "remote end closed connection",
+            "ssl: ",
+        ],
+    ),
+];
+
+fn match_failure_patterns(text: &str) -> Option<FailureKind> {
+    FAILURE_PATTERNS
+        .iter()
+        .find(|(_, phrases)| phrases.iter().any(|phrase| text.contains(phrase)))
+        .map(|(kind, _)| *kind)
+}
+
+/// yt-dlp の出力から失敗の種類を判定する。
+/// 古い Cookie の WARNING などが実際の失敗理由を覆い隠さないよう、ERROR 行を先に調べる。
+pub(crate) fn classify_failure(stdout: &str, stderr: &str) -> FailureKind {
+    let combined = format!("{} {}", stderr, stdout).to_lowercase();
+    let errors: Vec<&str> = combined
+        .lines()
+        .map(str::trim_start)
+        .filter(|line| line.starts_with("error:"))
+        .collect();
+    match_failure_patterns(&errors.join("\n"))
+        .or_else(|| match_failure_patterns(&combined))
+        .unwrap_or(FailureKind::Unknown)
+}
+
+impl FailureKind {
+    pub(crate) fn is_private(self) -> bool {
+        self == FailureKind::Private
+    }
+
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            FailureKind::Private => "非公開",
+            FailureKind::Deleted => "削除済み",
+            FailureKind::AccountTerminated => "アカウント停止",
+            FailureKind::MembersOnly => "メンバー限定",
+            FailureKind::AgeRestricted => "年齢制限",
+            FailureKind::GeoBlocked => "地域制限",
+            FailureKind::RateLimited => "アクセス制限",
+            FailureKind::CookiesInvalid => "Cookie無効",
+            FailureKind::FormatUnavailable => "形式なし",
+            FailureKind::InsufficientDiskSpace => "空き容量不足",
+            FailureKind::Network => "ネットワークエラー",
+            FailureKind::Unknown => "不明なエラー",
+        }
+    }
+
+    /// 従来の is_deleted はアカウント停止も含んでいたため、両方を削除済みとして扱う
+    pub(crate) fn is_deleted(self) -> bool {
+        matches!(self, FailureKind::Deleted | FailureKind::AccountTerminated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // =========================================================
+    // classify_failure (yt-dlp の実際のエラー出力)
+    // =========================================================
+
+    const FIXTURES: &[(&str, FailureKind)] = &[
+        (
+            "ERROR: [youtube] abc: Private video. Sign in if you've been granted access to this video",
+            FailureKind::Private,
+        ),
+        ("ERROR: [youtube] abc: This video is private", FailureKind::Private),
+        (
+            "ERROR: [youtube] abc: Video unavailable. This video has been removed by the uploader",
+            FailureKind::Deleted,
+        ),
+        (
+            "ERROR: [youtube] abc: Video unavailable. This video has been removed for violating YouTube's Terms of Service",
+            FailureKind::Deleted,
+        ),
+        (
+            "ERROR: [youtube] abc: Video unavailable. This video is no longer available because the YouTube account associated with this video has been terminated.",
+            FailureKind::AccountTerminated,
+        ),
+        (
+            "ERROR: [youtube] abc: Join this channel to get access to members-only content like this video, and other exclusive perks.",
+            FailureKind::MembersOnly,
+        ),
+        (
+            "ERROR: [youtube] abc: This video is available to this channel's members on level: Supporter (or any higher level). Join this channel to get access to the members-only content and other exclusive perks.",
+            FailureKind::MembersOnly,
+        ),
+        (
+            "ERROR: [youtube] abc: Sign in to confirm your age. This video may be inappropriate for some users. Use --cookies-from-browser or --cookies for the authentication.",
+            FailureKind::AgeRestricted,
+        ),
+        (
+            "ERROR: [youtube] abc: The uploader has not made this video available in your country\nYou might want to use a VPN or a proxy server (with --proxy) to workaround.",
+            FailureKind::GeoBlocked,
+        ),
+        (
+            "ERROR: [youtube] abc: Video unavailable. This video contains content from SME, who has blocked it in your country on copyright grounds",
+            FailureKind::GeoBlocked,
+        ),
+        (
+            "ERROR: unable to download video data: HTTP Error 429: Too Many Requests",
+            FailureKind::RateLimited,
+        ),
+        (
+            "ERROR: [youtube] abc: This content isn't available, try again later. Your account has been rate-limited by YouTube for up to an hour.",
+            FailureKind::RateLimited,
+        ),
+        (
+            "WARNING: [youtube] The provided YouTube account cookies are no longer valid. They have likely been rotated in the browser as a security measure.",
+            FailureKind::CookiesInvalid,
+        ),
+        (
+            "ERROR: [youtube] abc: Sign in to confirm you\u{2019}re not a bot. Use --cookies-from-browser or --cookies for the authentication.",
+            FailureKind::CookiesInvalid,
+        ),
+        (
+            "ERROR: could not find chrome cookies database in \"C:\\Users\\me\\AppData\\Local\\Google\\Chrome\\User Data\"",
+            FailureKind::CookiesInvalid,
+        ),
+        (
+            "ERROR: [youtube] abc: Requested format is not available. Use --list-formats for a list of available formats",
+            FailureKind::FormatUnavailable,
+        ),
+        (
+            "ERROR: [youtube] abc: Unable to download webpage: <urlopen error [Errno -3] Temporary failure in name resolution> (caused by TransportError)",
+            FailureKind::Network,
+        ),
+        (
+            "ERROR: unable to download video data: ('Connection aborted.', ConnectionResetError(104, 'Connection reset by peer'))",
+            FailureKind::Network,
+        ),
+        ("ERROR: [download] Got error: The read operation timed out", FailureKind::Network),
+        (
+            "ERROR: unable to write data: [Errno 28] No space left on device",
+            FailureKind::InsufficientDiskSpace,
+        ),
+        (
+            "ERROR: unable to write data: [Errno 28] There is not enough space on the disk",
+            FailureKind::InsufficientDiskSpace,
+        ),
+        ("ERROR: Postprocessing: Conversion failed!", FailureKind::Unknown),
+        ("", FailureKind::Unknown),
+    ];
+
+    #[test]
+    fn classify_fixtures() {
+        for (stderr, expected) in FIXTURES {
+            assert_eq!(classify_failure("", stderr), *expected, "stderr: {}", stderr);
+        }
+    }
+
+    #[test]
+    fn classify_reads_stdout_too() {
+        assert_eq!(
+            classify_failure("[youtube] abc: Private video", ""),
+            FailureKind::Private
+        );
+    }
+
+    #[test]
+    fn classify_prefers_error_over_warning() {
+        let stderr = "\
+WARNING: [youtube] The provided YouTube account cookies are no longer valid. They have likely been rotated in the browser as a security measure.
+ERROR: unable to download video data: HTTP Error 429: Too Many Requests";
+        assert_eq!(classify_failure("", stderr), FailureKind::RateLimited);
+        // ERROR 行から判定できない場合は WARNING も含めて判定する
+        let stderr = "\
+WARNING: [youtube] The provided YouTube account cookies are no longer valid.
+ERROR: Postprocessing: Conversion failed!";
+        assert_eq!(classify_failure("", stderr), FailureKind::CookiesInvalid);
+    }
+
+    #[test]
+    fn legacy_flags() {
+        assert!(FailureKind::Private.is_private());
+        assert!(FailureKind::Deleted.is_deleted());
+        assert!(FailureKind::AccountTerminated.is_deleted());
+        assert!(!FailureKind::MembersOnly.is_private());
+        assert!(!FailureKind::Network.is_deleted());
+    }
+}
@@ -10,6 +10,7 @@ use crate::models::{VideoIndexState, LocalFileCheckItem, LocalFileCheckResult, M
 use crate::paths::{collect_files_recursive, normalized_library_root, library_videos_dir, library_metadata_dir, library_comments_dir, library_thumbnails_dir};
 use crate::metadata::parse_video_metadata_value;
 use crate::tooling::{resolve_override, resolve_ffprobe};
+use crate::{AUDIO_FILE_EXTENSIONS, VIDEO_FILE_EXTENSIONS, VERIFY_DURATION_TOLERANCE_RATIO, VERIFY_DURATION_TOLERANCE_SECS};
 
 pub(crate) fn extract_id_from_filename(name: &str) -> Option<String> {
     if let (Some(open_idx), Some(close_idx)) = (name.rfind('['), name.rfind(']')) {
@@ -23,6 +24,28 @@ pub(crate) fn extract_id_from_filename(name: &str) -> Option<String> {
     None
 }
 
+/// 動画結合用に落とした "Title [id].f140.m4a" のような中間ファイルかどうか
+fn is_format_intermediate(stem: &str) -> bool {
+    stem.rsplit_once(".f")
+        .is_some_and(|(_, format_id)| !format_id.is_empty() && format_id.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// ライブラリに置かれる動画・音声ファイルかどうか
+pub(crate) fn is_library_media_file(path: &Path) -> bool {
+    let Some(ext) = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()) else {
+        return false;
+    };
+    if VIDEO_FILE_EXTENSIONS.contains(&ext.as_str()) {
+        return true;
+    }
+    if !AUDIO_FILE_EXTENSIONS.contains(&ext.as_str()) {
+        return false;
+    }
+    // 結合に失敗して残った音声の中間ファイルはダウンロード済みとみなさない
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    !is_format_intermediate(stem)
+}
+
 pub(crate) fn find_info_json(dir: &Path, id: &str) -> Option<PathBuf> {
     if !dir.exists() {
         return None;
@@ -94,11 +117,7 @@ pub(crate) fn update_video_index_from_scan(
             Some(name) => name.to_string(),
             None => continue,
         };
-        let ext = path
-            .extension()
-            .and_then(|e| e.to_str())
-            .map(|e| e.to_lowercase());
-        let is_video = matches!(ext.as_deref(), Some("mp4") | Some("webm") | Some("mkv") | Some("m4v"));
+        let is_video = is_library_media_file(path);
         if !is_video {
             continue;
         }
@@ -226,11 +245,7 @@ pub fn verify_local_files(
         };
         let name_lower = name.to_lowercase();
 
-        let ext = path
-            .extension()
-            .and_then(|e| e.to_str())
-            .map(|e| e.to_lowercase());
-        let is_video = matches!(ext.as_deref(), Some("mp4") | Some("webm") | Some("mkv") | Some("m4v"));
+        let is_video = is_library_media_file(path);
         if is_video {
             video_file_count += 1;
             if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
@@ -682,11 +697,7 @@ pub fn resolve_video_file(
             None => continue,
         };
         let name_lower = name.to_lowercase();
-        let ext = path
-            .extension()
-            .and_then(|e| e.to_str())
-            .map(|e| e.to_lowercase());
-        let is_video = matches!(ext.as_deref(), Some("mp4") | Some("webm") | Some("mkv") | Some("m4v"));
+        let is_video = is_library_media_file(path);
         if !is_video {
             continue;
         }
@@ -864,6 +875,65 @@ pub fn probe_media(file_path: String, ffprobe_path: Option<String>) -> Result<Me
     Ok(info)
 }
 
+/// ダウンロード完了後の最終ファイル（"Title [id].mp4" 形式）を探す。
+/// 結合前の "Title [id].f137.mp4" などの中間ファイルは除外する。
+pub(crate) fn find_downloaded_video_file(dir: &Path, id: &str) -> Option<PathBuf> {
+    let mut matches: Vec<(PathBuf, SystemTime)> = Vec::new();
+    for path in collect_files_recursive(dir) {
+        if !is_library_media_file(&path) {
+            continue;
+        }
+        let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if !stem.ends_with(']') {
+            continue;
+        }
+        let matched = extract_id_from_filename(stem)
+            .map(|found| found.eq_ignore_ascii_case(id))
+            .unwrap_or(false);
+        if matched {
+            let modified = fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            matches.push((path, modified));
+        }
+    }
+    matches
+        .into_iter()
+        .max_by_key(|(_, modified)| *modified)
+        .map(|(path, _)| path)
+}
+
+/// ffprobe の結果から、映像・音声ストリームの有無と長さが期待通りかを確認する。
+/// 音声のみで保存した場合は require_video を false にする。
+pub(crate) fn check_media_integrity(
+    info: &MediaInfo,
+    expected_duration_sec: Option<u64>,
+    require_video: bool,
+) -> Result<(), String> {
+    if require_video && info.video_codec.is_none() {
+        return Err("映像ストリームが見つかりません。".to_string());
+    }
+    if info.audio_codec.is_none() {
+        return Err("音声ストリームが見つかりません。".to_string());
+    }
+    if let Some(expected) = expected_duration_sec.filter(|d| *d > 0) {
+        let Some(actual) = info.duration else {
+            return Err("動画の長さを取得できませんでした。".to_string());
+        };
+        let expected = expected as f64;
+        let tolerance = (expected * VERIFY_DURATION_TOLERANCE_RATIO).max(VERIFY_DURATION_TOLERANCE_SECS);
+        if (actual - expected).abs() > tolerance {
+            return Err(format!(
+                "動画の長さが一致しません（期待値 {:.0}秒 / 実際 {:.1}秒）。",
+                expected, actual
+            ));
+        }
+    }
+    Ok(())
+}
+
 #[tauri::command]
 pub fn delete_video_files(
     id: String,
@@ -1189,11 +1259,15 @@ mod tests {
             cancelled: false,
             is_private: true,
             is_deleted: false,
+            paused: false,
+            verified: None,
+            failure_kind: Some(crate::models::FailureKind::Private),
         };
         let json = serde_json::to_value(&df).unwrap();
         assert_eq!(json["id"], "v1");
         assert_eq!(json["success"], false);
         assert_eq!(json["isPrivate"], true);
+        assert_eq!(json["failureKind"], "private");
     }
 
     #[test]
@@ -1203,6 +1277,7 @@ mod tests {
             success: true,
             stdout: String::new(),
             stderr: String::new(),
+            cancelled: false,
             metadata: Some(crate::models::VideoMetadata {
                 id: Some("m1".to_string()),
                 title: Some("Test".to_string()),
@@ -1231,10 +1306,12 @@ mod tests {
                 language: None,
                 audio_language: None,
                 age_limit: None,
+                chapters: None,
             }),
             has_live_chat: Some(false),
             is_private: false,
             is_deleted: false,
+            failure_kind: None,
         };
         let json = serde_json::to_value(&mf).unwrap();
         assert_eq!(json["id"], "m1");
@@ -1304,13 +1381,83 @@ mod tests {
             success: true,
             stdout: String::new(),
             stderr: String::new(),
+            cancelled: false,
             metadata: None,
             has_live_chat: Some(true),
+            failure_kind: None,
         };
         let json = serde_json::to_value(&cf).unwrap();
         assert_eq!(json["id"], "c1");
         assert_eq!(json["success"], true);
         assert_eq!(json["hasLiveChat"], true);
+        assert_eq!(json["cancelled"], false);
         assert!(json["metadata"].is_null());
     }
+
+    // =========================================================
+    // D-4c. post-download verification
+    // =========================================================
+
+    fn media(video: bool, audio: bool, duration: Option<f64>) -> MediaInfo {
+        MediaInfo {
+            video_codec: video.then(|| "h264".to_string()),
+            audio_codec: audio.then(|| "aac".to_string()),
+            width: None,
+            height: None,
+            duration,
+            container: None,
+        }
+    }
+
+    #[test]
+    fn integrity_ok_within_tolerance() {
+        assert!(check_media_integrity(&media(true, true, Some(599.2)), Some(600), true).is_ok());
+        assert!(check_media_integrity(&media(true, true, Some(10_830.0)), Some(10_800), true).is_ok());
+        assert!(check_media_integrity(&media(true, true, None), None, true).is_ok());
+    }
+
+    #[test]
+    fn integrity_truncated_merge() {
+        let err = check_media_integrity(&media(true, true, Some(1_200.0)), Some(10_800), true).unwrap_err();
+        assert!(err.contains("長さ"));
+    }
+
+    #[test]
+    fn integrity_missing_streams() {
+        assert!(check_media_integrity(&media(false, true, Some(10.0)), Some(10), true).is_err());
+        assert!(check_media_integrity(&media(true, false, Some(10.0)), Some(10), true).is_err());
+    }
+
+    #[test]
+    fn integrity_audio_only() {
+        assert!(check_media_integrity(&media(false, true, Some(10.0)), Some(10), false).is_ok());
+        assert!(check_media_integrity(&media(false, false, Some(10.0)), Some(10), false).is_err());
+    }
+
+    #[test]
+    fn library_media_file_extensions() {
+        assert!(is_library_media_file(Path::new("Title [abc].mp4")));
+        assert!(is_library_media_file(Path::new("Title [abc].OPUS")));
+        assert!(is_library_media_file(Path::new("Title [abc].mp3")));
+        assert!(is_library_media_file(Path::new("Title [abc].m4a")));
+        assert!(is_library_media_file(Path::new("Title [abc].f137.mp4")));
+        assert!(!is_library_media_file(Path::new("Title [abc].f140.m4a")));
+        assert!(!is_library_media_file(Path::new("Title [abc].info.json")));
+        assert!(!is_library_media_file(Path::new("Title [abc].m4a.part")));
+    }
+
+    #[test]
+    fn find_downloaded_video_skips_intermediate_files() {
+        let dir = std::env::temp_dir().join("ylv_test_find_downloaded");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("ch")).unwrap();
+        fs::write(dir.join("ch/Title [abc].f137.mp4"), "x").unwrap();
+        fs::write(dir.join("ch/Title [abc].mp4.part"), "x").unwrap();
+        assert!(find_downloaded_video_file(&dir, "abc").is_none());
+        fs::write(dir.join("ch/Title [abc].mp4"), "x").unwrap();
+        fs::write(dir.join("ch/Other [xyz].mp4"), "x").unwrap();
+        let found = find_downloaded_video_file(&dir, "ABC").unwrap();
+        assert!(found.ends_with("ch/Title [abc].mp4"));
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,228 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::AppHandle;
+use crate::models::{DownloadHistoryFilter, DownloadHistoryPage, JournalEntry};
+use crate::paths::{journal_file_path, write_error_log};
+use crate::DEFAULT_HISTORY_PAGE_SIZE;
+
+/// 複数のジョブスレッドから同時に追記されても行が混ざらないようにする
+static JOURNAL_LOCK: Mutex<()> = Mutex::new(());
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// yt-dlp の "[info] abc: Downloading 1 format(s): 137+140" から形式IDを取り出す
+pub(crate) fn parse_selected_format(stdout: &str) -> Option<String> {
+    stdout.lines().rev().find_map(|line| {
+        let (_, rest) = line.split_once("Downloading ")?;
+        let (_, format) = rest.split_once("format(s): ")?;
+        let format = format.trim();
+        (!format.is_empty()).then(|| format.to_string())
+    })
+}
+
+/// 壊れた行（書き込み途中で終了した場合など）は読み飛ばす
+pub(crate) fn parse_journal(content: &str) -> Vec<JournalEntry> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str::<JournalEntry>(line).ok())
+        .collect()
+}
+
+pub(crate) fn matches_filter(entry: &JournalEntry, filter: &DownloadHistoryFilter) -> bool {
+    filter.kind.is_none_or(|kind| entry.kind == kind)
+        && filter
+            .id
+            .as_deref()
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .is_none_or(|id| entry.id.eq_ignore_ascii_case(id))
+        && filter.success.is_none_or(|success| entry.success == success)
+        && filter
+            .failure_kind
+            .is_none_or(|kind| entry.failure_kind == Some(kind))
+        && filter.since_ms.is_none_or(|since| entry.finished_at_ms >= since)
+        && filter.until_ms.is_none_or(|until| entry.finished_at_ms < until)
+}
+
+/// 新しい順に並べてから絞り込み、offset から limit 件を返す
+pub(crate) fn query_history(
+    mut entries: Vec<JournalEntry>,
+    filter: &DownloadHistoryFilter,
+    offset: usize,
+    limit: usize,
+) -> DownloadHistoryPage {
+    entries.reverse();
+    let matched: Vec<JournalEntry> = entries
+        .into_iter()
+        .filter(|entry| matches_filter(entry, filter))
+        .collect();
+    let total = matched.len();
+    let items = matched.into_iter().skip(offset).take(limit).collect();
+    DownloadHistoryPage { total, offset, items }
+}
+
+pub(crate) fn append_journal_entry(app: &AppHandle, entry: &JournalEntry) -> Result<(), String> {
+    let path = journal_file_path(app)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("履歴フォルダの作成に失敗しました: {}", e))?;
+    }
+    let mut line = serde_json::to_string(entry)
+        .map_err(|e| format!("履歴の整形に失敗しました: {}", e))?;
+    line.push('\n');
+    let _guard = JOURNAL_LOCK.lock().map_err(|e| format!("履歴の書き込みに失敗しました: {}", e))?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("履歴ファイルを開けませんでした: {}", e))?;
+    file.write_all(line.as_bytes())
+        .map_err(|e| format!("履歴の書き込みに失敗しました: {}", e))
+}
+
+/// 履歴の記録に失敗してもジョブ自体は成功扱いのままにする
+pub(crate) fn record_job(app: &AppHandle, entry: JournalEntry) {
+    if let Err(err) = append_journal_entry(app, &entry) {
+        let _ = write_error_log(app, "journal", &entry.id, "", &err);
+    }
+}
+
+#[tauri::command]
+pub fn get_download_history(
+    app: AppHandle,
+    filter: Option<DownloadHistoryFilter>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Result<DownloadHistoryPage, String> {
+    let path = journal_file_path(&app)?;
+    let entries = if path.exists() {
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("履歴の読み込みに失敗しました: {}", e))?;
+        parse_journal(&content)
+    } else {
+        Vec::new()
+    };
+    Ok(query_history(
+        entries,
+        &filter.unwrap_or_default(),
+        offset.unwrap_or(0),
+        limit.unwrap_or(DEFAULT_HISTORY_PAGE_SIZE),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{FailureKind, JobKind};
+
+    fn entry(id: &str, kind: JobKind, finished_at_ms: u64, failure_kind: Option<FailureKind>) -> JournalEntry {
+        JournalEntry {
+            kind,
+            id: id.to_string(),
+            started_at_ms: finished_at_ms.saturating_sub(10),
+            finished_at_ms,
+            success: failure_kind.is_none(),
+            cancelled: false,
+            bytes: None,
+            format_id: None,
+            container: None,
+            attempts: 1,
+            failure_kind,
+        }
+    }
+
+    // =========================================================
+    // parse_selected_format
+    // =========================================================
+
+    #[test]
+    fn selected_format_from_stdout() {
+        let stdout = "[youtube] abc: Downloading webpage\n[info] abc: Downloading 1 format(s): 137+140\n[download] 10%";
+        assert_eq!(parse_selected_format(stdout), Some("137+140".to_string()));
+        assert_eq!(parse_selected_format("[download] 100%"), None);
+    }
+
+    // =========================================================
+    // parse_journal
+    // =========================================================
+
+    #[test]
+    fn parse_journal_skips_broken_lines() {
+        let first = serde_json::to_string(&entry("a", JobKind::Video, 100, None)).unwrap();
+        let content = format!("{}\n{{\"kind\":\"video\",\"id\":\"trunc\n\n", first);
+        let entries = parse_journal(&content);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, "a");
+    }
+
+    #[test]
+    fn journal_entry_serialization() {
+        let json = serde_json::to_value(entry("a", JobKind::Comments, 5, Some(FailureKind::RateLimited))).unwrap();
+        assert_eq!(json["kind"], "comments");
+        assert_eq!(json["failureKind"], "rateLimited");
+        assert_eq!(json["finishedAtMs"], 5);
+    }
+
+    // =========================================================
+    // query_history
+    // =========================================================
+
+    fn sample() -> Vec<JournalEntry> {
+        vec![
+            entry("a", JobKind::Metadata, 100, None),
+            entry("a", JobKind::Video, 200, None),
+            entry("b", JobKind::Video, 300, Some(FailureKind::Private)),
+            entry("c", JobKind::Video, 400, Some(FailureKind::RateLimited)),
+            entry("d", JobKind::Comments, 500, None),
+        ]
+    }
+
+    #[test]
+    fn history_newest_first_with_paging() {
+        let page = query_history(sample(), &DownloadHistoryFilter::default(), 1, 2);
+        assert_eq!(page.total, 5);
+        assert_eq!(page.offset, 1);
+        assert_eq!(page.items.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(), vec!["c", "b"]);
+        assert!(query_history(sample(), &DownloadHistoryFilter::default(), 10, 2).items.is_empty());
+    }
+
+    #[test]
+    fn history_filters() {
+        let filter = DownloadHistoryFilter {
+            kind: Some(JobKind::Video),
+            success: Some(false),
+            ..Default::default()
+        };
+        let page = query_history(sample(), &filter, 0, 10);
+        assert_eq!(page.total, 2);
+
+        let filter = DownloadHistoryFilter {
+            failure_kind: Some(FailureKind::RateLimited),
+            ..Default::default()
+        };
+        assert_eq!(query_history(sample(), &filter, 0, 10).items[0].id, "c");
+
+        let filter = DownloadHistoryFilter {
+            id: Some(" A ".to_string()),
+            since_ms: Some(150),
+            ..Default::default()
+        };
+        let page = query_history(sample(), &filter, 0, 10);
+        assert_eq!(page.total, 1);
+        assert_eq!(page.items[0].kind, JobKind::Video);
+
+        let filter = DownloadHistoryFilter {
+            until_ms: Some(300),
+            ..Default::default()
+        };
+        assert_eq!(query_history(sample(), &filter, 0, 10).total, 2);
+    }
+}
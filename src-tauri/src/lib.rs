@@ -8,9 +8,25 @@ mod window;
 mod thumbnails;
 mod state;
 mod files;
+mod audio;
+mod tagging;
+mod failure;
+mod retry;
+mod runner;
+mod journal;
+mod network;
+mod diskspace;
 mod metadata;
 mod comments;
+mod livechat;
+mod subtitles;
+mod sponsorblock;
 mod download;
+mod queue;
+mod scheduler;
+mod subscriptions;
+mod availability;
+mod metadata_history;
 
 // Re-export for use in module cross-references
 pub(crate) use models::*;
@@ -19,9 +35,39 @@ const YTDLP_TITLE_WARNING: &str =
     "No title found in player responses; falling back to title from initial data";
 const YTDLP_WARNING_RETRY_MAX: usize = 10;
 const YTDLP_WARNING_RETRY_SLEEP_MS: u64 = 500;
+const YTDLP_POSTPROCESSOR_TAGS: &[&str] = &[
+    "ExtractAudio",
+    "EmbedSubtitle",
+    "EmbedThumbnail",
+    "Metadata",
+    "ModifyChapters",
+    "SponsorBlock",
+    "SplitChapters",
+    "VideoConvertor",
+    "VideoRemuxer",
+    "ThumbnailsConvertor",
+    "SubtitlesConvertor",
+];
 const YTDLP_NONE_DECODE_ERROR: &str = "NoneType";
 const YTDLP_NONE_DECODE_RETRY_MAX: usize = 2;
 const YTDLP_NONE_DECODE_RETRY_SLEEP_MS: u64 = 10_000;
+const LIVE_CHAT_FINISH_GRACE_SECS: u64 = 30;
+/// 配信開始前の録画で yt-dlp が開始を確認する間隔（--wait-for-video）
+const LIVE_WAIT_FOR_VIDEO_SECS: u64 = 60;
+const SUBTITLE_FORMATS: &[&str] = &["json3", "srv3", "vtt"];
+const SUBTITLE_DOWNLOAD_TIMEOUT_SECS: u64 = 120;
+const DEFAULT_SPONSORBLOCK_API_URL: &str = "https://sponsor.ajay.app";
+const DEFAULT_SPONSORBLOCK_CATEGORIES: &[&str] = &[
+    "sponsor",
+    "intro",
+    "outro",
+    "selfpromo",
+    "interaction",
+    "music_offtopic",
+    "preview",
+    "filler",
+];
+const SPONSORBLOCK_TIMEOUT_SECS: u64 = 15;
 const WINDOW_MIN_WIDTH: u32 = 1280;
 const WINDOW_MIN_HEIGHT: u32 = 720;
 const WINDOW_SIZE_FILE_NAME: &str = "window_size.json";
@@ -30,8 +76,56 @@ const SETTINGS_DIR_NAME: &str = "settings";
 const INDEX_DIR_NAME: &str = "index";
 const SETTINGS_FILE_NAME: &str = "app.json";
 const VIDEOS_FILE_NAME: &str = "videos.json";
+const QUEUE_FILE_NAME: &str = "download_queue.json";
+const SCHEDULE_FILE_NAME: &str = "recording_schedule.json";
+const SUBSCRIPTIONS_FILE_NAME: &str = "subscriptions.json";
+const AVAILABILITY_FILE_NAME: &str = "availability.json";
+const METADATA_HISTORY_DIR_NAME: &str = "metadata_history";
+const JOURNAL_FILE_NAME: &str = "download_journal.jsonl";
+const DEFAULT_HISTORY_PAGE_SIZE: usize = 50;
 const SETTINGS_SCHEMA_VERSION: u32 = 1;
 const VIDEOS_SCHEMA_VERSION: u32 = 1;
+const QUEUE_SCHEMA_VERSION: u32 = 1;
+const SCHEDULE_SCHEMA_VERSION: u32 = 1;
+const SUBSCRIPTIONS_SCHEMA_VERSION: u32 = 1;
+const AVAILABILITY_SCHEMA_VERSION: u32 = 1;
+const METADATA_HISTORY_SCHEMA_VERSION: u32 = 1;
+const DEFAULT_MAX_CONCURRENT_DOWNLOADS: u32 = 2;
+const MAX_CONCURRENT_DOWNLOADS_LIMIT: u32 = 8;
+const AUDIO_FORMATS: &[&str] = &["opus", "m4a", "mp3"];
+const VIDEO_FILE_EXTENSIONS: &[&str] = &["mp4", "webm", "mkv", "m4v"];
+const AUDIO_FILE_EXTENSIONS: &[&str] = &["opus", "m4a", "mp3", "ogg"];
+const VERIFY_DURATION_TOLERANCE_SECS: f64 = 3.0;
+const VERIFY_DURATION_TOLERANCE_RATIO: f64 = 0.01;
+const VERIFY_RETRY_MAX: u32 = 2;
+const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 5_000;
+const DEFAULT_RETRY_MAX_DELAY_MS: u64 = 300_000;
+const DEFAULT_RETRY_JITTER_RATIO: f64 = 0.2;
+const RETRY_MAX_ATTEMPTS_LIMIT: u32 = 10;
+const DEFAULT_DISK_SPACE_MARGIN_MB: u64 = 1_024;
+const RECORDING_LEAD_SECS: i64 = 120;
+/// 予約録画をキューに入れるときの優先度（通常のダウンロードより先に開始する）
+const RECORDING_QUEUE_PRIORITY: i32 = 1_000;
+const RECORDING_SCHEDULER_POLL_SECS: u64 = 30;
+const SUBSCRIPTION_POLLER_TICK_SECS: u64 = 60;
+const DEFAULT_SUBSCRIPTION_POLL_INTERVAL_SECS: u64 = 3_600;
+const MIN_SUBSCRIPTION_POLL_INTERVAL_SECS: u64 = 300;
+/// 巡回時に各タブから取得する件数（新しい順）
+const SUBSCRIPTION_FETCH_LIMIT: u32 = 30;
+const SUBSCRIPTION_KNOWN_IDS_LIMIT: usize = 5_000;
+/// 差分取得で既知の動画がこの件数続いたら一覧の取得を打ち切る
+const INCREMENTAL_SYNC_KNOWN_STREAK: usize = 5;
+const CHANNEL_SYNC_TIMEOUT_SECS: u64 = 120;
+const AVAILABILITY_WATCH_TICK_SECS: u64 = 900;
+/// 1回の巡回で確認する動画数（YouTube への負荷を抑えるため少なめ）
+const AVAILABILITY_CHECKS_PER_TICK: usize = 3;
+const AVAILABILITY_CHECK_SPACING_SECS: u64 = 20;
+const AVAILABILITY_RECHECK_INTERVAL_SECS: u64 = 604_800;
+const AVAILABILITY_CHECK_TIMEOUT_SECS: u64 = 60;
+/// 履歴に残す VideoMetadata の項目（再生数などの変わり続ける値は含めない）
+const METADATA_HISTORY_FIELDS: &[&str] = &["title", "description", "thumbnail", "tags", "categories", "chapters", "availability"];
+const METADATA_HISTORY_LIMIT: usize = 50;
 const BACKUP_SCHEMA_VERSION: u32 = 2;
 const LIBRARY_VIDEOS_DIR_NAME: &str = "videos";
 const LIBRARY_COMMENTS_DIR_NAME: &str = "comments";
@@ -47,6 +141,11 @@ pub fn run() {
         .plugin(tauri_plugin_process::init())
 
         .manage(DownloadProcessState::default())
+        .manage(JobProcessState::default())
+        .manage(DownloadQueueState::default())
+        .manage(RecordingScheduleState::default())
+        .manage(SubscriptionState::default())
+        .manage(AvailabilityState::default())
         .manage(WindowSizeState::default())
         .manage(PlayerWindowSizeState::default())
         .manage(VideoIndexState::default())
@@ -55,12 +154,52 @@ pub fn run() {
             window::get_player_window_size,
             download::start_download,
             download::stop_download,
+            download::pause_download,
+            download::resume_download,
+            download::get_paused_downloads,
+            queue::enqueue_download,
+            queue::dequeue_download,
+            queue::reorder_download_queue,
+            queue::set_download_priority,
+            queue::set_max_concurrent_downloads,
+            queue::get_download_queue,
+            scheduler::schedule_recording,
+            scheduler::cancel_scheduled_recording,
+            scheduler::get_scheduled_recordings,
+            scheduler::set_auto_record_upcoming,
+            subscriptions::subscribe_channel,
+            subscriptions::update_subscription,
+            subscriptions::unsubscribe_channel,
+            subscriptions::get_subscriptions,
+            subscriptions::check_subscription_now,
+            availability::get_availability_records,
+            availability::check_availability_now,
+            availability::set_availability_watch,
             comments::start_comments_download,
+            comments::stop_comments_download,
             metadata::start_metadata_download,
+            metadata::stop_metadata_download,
+            metadata::stop_channel_videos_listing,
             metadata::list_channel_videos,
+            metadata::list_playlist_videos,
             metadata::get_channel_metadata,
+            metadata::get_extended_video_metadata,
+            metadata_history::get_metadata_history,
             metadata::get_video_metadata,
             comments::get_comments,
+            subtitles::get_subtitles,
+            sponsorblock::get_sponsorblock_segments,
+            sponsorblock::fetch_sponsorblock_segments,
+            sponsorblock::get_sponsorblock_settings,
+            sponsorblock::save_sponsorblock_settings,
+            retry::get_retry_policy,
+            retry::save_retry_policy,
+            diskspace::get_disk_space_settings,
+            diskspace::save_disk_space_settings,
+            tagging::retag_video_file,
+            network::get_network_settings,
+            network::save_network_settings,
+            journal::get_download_history,
             files::resolve_video_file,
             files::video_file_exists,
             files::comments_file_exists,
@@ -73,6 +212,8 @@ pub fn run() {
             files::delete_live_metadata_files,
             state::load_state,
             state::save_state,
+            state::get_format_presets,
+            state::save_format_presets,
             state::export_state,
             state::import_state,
             state::dev_reset,
@@ -146,6 +287,15 @@ pub fn run() {
                 }
             }
 
+            // 前回終了時に残っていたキューを復元し、中断されたジョブを再開する
+            queue::restore_download_queue(app.handle());
+            // 配信予定の録画予約を復元し、開始判定のスレッドを起動する
+            scheduler::start_recording_scheduler(app.handle());
+            // 購読チャンネルを復元し、新着を巡回するスレッドを起動する
+            subscriptions::start_subscription_poller(app.handle());
+            // 保存済み動画の公開状態を少しずつ確認するスレッドを起動する
+            availability::start_availability_watch(app.handle());
+
             Ok(())
         })
         .on_window_event(|window, event| {
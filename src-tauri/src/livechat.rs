@@ -0,0 +1,335 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter};
+use crate::models::{DownloadRequest, FailureKind, LiveChatCaptureFinished, NetworkSettings};
+use crate::paths::{atomic_write, collect_files_recursive, library_metadata_dir, write_error_log};
+use crate::tooling::{apply_cookies_args, resolve_yt_dlp, resolve_override};
+use crate::network::apply_network_args;
+use crate::files::find_info_json;
+use crate::{LIVE_CHAT_FINISH_GRACE_SECS, LIVE_WAIT_FOR_VIDEO_SECS};
+
+/// ライブ録画と並行して動くライブチャット取得ジョブ
+pub(crate) struct LiveChatCapture {
+    id: String,
+    output_dir: String,
+    child: Child,
+    started_ms: i64,
+    /// 録画が --live-from-start（配信の先頭から）かどうか
+    live_from_start: bool,
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+fn find_timestamp_usec(value: &serde_json::Value) -> Option<i64> {
+    match value {
+        serde_json::Value::Object(map) => {
+            if let Some(raw) = map.get("timestampUsec") {
+                if let Some(parsed) = raw.as_str().and_then(|s| s.parse::<i64>().ok()).or_else(|| raw.as_i64()) {
+                    return Some(parsed);
+                }
+            }
+            map.values().find_map(find_timestamp_usec)
+        }
+        serde_json::Value::Array(items) => items.iter().find_map(find_timestamp_usec),
+        _ => None,
+    }
+}
+
+/// チャット1行を replayChatItemAction 形式に揃え、videoOffsetTimeMsec を録画開始からの経過時間にする。
+/// 投稿時刻が取れない行（チャット以外のアクション）は None。
+pub(crate) fn align_live_chat_line(line: &str, recording_start_ms: i64) -> Option<String> {
+    let value = serde_json::from_str::<serde_json::Value>(line).ok()?;
+    let timestamp_ms = find_timestamp_usec(&value)? / 1000;
+    let offset_ms = (timestamp_ms - recording_start_ms).max(0);
+    let actions = match value.get("replayChatItemAction").and_then(|v| v.get("actions")) {
+        Some(actions) => actions.clone(),
+        None => serde_json::Value::Array(vec![value.clone()]),
+    };
+    let aligned = serde_json::json!({
+        "replayChatItemAction": {
+            "actions": actions,
+            "videoOffsetTimeMsec": offset_ms.to_string(),
+        }
+    });
+    serde_json::to_string(&aligned).ok()
+}
+
+pub(crate) fn align_live_chat_content(content: &str, recording_start_ms: i64) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| align_live_chat_line(line, recording_start_ms))
+        .collect()
+}
+
+/// --live-from-start で録画した場合、動画の先頭は配信開始時刻になる。
+/// info.json から開始時刻が取れない場合、チャット取得の開始時刻を使えるのは途中から録画したときだけ。
+/// 先頭から録画していて開始時刻が不明な場合は None（時刻を揃えずに保存する）。
+pub(crate) fn resolve_recording_start_ms(
+    info: Option<&serde_json::Value>,
+    capture_started_ms: i64,
+    live_from_start: bool,
+) -> Option<i64> {
+    info.and_then(|value| {
+        value
+            .get("release_timestamp")
+            .and_then(|v| v.as_i64())
+            .or_else(|| value.get("timestamp").and_then(|v| v.as_i64()))
+    })
+    .map(|secs| secs * 1000)
+    .or((!live_from_start).then_some(capture_started_ms))
+}
+
+/// 取得途中（.part）のファイルも含めて、対象IDのライブチャットファイルを探す
+pub(crate) fn find_captured_live_chat(dir: &Path, id: &str) -> Option<PathBuf> {
+    let marker = format!("[{}].live_chat.json", id.to_lowercase());
+    let mut part: Option<PathBuf> = None;
+    for path in collect_files_recursive(dir) {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let name_lower = name.to_lowercase();
+        if name_lower.ends_with(&marker) {
+            return Some(path);
+        }
+        if name_lower.ends_with(&format!("{}.part", marker)) {
+            part = Some(path);
+        }
+    }
+    part
+}
+
+/// 取得したチャットの時刻を揃えて .live_chat.json として保存し、件数を返す
+pub(crate) fn finalize_live_chat_file(path: &Path, recording_start_ms: Option<i64>) -> Result<usize, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("ライブチャットの読み込みに失敗しました: {}", e))?;
+    let lines = match recording_start_ms {
+        Some(start_ms) => align_live_chat_content(&content, start_ms),
+        None => content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.to_string())
+            .collect(),
+    };
+    let final_path = match path.to_string_lossy().strip_suffix(".part") {
+        Some(stripped) => PathBuf::from(stripped),
+        None => path.to_path_buf(),
+    };
+    let mut output = lines.join("\n");
+    output.push('\n');
+    atomic_write(&final_path, output.as_bytes())?;
+    if final_path != path {
+        let _ = fs::remove_file(path);
+    }
+    Ok(lines.len())
+}
+
+/// ライブ録画の開始と同時にライブチャットの取得を始める
+pub(crate) fn start_live_chat_capture(
+    request: &DownloadRequest,
+    network: &NetworkSettings,
+) -> Result<LiveChatCapture, String> {
+    let output_dir_path = library_metadata_dir(&request.output_dir);
+    fs::create_dir_all(&output_dir_path)
+        .map_err(|e| format!("保存先フォルダの作成に失敗しました: {}", e))?;
+    let output_path = output_dir_path
+        .join("%(uploader_id)s/%(title)s [%(id)s].%(ext)s")
+        .to_string_lossy()
+        .to_string();
+    let yt_dlp = resolve_override(request.yt_dlp_path.clone()).unwrap_or_else(resolve_yt_dlp);
+
+    let mut command = Command::new(&yt_dlp);
+    #[cfg(windows)]
+    command.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    command
+        .arg("--no-playlist")
+        .arg("--skip-download")
+        .arg("--write-subs")
+        .arg("--sub-langs")
+        .arg("live_chat")
+        // 録画予約は配信開始前に始まるため、動画のダウンロードと同じく開始を待つ
+        .arg("--wait-for-video")
+        .arg(LIVE_WAIT_FOR_VIDEO_SECS.to_string())
+        .arg("-o")
+        .arg(&output_path);
+    apply_cookies_args(
+        &mut command,
+        request.cookies_source.as_deref(),
+        request.cookies_file.as_deref(),
+        request.cookies_browser.as_deref(),
+    );
+    if let Some(remote) = &request.remote_components {
+        if !remote.trim().is_empty() {
+            command.arg("--remote-components").arg(remote);
+        }
+    }
+    apply_network_args(&mut command, network);
+    command.arg(&request.url).stdout(Stdio::null()).stderr(Stdio::null());
+
+    let child = command
+        .spawn()
+        .map_err(|e| format!("ライブチャット取得の起動に失敗しました: {}", e))?;
+    Ok(LiveChatCapture {
+        id: request.id.clone(),
+        output_dir: request.output_dir.clone(),
+        child,
+        started_ms: now_ms(),
+        live_from_start: request.is_live.unwrap_or(false),
+    })
+}
+
+impl LiveChatCapture {
+    /// 録画終了後に呼ぶ。配信終了でチャット取得が自然に終わるのを少し待ち、残っていれば停止して保存する。
+    pub(crate) fn finish(mut self, app: &AppHandle) {
+        let deadline = Instant::now() + Duration::from_secs(LIVE_CHAT_FINISH_GRACE_SECS);
+        loop {
+            match self.child.try_wait() {
+                Ok(Some(_)) => break,
+                Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(200)),
+                _ => {
+                    let _ = self.child.kill();
+                    let _ = self.child.wait();
+                    break;
+                }
+            }
+        }
+
+        let dir = library_metadata_dir(&self.output_dir);
+        let info = find_info_json(&dir, &self.id)
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok());
+        let recording_start_ms = resolve_recording_start_ms(info.as_ref(), self.started_ms, self.live_from_start);
+
+        let result = find_captured_live_chat(&dir, &self.id)
+            .ok_or_else(|| "ライブチャットが取得できませんでした。".to_string())
+            .and_then(|path| finalize_live_chat_file(&path, recording_start_ms));
+        if let Err(err) = &result {
+            let _ = write_error_log(app, "live_chat_capture", &self.id, "", err);
+        }
+        let _ = app.emit(
+            "live-chat-capture-finished",
+            LiveChatCaptureFinished {
+                id: self.id.clone(),
+                success: result.is_ok(),
+                message_count: result.as_ref().ok().copied(),
+                failure_kind: result.is_err().then_some(FailureKind::Unknown),
+                error: result.err(),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn live_action(timestamp_usec: i64, text: &str) -> String {
+        serde_json::to_string(&json!({
+            "addChatItemAction": {
+                "item": {
+                    "liveChatTextMessageRenderer": {
+                        "message": { "runs": [{ "text": text }] },
+                        "authorName": { "simpleText": "User" },
+                        "timestampUsec": timestamp_usec.to_string()
+                    }
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    // =========================================================
+    // align_live_chat_line
+    // =========================================================
+
+    #[test]
+    fn align_wraps_live_action() {
+        let line = align_live_chat_line(&live_action(1_700_000_005_000_000, "hi"), 1_700_000_000_000).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["replayChatItemAction"]["videoOffsetTimeMsec"], "5000");
+        assert!(value["replayChatItemAction"]["actions"][0]["addChatItemAction"].is_object());
+    }
+
+    #[test]
+    fn align_rewrites_existing_offset() {
+        let line = serde_json::to_string(&json!({
+            "replayChatItemAction": {
+                "actions": [serde_json::from_str::<serde_json::Value>(&live_action(2_000_000, "x")).unwrap()],
+                "videoOffsetTimeMsec": "999999"
+            }
+        }))
+        .unwrap();
+        let aligned = align_live_chat_line(&line, 500).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&aligned).unwrap();
+        assert_eq!(value["replayChatItemAction"]["videoOffsetTimeMsec"], "1500");
+    }
+
+    #[test]
+    fn align_clamps_before_start_and_skips_non_chat() {
+        let aligned = align_live_chat_line(&live_action(1_000_000, "early"), 5_000).unwrap();
+        assert!(aligned.contains("\"videoOffsetTimeMsec\":\"0\""));
+        assert!(align_live_chat_line(r#"{"clickTrackingParams":"x"}"#, 0).is_none());
+        assert!(align_live_chat_line("broken", 0).is_none());
+    }
+
+    // =========================================================
+    // resolve_recording_start_ms
+    // =========================================================
+
+    #[test]
+    fn recording_start_from_info() {
+        let info = json!({ "release_timestamp": 1_700_000_000, "timestamp": 1 });
+        assert_eq!(resolve_recording_start_ms(Some(&info), 42, true), Some(1_700_000_000_000));
+        assert_eq!(resolve_recording_start_ms(Some(&json!({ "timestamp": 7 })), 42, true), Some(7_000));
+        assert_eq!(resolve_recording_start_ms(None, 42, false), Some(42));
+    }
+
+    #[test]
+    fn recording_start_unknown_when_live_from_start() {
+        // 先頭から録画している場合、取得開始時刻は配信開始と一致しない
+        assert_eq!(resolve_recording_start_ms(None, 42, true), None);
+        assert_eq!(resolve_recording_start_ms(Some(&json!({ "id": "abc" })), 42, true), None);
+    }
+
+    // =========================================================
+    // finalize_live_chat_file
+    // =========================================================
+
+    #[test]
+    fn finalize_part_file_readable_by_comments_parser() {
+        let dir = std::env::temp_dir().join("ylv_test_live_chat_capture");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("ch")).unwrap();
+        let part = dir.join("ch/Live [abc].live_chat.json.part");
+        let content = format!(
+            "{}\n{}\n{}\n",
+            live_action(10_000_000, "first"),
+            r#"{"clickTrackingParams":"x"}"#,
+            live_action(12_500_000, "second")
+        );
+        fs::write(&part, content).unwrap();
+
+        let found = find_captured_live_chat(&dir, "abc").unwrap();
+        assert_eq!(found, part);
+        assert_eq!(finalize_live_chat_file(&found, Some(10_000)).unwrap(), 2);
+
+        let final_path = dir.join("ch/Live [abc].live_chat.json");
+        assert!(final_path.exists());
+        assert!(!part.exists());
+        let items = crate::comments::parse_live_chat_content(&fs::read_to_string(&final_path).unwrap());
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].offset_ms, Some(0));
+        assert_eq!(items[1].offset_ms, Some(2_500));
+        assert_eq!(items[1].text, "second");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,17 +1,26 @@
-use std::io::{BufRead, BufReader};
+use std::collections::HashSet;
 use std::fs;
 use std::process::{Command, Stdio};
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
+use std::thread::JoinHandle;
 use std::time::Duration;
-use tauri::{AppHandle, Emitter};
-use crate::models::{VideoMetadata, ChannelVideoItem, MetadataFinished};
-use crate::paths::{library_metadata_dir, write_error_log};
+use tauri::{AppHandle, Emitter, Manager, State};
+use crate::models::{VideoMetadata, ChannelVideoItem, PlaylistVideos, MetadataFinished, Chapter, ExtendedVideoMetadata, HeatmapPoint, SubtitleLanguage, ThumbnailVariant, VideoFormatInfo, DownloadRequest, FailureKind, JobKind, JobProcessState, JournalEntry, NetworkSettings, RetryPolicy};
+use crate::failure::classify_failure;
+use crate::network::{apply_network_args, effective_network_settings, network_settings};
+use crate::retry::{effective_retry_policy, jitter_sample, plan_retry_with};
+use crate::runner::{job_process_key, run_job, LineAction, OutputStream, ProcessHandle, ProcessRunner, RunOptions, RunOutput, Verdict, YtDlpJob, YtDlpRunner};
+use crate::journal::{now_ms, record_job};
+use crate::metadata_history::record_metadata_revision;
+use crate::paths::{library_comments_dir, library_metadata_dir, write_error_log};
+use crate::state::read_settings;
 use crate::tooling::{apply_cookies_args, resolve_yt_dlp, resolve_override, resolve_ffmpeg};
 use crate::files::{find_info_json, comments_file_exists, cleanup_old_live_metadata_files};
-use crate::{YTDLP_TITLE_WARNING, YTDLP_WARNING_RETRY_MAX, YTDLP_WARNING_RETRY_SLEEP_MS};
+use crate::subtitles::build_subtitle_args;
+use crate::sponsorblock::store_sponsor_segments_blocking;
+use crate::scheduler::{parse_upcoming_start, schedule_upcoming_if_enabled};
+use crate::{CHANNEL_SYNC_TIMEOUT_SECS, INCREMENTAL_SYNC_KNOWN_STREAK, SUBTITLE_DOWNLOAD_TIMEOUT_SECS};
 
 pub(crate) fn parse_video_metadata_value(value: &serde_json::Value) -> VideoMetadata {
     VideoMetadata {
@@ -102,6 +111,171 @@ pub(crate) fn parse_video_metadata_value(value: &serde_json::Value) -> VideoMeta
             .and_then(|v| v.as_str())
             .map(|s| s.to_string()),
         age_limit: value.get("age_limit").and_then(|v| v.as_u64()),
+        chapters: parse_chapters(value),
+    }
+}
+
+/// info.json の chapters 配列を読み取る。終了時刻が無い章は次の章の開始（最後は動画長）で補う。
+pub(crate) fn parse_chapters(value: &serde_json::Value) -> Option<Vec<Chapter>> {
+    let entries = value.get("chapters").and_then(|v| v.as_array())?;
+    let starts: Vec<Option<f64>> = entries
+        .iter()
+        .map(|entry| entry.get("start_time").and_then(|v| v.as_f64()))
+        .collect();
+    let duration = value.get("duration").and_then(|v| v.as_f64());
+    let chapters: Vec<Chapter> = entries
+        .iter()
+        .enumerate()
+        .filter_map(|(index, entry)| {
+            let start_time = starts[index]?;
+            let end_time = entry
+                .get("end_time")
+                .and_then(|v| v.as_f64())
+                .or_else(|| starts.get(index + 1).copied().flatten())
+                .or(duration)
+                .unwrap_or(start_time);
+            let title = entry
+                .get("title")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string();
+            Some(Chapter {
+                start_time,
+                end_time,
+                title,
+            })
+        })
+        .collect();
+    if chapters.is_empty() {
+        None
+    } else {
+        Some(chapters)
+    }
+}
+
+/// info.json の formats を読み取る。画質選択に関係しないストーリーボード（mhtml）は除く。
+pub(crate) fn parse_formats(value: &serde_json::Value) -> Vec<VideoFormatInfo> {
+    let Some(entries) = value.get("formats").and_then(|v| v.as_array()) else {
+        return Vec::new();
+    };
+    let text = |entry: &serde_json::Value, key: &str| {
+        entry.get(key).and_then(|v| v.as_str()).map(|s| s.to_string())
+    };
+    // yt-dlp はコーデックが無い場合に "none" を入れる
+    let codec = |entry: &serde_json::Value, key: &str| text(entry, key).filter(|codec| codec != "none");
+    entries
+        .iter()
+        .filter(|entry| {
+            text(entry, "ext").as_deref() != Some("mhtml")
+                && !text(entry, "format_note").is_some_and(|note| note.contains("storyboard"))
+        })
+        .filter_map(|entry| {
+            let format_id = text(entry, "format_id")?;
+            let filesize = entry.get("filesize").and_then(|v| v.as_u64());
+            let filesize_approx = entry.get("filesize_approx").and_then(|v| v.as_u64());
+            Some(VideoFormatInfo {
+                format_id,
+                ext: text(entry, "ext"),
+                format_note: text(entry, "format_note"),
+                resolution: text(entry, "resolution"),
+                width: entry.get("width").and_then(|v| v.as_u64()),
+                height: entry.get("height").and_then(|v| v.as_u64()),
+                fps: entry.get("fps").and_then(|v| v.as_f64()),
+                vcodec: codec(entry, "vcodec"),
+                acodec: codec(entry, "acodec"),
+                tbr: entry.get("tbr").and_then(|v| v.as_f64()),
+                filesize: filesize.or(filesize_approx),
+                filesize_approx: filesize.is_none() && filesize_approx.is_some(),
+            })
+        })
+        .collect()
+}
+
+/// info.json の thumbnails を読み取る。URL の無いものは除く。
+pub(crate) fn parse_thumbnail_variants(value: &serde_json::Value) -> Vec<ThumbnailVariant> {
+    let Some(entries) = value.get("thumbnails").and_then(|v| v.as_array()) else {
+        return Vec::new();
+    };
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let url = entry.get("url").and_then(|v| v.as_str())?.to_string();
+            Some(ThumbnailVariant {
+                id: entry.get("id").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                url,
+                width: entry.get("width").and_then(|v| v.as_u64()),
+                height: entry.get("height").and_then(|v| v.as_u64()),
+                preference: entry.get("preference").and_then(|v| v.as_i64()),
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn parse_heatmap(value: &serde_json::Value) -> Option<Vec<HeatmapPoint>> {
+    let entries = value.get("heatmap").and_then(|v| v.as_array())?;
+    let points: Vec<HeatmapPoint> = entries
+        .iter()
+        .filter_map(|entry| {
+            Some(HeatmapPoint {
+                start_time: entry.get("start_time").and_then(|v| v.as_f64())?,
+                end_time: entry.get("end_time").and_then(|v| v.as_f64())?,
+                value: entry.get("value").and_then(|v| v.as_f64())?,
+            })
+        })
+        .collect();
+    if points.is_empty() {
+        None
+    } else {
+        Some(points)
+    }
+}
+
+/// subtitles と automatic_captions から字幕の言語一覧を作る。手動字幕を先に並べる。
+/// ライブチャットのリプレイは subtitles に "live_chat" として入るが字幕ではないため除く。
+pub(crate) fn parse_subtitle_languages(value: &serde_json::Value) -> Vec<SubtitleLanguage> {
+    let mut languages = Vec::new();
+    for (key, automatic) in [("subtitles", false), ("automatic_captions", true)] {
+        let Some(map) = value.get(key).and_then(|v| v.as_object()) else {
+            continue;
+        };
+        let mut section: Vec<SubtitleLanguage> = map
+            .iter()
+            .filter(|(lang, _)| lang.as_str() != "live_chat")
+            .map(|(lang, tracks)| {
+                let tracks = tracks.as_array().map(|arr| arr.as_slice()).unwrap_or_default();
+                let mut exts: Vec<String> = Vec::new();
+                for ext in tracks.iter().filter_map(|track| track.get("ext").and_then(|v| v.as_str())) {
+                    if !exts.iter().any(|existing| existing == ext) {
+                        exts.push(ext.to_string());
+                    }
+                }
+                SubtitleLanguage {
+                    lang: lang.clone(),
+                    name: tracks
+                        .iter()
+                        .find_map(|track| track.get("name").and_then(|v| v.as_str()))
+                        .map(|s| s.to_string()),
+                    exts,
+                    automatic,
+                }
+            })
+            .collect();
+        section.sort_by(|a, b| a.lang.cmp(&b.lang));
+        languages.extend(section);
+    }
+    languages
+}
+
+pub(crate) fn parse_extended_metadata_value(value: &serde_json::Value) -> ExtendedVideoMetadata {
+    let text = |key: &str| value.get(key).and_then(|v| v.as_str()).map(|s| s.to_string());
+    ExtendedVideoMetadata {
+        metadata: parse_video_metadata_value(value),
+        formats: parse_formats(value),
+        thumbnails: parse_thumbnail_variants(value),
+        heatmap: parse_heatmap(value),
+        subtitles: parse_subtitle_languages(value),
+        release_date: text("release_date"),
+        modified_date: text("modified_date"),
     }
 }
 
@@ -129,6 +303,41 @@ pub(crate) fn normalize_channel_base_url(url: &str) -> String {
     }
 }
 
+/// URL の list= パラメータから再生リストIDを取り出す
+pub(crate) fn extract_playlist_id(url: &str) -> Option<String> {
+    let parsed = url::Url::parse(url.trim()).ok()?;
+    parsed
+        .query_pairs()
+        .find(|(key, _)| key == "list")
+        .map(|(_, value)| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// 非公開・削除済みの動画は再生リスト上で "[Private video]" などのタイトルだけが残る
+fn placeholder_availability(title: &str) -> Option<&'static str> {
+    match title {
+        "[Private video]" => Some("private"),
+        "[Deleted video]" | "[Unavailable video]" => Some("unavailable"),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_playlist_videos(value: &serde_json::Value, playlist_id: &str) -> Result<PlaylistVideos, String> {
+    let mut items = parse_flat_playlist_entries(value)?;
+    for item in items.iter_mut() {
+        if item.availability.is_none() {
+            item.availability = placeholder_availability(&item.title).map(|s| s.to_string());
+        }
+    }
+    let text = |key: &str| value.get(key).and_then(|v| v.as_str()).map(|s| s.to_string());
+    Ok(PlaylistVideos {
+        playlist_id: text("id").unwrap_or_else(|| playlist_id.to_string()),
+        playlist_title: text("title"),
+        channel: text("channel").or_else(|| text("uploader")),
+        items,
+    })
+}
+
 pub(crate) fn build_channel_section_urls(base_url: &str) -> Vec<String> {
     vec![
         format!("{}/videos", base_url.trim_end_matches('/')),
@@ -138,15 +347,16 @@ pub(crate) fn build_channel_section_urls(base_url: &str) -> Vec<String> {
     ]
 }
 
-pub(crate) fn fetch_channel_section(
+/// --flat-playlist の共通引数を組み立てる。出力形式の引数と URL は呼び出し側で付ける。
+fn flat_playlist_command(
     yt_dlp: &str,
-    url: &str,
     cookies_file: Option<&String>,
     cookies_source: Option<&str>,
     cookies_browser: Option<&str>,
     remote_components: Option<&String>,
     limit: Option<u32>,
-) -> Result<Vec<ChannelVideoItem>, String> {
+    network: &NetworkSettings,
+) -> Command {
     let mut command = Command::new(yt_dlp);
     #[cfg(windows)]
     command.creation_flags(0x08000000); // CREATE_NO_WINDOW
@@ -155,8 +365,7 @@ pub(crate) fn fetch_channel_section(
         .arg("--yes-playlist")
         .arg("--ignore-errors")
         .arg("--no-warnings")
-        .arg("--skip-download")
-        .arg("--dump-single-json");
+        .arg("--skip-download");
     if let Some(limit) = limit {
         if limit > 0 {
             command.arg("--playlist-end").arg(limit.to_string());
@@ -173,7 +382,31 @@ pub(crate) fn fetch_channel_section(
             command.arg("--remote-components").arg(remote);
         }
     }
-    command.arg(url);
+    apply_network_args(&mut command, network);
+    command
+}
+
+/// --flat-playlist で一覧を取得し、yt-dlp が出力した JSON をそのまま返す。出力が空の場合は None。
+fn dump_flat_playlist(
+    yt_dlp: &str,
+    url: &str,
+    cookies_file: Option<&String>,
+    cookies_source: Option<&str>,
+    cookies_browser: Option<&str>,
+    remote_components: Option<&String>,
+    limit: Option<u32>,
+    network: &NetworkSettings,
+) -> Result<Option<serde_json::Value>, String> {
+    let mut command = flat_playlist_command(
+        yt_dlp,
+        cookies_file,
+        cookies_source,
+        cookies_browser,
+        remote_components,
+        limit,
+        network,
+    );
+    command.arg("--dump-single-json").arg(url);
 
     let output = command
         .output()
@@ -190,11 +423,114 @@ pub(crate) fn fetch_channel_section(
     }
 
     if stdout.is_empty() {
-        return Ok(Vec::new());
+        return Ok(None);
     }
 
-    let value: serde_json::Value = serde_json::from_slice(&stdout)
-        .map_err(|e| format!("yt-dlpの出力解析に失敗しました: {}", e))?;
+    serde_json::from_slice(&stdout)
+        .map(Some)
+        .map_err(|e| format!("yt-dlpの出力解析に失敗しました: {}", e))
+}
+
+/// チャンネルのタブ1つ分の動画一覧を取得する。
+/// known_ids を渡すと新しい順に1件ずつ読み、既知の動画が続いたところで打ち切る。
+pub(crate) fn fetch_channel_section(
+    yt_dlp: &str,
+    url: &str,
+    cookies_file: Option<&String>,
+    cookies_source: Option<&str>,
+    cookies_browser: Option<&str>,
+    remote_components: Option<&String>,
+    limit: Option<u32>,
+    network: &NetworkSettings,
+    known_ids: Option<&HashSet<String>>,
+    process: ProcessHandle,
+) -> Result<Vec<ChannelVideoItem>, String> {
+    if let Some(known_ids) = known_ids.filter(|ids| !ids.is_empty()) {
+        let mut command = flat_playlist_command(
+            yt_dlp,
+            cookies_file,
+            cookies_source,
+            cookies_browser,
+            remote_components,
+            limit,
+            network,
+        );
+        // --lazy-playlist でページを取得するたびに1件ずつ出力させる
+        command.arg("--lazy-playlist").arg("--dump-json").arg(url);
+        let options = RunOptions {
+            timeout: Some(Duration::from_secs(CHANNEL_SYNC_TIMEOUT_SECS)),
+            process: Some(process),
+        };
+        return read_flat_playlist_until_known(&ProcessRunner, command, &options, known_ids);
+    }
+    match dump_flat_playlist(
+        yt_dlp,
+        url,
+        cookies_file,
+        cookies_source,
+        cookies_browser,
+        remote_components,
+        limit,
+        network,
+    )? {
+        Some(value) => parse_flat_playlist_entries(&value),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// --dump-json の出力を1行ずつ読み、既知の動画が INCREMENTAL_SYNC_KNOWN_STREAK 件続いたら yt-dlp を止める。
+/// 固定表示や配信予定の枠で古い動画が先頭に来ることがあるため、1件目の既知では止めない。
+/// 既知の動画に届く前に yt-dlp が失敗した場合は、途中まで読めていてもエラーにする。
+pub(crate) fn read_flat_playlist_until_known(
+    runner: &dyn YtDlpRunner,
+    command: Command,
+    options: &RunOptions,
+    known_ids: &HashSet<String>,
+) -> Result<Vec<ChannelVideoItem>, String> {
+    let mut items = Vec::new();
+    let mut known_streak = 0;
+    let output = runner.run(command, options, &mut |stream, line| {
+        if stream != OutputStream::Stdout {
+            return LineAction::Continue;
+        }
+        let Ok(entry) = serde_json::from_str::<serde_json::Value>(line.trim()) else {
+            return LineAction::Continue;
+        };
+        let playlist_channel_id = entry
+            .get("playlist_channel_id")
+            .and_then(|v| v.as_str())
+            .or_else(|| entry.get("playlist_uploader_id").and_then(|v| v.as_str()));
+        let Some(item) = parse_flat_playlist_entry(&entry, playlist_channel_id) else {
+            return LineAction::Continue;
+        };
+        if known_ids.contains(&item.id) {
+            known_streak += 1;
+        } else {
+            known_streak = 0;
+        }
+        items.push(item);
+        if known_streak >= INCREMENTAL_SYNC_KNOWN_STREAK {
+            LineAction::Stop
+        } else {
+            LineAction::Continue
+        }
+    })?;
+    if output.stopped || output.success {
+        return Ok(items);
+    }
+    Err(if output.timed_out {
+        format!("動画一覧の取得がタイムアウトしました ({}秒)", CHANNEL_SYNC_TIMEOUT_SECS)
+    } else if output.cancelled {
+        "動画一覧の取得を停止しました。".to_string()
+    } else if output.stderr.trim().is_empty() {
+        "yt-dlpの実行に失敗しました。".to_string()
+    } else {
+        output.stderr
+    })
+}
+
+/// --flat-playlist の出力から動画一覧を組み立てる
+pub(crate) fn parse_flat_playlist_entries(value: &serde_json::Value) -> Result<Vec<ChannelVideoItem>, String> {
     let entries = value
         .get("entries")
         .and_then(|v| v.as_array())
@@ -211,157 +547,361 @@ pub(crate) fn fetch_channel_section(
                 .map(|s| s.to_string())
         });
 
-    let mut items = Vec::new();
-    for entry in entries {
-        let id = entry
-            .get("id")
-            .and_then(|v| v.as_str())
-            .or_else(|| entry.get("url").and_then(|v| v.as_str()))
-            .map(|s| s.to_string());
-        let Some(id) = id else {
-            continue;
-        };
+    Ok(entries
+        .iter()
+        .filter_map(|entry| parse_flat_playlist_entry(entry, channel_id.as_deref()))
+        .collect())
+}
 
-        if channel_id.as_deref().is_some_and(|cid| cid == id) {
-            continue;
+/// --flat-playlist の1件分を組み立てる。チャンネル自身やタブの見出しは None。
+fn parse_flat_playlist_entry(entry: &serde_json::Value, playlist_channel_id: Option<&str>) -> Option<ChannelVideoItem> {
+    let id = entry
+        .get("id")
+        .and_then(|v| v.as_str())
+        .or_else(|| entry.get("url").and_then(|v| v.as_str()))
+        .map(|s| s.to_string())?;
+
+    if playlist_channel_id.is_some_and(|cid| cid == id) {
+        return None;
+    }
+
+    let title = entry
+        .get("title")
+        .and_then(|v| v.as_str())
+        .unwrap_or("Untitled")
+        .to_string();
+
+    if title.ends_with(" - Videos") || title.ends_with(" - Live") || title.ends_with(" - Shorts") {
+        return None;
+    }
+
+    let channel = entry
+        .get("channel")
+        .and_then(|v| v.as_str())
+        .or_else(|| entry.get("uploader").and_then(|v| v.as_str()))
+        .or_else(|| entry.get("channel_title").and_then(|v| v.as_str()))
+        .map(|s| s.to_string());
+    let url_value = entry
+        .get("url")
+        .and_then(|v| v.as_str())
+        .unwrap_or(&id);
+    let full_url = if url_value.starts_with("http") {
+        url_value.to_string()
+    } else {
+        format!("https://www.youtube.com/watch?v={}", url_value)
+    };
+    let thumbnail = entry
+        .get("thumbnail")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let webpage_url = entry
+        .get("webpage_url")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let duration_sec = entry.get("duration").and_then(|v| v.as_u64());
+    let upload_date = entry
+        .get("upload_date")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let release_timestamp = entry.get("release_timestamp").and_then(|v| v.as_i64());
+    let timestamp = entry.get("timestamp").and_then(|v| v.as_i64());
+    let live_status = entry
+        .get("live_status")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let is_live = entry.get("is_live").and_then(|v| v.as_bool());
+    let was_live = entry.get("was_live").and_then(|v| v.as_bool());
+    let view_count = entry.get("view_count").and_then(|v| v.as_u64());
+    let like_count = entry.get("like_count").and_then(|v| v.as_u64());
+    let comment_count = entry.get("comment_count").and_then(|v| v.as_u64());
+    let tags = entry
+        .get("tags")
+        .and_then(|v| v.as_array())
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|item| item.as_str().map(|s| s.to_string()))
+                .collect::<Vec<String>>()
+        });
+    let categories = entry
+        .get("categories")
+        .and_then(|v| v.as_array())
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|item| item.as_str().map(|s| s.to_string()))
+                .collect::<Vec<String>>()
+        });
+    let description = entry
+        .get("description")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let channel_id = entry
+        .get("channel_id")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let uploader_id = entry
+        .get("uploader_id")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let channel_url = entry
+        .get("channel_url")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let uploader_url = entry
+        .get("uploader_url")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let availability = entry
+        .get("availability")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let language = entry
+        .get("language")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let audio_language = entry
+        .get("audio_language")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let age_limit = entry.get("age_limit").and_then(|v| v.as_u64());
+
+    Some(ChannelVideoItem {
+        id,
+        title,
+        channel,
+        url: full_url,
+        thumbnail,
+        webpage_url,
+        duration_sec,
+        upload_date,
+        release_timestamp,
+        timestamp,
+        live_status,
+        is_live,
+        was_live,
+        view_count,
+        like_count,
+        comment_count,
+        tags,
+        categories,
+        description,
+        channel_id,
+        uploader_id,
+        channel_url,
+        uploader_url,
+        availability,
+        language,
+        audio_language,
+        age_limit,
+    })
+}
+
+/// ライブ配信を取得し始めたときに出力されるパターン
+fn is_live_stream_line(stream: OutputStream, line: &str) -> bool {
+    line.contains("live/1")
+        || line.contains("live_broadcast")
+        || line.contains("/live_")
+        || line.contains("playlist_type/DVR")
+        || line.starts_with("frame=")
+        || (stream == OutputStream::Stdout && line.contains("Output #0, mpegts,"))
+}
+
+/// メタデータ取得の各段階で共通する yt-dlp の引数
+struct MetadataCommand {
+    yt_dlp: String,
+    url: String,
+    output_path: String,
+    ffmpeg_location: Option<String>,
+    cookies_source: Option<String>,
+    cookies_file: Option<String>,
+    cookies_browser: Option<String>,
+    remote_components: Option<String>,
+    network: NetworkSettings,
+}
+
+impl MetadataCommand {
+    /// 段階ごとの引数の後に出力先・認証・ネットワークの引数と URL を付ける
+    fn build<I, S>(&self, args: I) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        let mut command = Command::new(&self.yt_dlp);
+        command.args(args).arg("-o").arg(&self.output_path);
+        if let Some(location) = &self.ffmpeg_location {
+            command.arg("--ffmpeg-location").arg(location);
         }
+        apply_cookies_args(
+            &mut command,
+            self.cookies_source.as_deref(),
+            self.cookies_file.as_deref(),
+            self.cookies_browser.as_deref(),
+        );
+        if let Some(remote) = &self.remote_components {
+            if !remote.trim().is_empty() {
+                command.arg("--remote-components").arg(remote);
+            }
+        }
+        apply_network_args(&mut command, &self.network);
+        command.arg(&self.url);
+        command
+    }
+}
 
-        let title = entry
-            .get("title")
-            .and_then(|v| v.as_str())
-            .unwrap_or("Untitled")
-            .to_string();
+/// Step 1: info.json のみを取得するジョブ。配信中・配信予定・非公開などの判定も行う。
+struct InfoJsonJob<'a> {
+    id: &'a str,
+    output_dir: &'a str,
+    base: &'a MetadataCommand,
+    policy: RetryPolicy,
+    emit: &'a mut dyn FnMut(&str),
+    live_detected: bool,
+    upcoming_detected: bool,
+    private_detected: bool,
+    deleted_detected: bool,
+    failure_kind: Option<FailureKind>,
+}
 
-        if title.ends_with(" - Videos") || title.ends_with(" - Live") || title.ends_with(" - Shorts") {
-            continue;
+impl<'a> InfoJsonJob<'a> {
+    fn new(
+        id: &'a str,
+        output_dir: &'a str,
+        base: &'a MetadataCommand,
+        policy: RetryPolicy,
+        emit: &'a mut dyn FnMut(&str),
+    ) -> Self {
+        Self {
+            id,
+            output_dir,
+            base,
+            policy,
+            emit,
+            live_detected: false,
+            upcoming_detected: false,
+            private_detected: false,
+            deleted_detected: false,
+            failure_kind: None,
+        }
+    }
+
+    /// 取得した info.json から配信中かどうかを判定する。読めなかった場合は None。
+    fn check_info_json(&mut self) -> Option<()> {
+        let id = self.id;
+        // 判定前に古いタイムスタンプ付きinfo.jsonを掃除して、最新のファイルだけ残す
+        let cleaned = cleanup_old_live_metadata_files(id, self.output_dir);
+        #[cfg(debug_assertions)]
+        println!("[metadata:{}] cleanup before judgment: {} files deleted", id, cleaned);
+        let info_path = find_info_json(&library_metadata_dir(self.output_dir), id);
+        #[cfg(debug_assertions)]
+        println!("[metadata:{}] find_info_json result: {:?}", id, info_path.as_ref().and_then(|p| p.file_name()));
+        let info_data = fs::read_to_string(info_path?).ok()?;
+        let json_value = serde_json::from_str::<serde_json::Value>(&info_data).ok()?;
+
+        // Check both is_live (boolean) and live_status/liveStatus (string)
+        let is_live_bool = json_value.get("is_live").and_then(|v| v.as_bool()).unwrap_or(false);
+        let live_status_str = json_value.get("live_status")
+            .or_else(|| json_value.get("liveStatus"))
+            .and_then(|v| v.as_str());
+        let is_live_status = live_status_str
+            .map(|s| s == "is_live" || s == "is_upcoming")
+            .unwrap_or(false);
+
+        #[cfg(debug_assertions)]
+        println!("[metadata:{}] info.json check: is_live={}, live_status={:?}", id, is_live_bool, live_status_str);
+
+        if is_live_bool || is_live_status {
+            // It's a live stream, mark as detected and skip comments
+            self.live_detected = true;
+            (self.emit)(&format!(
+                "ライブ配信を検出しました (is_live: {}, live_status: {:?}). コメント取得をスキップします。",
+                is_live_bool,
+                live_status_str
+            ));
+        } else {
+            (self.emit)(&format!(
+                "通常動画を確認 (is_live: {}, live_status: {:?}). info.json取得完了。",
+                is_live_bool,
+                live_status_str
+            ));
         }
+        Some(())
+    }
+}
 
-        let channel = entry
-            .get("channel")
-            .and_then(|v| v.as_str())
-            .or_else(|| entry.get("uploader").and_then(|v| v.as_str()))
-            .or_else(|| entry.get("channel_title").and_then(|v| v.as_str()))
-            .map(|s| s.to_string());
-        let url_value = entry
-            .get("url")
-            .and_then(|v| v.as_str())
-            .unwrap_or(&id);
-        let full_url = if url_value.starts_with("http") {
-            url_value.to_string()
+impl YtDlpJob for InfoJsonJob<'_> {
+    fn command(&mut self) -> Command {
+        self.base.build([
+            "--no-playlist",
+            "--newline",
+            "--progress",
+            "--skip-download",
+            "--write-info-json",
+        ])
+    }
+
+    fn on_line(&mut self, stream: OutputStream, line: &str) -> LineAction {
+        (self.emit)(line);
+        // 配信中は info.json の取得が終わらないため、検出した時点で止める
+        if is_live_stream_line(stream, line) {
+            #[cfg(debug_assertions)]
+            println!("[metadata:{}] live detection triggered, killing process", self.id);
+            self.live_detected = true;
+            return LineAction::Stop;
+        }
+        LineAction::Continue
+    }
+
+    fn inspect(&mut self, attempt: u32, output: &RunOutput) -> Verdict {
+        if output.cancelled {
+            return Verdict::Finish;
+        }
+        let success = output.success && !output.timed_out;
+        #[cfg(debug_assertions)]
+        println!("[metadata:{}] attempt {} result: success={}, timed_out={}", self.id, attempt, success, output.timed_out);
+
+        // 非公開・削除済み動画の検出
+        if !output.success {
+            let kind = classify_failure(&output.stdout, &output.stderr);
+            self.private_detected |= kind.is_private();
+            self.deleted_detected |= kind.is_deleted();
+            self.failure_kind = Some(kind);
         } else {
-            format!("https://www.youtube.com/watch?v={}", url_value)
-        };
-        let thumbnail = entry
-            .get("thumbnail")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let webpage_url = entry
-            .get("webpage_url")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let duration_sec = entry.get("duration").and_then(|v| v.as_u64());
-        let upload_date = entry
-            .get("upload_date")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let release_timestamp = entry.get("release_timestamp").and_then(|v| v.as_i64());
-        let timestamp = entry.get("timestamp").and_then(|v| v.as_i64());
-        let live_status = entry
-            .get("live_status")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let is_live = entry.get("is_live").and_then(|v| v.as_bool());
-        let was_live = entry.get("was_live").and_then(|v| v.as_bool());
-        let view_count = entry.get("view_count").and_then(|v| v.as_u64());
-        let like_count = entry.get("like_count").and_then(|v| v.as_u64());
-        let comment_count = entry.get("comment_count").and_then(|v| v.as_u64());
-        let tags = entry
-            .get("tags")
-            .and_then(|v| v.as_array())
-            .map(|arr| {
-                arr.iter()
-                    .filter_map(|item| item.as_str().map(|s| s.to_string()))
-                    .collect::<Vec<String>>()
-            });
-        let categories = entry
-            .get("categories")
-            .and_then(|v| v.as_array())
-            .map(|arr| {
-                arr.iter()
-                    .filter_map(|item| item.as_str().map(|s| s.to_string()))
-                    .collect::<Vec<String>>()
-            });
-        let description = entry
-            .get("description")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let channel_id = entry
-            .get("channel_id")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let uploader_id = entry
-            .get("uploader_id")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let channel_url = entry
-            .get("channel_url")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let uploader_url = entry
-            .get("uploader_url")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let availability = entry
-            .get("availability")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let language = entry
-            .get("language")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let audio_language = entry
-            .get("audio_language")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let age_limit = entry.get("age_limit").and_then(|v| v.as_u64());
+            self.failure_kind = None;
+        }
 
-        items.push(ChannelVideoItem {
-            id,
-            title,
-            channel,
-            url: full_url,
-            thumbnail,
-            webpage_url,
-            duration_sec,
-            upload_date,
-            release_timestamp,
-            timestamp,
-            live_status,
-            is_live,
-            was_live,
-            view_count,
-            like_count,
-            comment_count,
-            tags,
-            categories,
-            description,
-            channel_id,
-            uploader_id,
-            channel_url,
-            uploader_url,
-            availability,
-            language,
-            audio_language,
-            age_limit,
-        });
+        // If upcoming live event detected (e.g. "This live event will begin in N minutes"),
+        // skip metadata fetch and mark as upcoming.
+        if output.stdout.contains("This live event will begin")
+            || output.stderr.contains("This live event will begin") {
+            self.upcoming_detected = true;
+            (self.emit)("配信予定の動画を検出しました。メタデータ取得をスキップします。");
+            return Verdict::Finish;
+        }
+
+        // If live stream detected, exit retry loop immediately
+        if self.live_detected {
+            return Verdict::Finish;
+        }
+
+        // Step 2: Check if it's a live stream from info.json (inside retry loop)
+        if success && self.check_info_json().is_some() {
+            return Verdict::Finish;
+        }
+        Verdict::Default
     }
 
-    Ok(items)
+    fn plan_failure_retry(&mut self, kind: FailureKind, retries_done: u32) -> Option<(Duration, String)> {
+        plan_retry_with(&self.policy, kind, retries_done, jitter_sample())
+    }
+
+    fn notify(&mut self, message: &str) {
+        (self.emit)(message);
+    }
 }
 
 #[tauri::command]
 pub fn start_metadata_download(
     app: AppHandle,
+    state: State<JobProcessState>,
     id: String,
     url: String,
     output_dir: String,
@@ -371,543 +911,209 @@ pub fn start_metadata_download(
     remote_components: Option<String>,
     yt_dlp_path: Option<String>,
     ffmpeg_path: Option<String>,
+    subtitle_langs: Option<Vec<String>>,
+    auto_subtitles: Option<bool>,
 ) -> Result<(), String> {
-    let output_dir_path = library_metadata_dir(&output_dir);
-    let output_path = output_dir_path
-        .join("%(uploader_id)s/%(title)s [%(id)s].%(ext)s")
-        .to_string_lossy()
-        .to_string();
-    let yt_dlp = resolve_override(yt_dlp_path).unwrap_or_else(resolve_yt_dlp);
-    let ffmpeg_location = resolve_override(ffmpeg_path).or_else(|| Some(resolve_ffmpeg()));
-
-    std::thread::spawn(move || {
-        let mut last_stdout = String::new();
-        let mut last_stderr = String::new();
-        let mut last_success = false;
-        let mut live_detected = false;
-        let mut upcoming_detected = false;
-        let mut private_detected = false;
-        let mut deleted_detected = false;
-
-        if let Err(err) = fs::create_dir_all(&output_dir_path) {
-            let _ = app.emit(
-                "metadata-finished",
-                MetadataFinished {
-                    id,
-                    success: false,
-                    stdout: "".to_string(),
-                    stderr: format!("保存先フォルダの作成に失敗しました: {}", err),
-                    metadata: None,
-                    has_live_chat: None,
-                    is_private: false,
-                    is_deleted: false,
-                },
-            );
-            return;
-        }
-
-
-        for attempt in 1..=YTDLP_WARNING_RETRY_MAX {
-            let warning_seen = Arc::new(AtomicBool::new(false));
-            let live_stream_detected = Arc::new(AtomicBool::new(false));
-            let upcoming_stream_detected = Arc::new(AtomicBool::new(false));
-            
-            // Step 1: Download info.json only (fast, no comments)
-            let mut command = Command::new(&yt_dlp);
-            #[cfg(windows)]
-            command.creation_flags(0x08000000); // CREATE_NO_WINDOW
-            command
-                .arg("--no-playlist")
-                .arg("--newline")
-                .arg("--progress")
-                .arg("--skip-download")
-                .arg("--write-info-json")
-                .arg("-o")
-                .arg(&output_path);
-            if let Some(location) = &ffmpeg_location {
-                command.arg("--ffmpeg-location").arg(location);
-            }
-            apply_cookies_args(
-                &mut command,
-                cookies_source.as_deref(),
-                cookies_file.as_deref(),
-                cookies_browser.as_deref(),
-            );
-            if let Some(remote) = &remote_components {
-                if !remote.trim().is_empty() {
-                    command.arg("--remote-components").arg(remote);
-                }
-            }
-            command.arg(&url).stdout(Stdio::piped()).stderr(Stdio::piped());
-
-            let mut child = match command.spawn() {
-                Ok(child) => child,
-                Err(err) => {
-                    let _ = write_error_log(
-                        &app,
-                        "metadata_download",
-                        &id,
-                        "",
-                        &format!("yt-dlpの起動に失敗しました: {}", err),
-                    );
-                    let _ = app.emit(
-                        "metadata-finished",
-                        MetadataFinished {
-                            id,
-                            success: false,
-                            stdout: "".to_string(),
-                            stderr: format!("yt-dlpの起動に失敗しました: {}", err),
-                            metadata: None,
-                            has_live_chat: None,
-                            is_private: false,
-                            is_deleted: false,
-                        },
-                    );
-                    return;
-                }
-            };
-
-            let stdout_acc = Arc::new(Mutex::new(String::new()));
-            let stderr_acc = Arc::new(Mutex::new(String::new()));
-
-            if let Some(stdout) = child.stdout.take() {
-                let app_clone = app.clone();
-                let id_clone = id.clone();
-                let stdout_acc_clone = stdout_acc.clone();
-                let warning_seen_clone = warning_seen.clone();
-                let live_stream_detected_clone = live_stream_detected.clone();
-                let upcoming_stream_detected_clone = upcoming_stream_detected.clone();
-                std::thread::spawn(move || {
-                    let reader = BufReader::new(stdout);
-                    for line in reader.lines().flatten() {
-                        if line.contains(YTDLP_TITLE_WARNING) {
-                            warning_seen_clone.store(true, Ordering::Relaxed);
-                        }
-                        // Detect upcoming live event patterns
-                        if line.contains("This live event will begin") {
-                            upcoming_stream_detected_clone.store(true, Ordering::Relaxed);
-                        }
-                        // Detect live streaming patterns in stdout
-                        if line.contains("live/1") 
-                            || line.contains("live_broadcast") 
-                            || line.contains("/live_")
-                            || line.contains("playlist_type/DVR")
-                            || line.starts_with("frame=")
-                            || line.contains("Output #0, mpegts,") {
-                            live_stream_detected_clone.store(true, Ordering::Relaxed);
-                        }
-                        if let Ok(mut buf) = stdout_acc_clone.lock() {
-                            buf.push_str(&line);
-                            buf.push('\n');
-                        }
-                        let _ = app_clone.emit(
-                            "metadata-progress",
-                            serde_json::json!({ "id": id_clone, "line": line }),
-                        );
-                    }
-                });
-            }
-
-            if let Some(stderr) = child.stderr.take() {
-                let app_clone = app.clone();
-                let id_clone = id.clone();
-                let stderr_acc_clone = stderr_acc.clone();
-                let warning_seen_clone = warning_seen.clone();
-                let live_stream_detected_clone = live_stream_detected.clone();
-                let upcoming_stream_detected_clone = upcoming_stream_detected.clone();
-                std::thread::spawn(move || {
-                    let reader = BufReader::new(stderr);
-                    for line in reader.lines().flatten() {
-                        if line.contains(YTDLP_TITLE_WARNING) {
-                            warning_seen_clone.store(true, Ordering::Relaxed);
-                        }
-                        // Detect upcoming live event patterns
-                        if line.contains("This live event will begin") {
-                            upcoming_stream_detected_clone.store(true, Ordering::Relaxed);
-                        }
-                        // Detect live streaming patterns
-                        if line.contains("live/1") 
-                            || line.contains("live_broadcast") 
-                            || line.contains("/live_")
-                            || line.contains("playlist_type/DVR")
-                            || line.starts_with("frame=") {
-                            live_stream_detected_clone.store(true, Ordering::Relaxed);
-                        }
-                        if let Ok(mut buf) = stderr_acc_clone.lock() {
-                            buf.push_str(&line);
-                            buf.push('\n');
-                        }
-                        let _ = app_clone.emit(
-                            "metadata-progress",
-                            serde_json::json!({ "id": id_clone, "line": line }),
-                        );
-                    }
-                });
-            }
-
-            let start_time = std::time::Instant::now();
-            let timeout_duration = Duration::from_secs(30); // 30 second timeout for info.json only
-            let mut timed_out = false;
-
-            let output = loop {
-                // Check timeout
-                if start_time.elapsed() > timeout_duration {
-                    // Timeout reached, kill process
-                    let _ = child.kill();
-                    timed_out = true;
-                    
-                    // Wait for process to finish after killing
-                    match child.wait() {
-                        Ok(status) => break status,
-                        Err(err) => {
-                            let _ = write_error_log(
-                                &app,
-                                "metadata_download",
-                                &id,
-                                "",
-                                &format!("タイムアウト後のプロセス終了に失敗: {}", err),
-                            );
-                            let _ = app.emit(
-                                "metadata-finished",
-                                MetadataFinished {
-                                    id: id.clone(),
-                                    success: false,
-                                    stdout: "".to_string(),
-                                    stderr: format!("タイムアウト後のプロセス終了に失敗: {}", err),
-                                    metadata: None,
-                                    has_live_chat: None,
-                                    is_private: false,
-                                    is_deleted: false,
-                                },
-                            );
-                            return;
-                        }
-                    }
-                }
-                
-                // Check if live stream is detected
-                if live_stream_detected.load(Ordering::Relaxed) {
-                    // Kill the process immediately
-                    let _ = child.kill();
-                    live_detected = true;
-                    #[cfg(debug_assertions)]
-                    println!("[metadata:{}] stderr live detection triggered, killing process", id);
-                    
-                    // Wait briefly for process to terminate, but don't block forever
-                    let kill_wait_start = std::time::Instant::now();
-                    let kill_wait_timeout = Duration::from_secs(2);
-                    let mut final_status = None;
-                    while kill_wait_start.elapsed() < kill_wait_timeout {
-                        match child.try_wait() {
-                            Ok(Some(status)) => {
-                                final_status = Some(status);
-                                break;
-                            }
-                            Ok(None) => {
-                                std::thread::sleep(Duration::from_millis(50));
-                            }
-                            Err(_) => break,
-                        }
-                    }
-                    
-                    // Break with whatever status we have (or force success)
-                    break final_status.unwrap_or_else(|| {
-                        // Process didn't exit cleanly, but we detected live stream
-                        // Create a "success" status since we accomplished our goal
-                        #[cfg(unix)]
-                        {
-                            use std::os::unix::process::ExitStatusExt;
-                            std::process::ExitStatus::from_raw(0)
-                        }
-                        #[cfg(windows)]
-                        {
-                            use std::os::windows::process::ExitStatusExt;
-                            std::process::ExitStatus::from_raw(0)
-                        }
-                    });
-                } else {
-                    // Normal exit path - non-blocking check
-                    match child.try_wait() {
-                        Ok(Some(status)) => break status,
-                        Ok(None) => {
-                            // Process still running, sleep briefly and check again
-                            std::thread::sleep(Duration::from_millis(100));
-                        }
-                        Err(err) => {
-                            let _ = write_error_log(
-                                &app,
-                                "metadata_download",
-                                &id,
-                                "",
-                                &format!("プロセス待機中にエラー: {}", err),
-                            );
-                            let _ = app.emit(
-                                "metadata-finished",
-                                MetadataFinished {
-                                    id: id.clone(),
-                                    success: false,
-                                    stdout: "".to_string(),
-                                    stderr: format!("プロセス待機中にエラー: {}", err),
-                                    metadata: None,
-                                    has_live_chat: None,
-                                    is_private: false,
-                                    is_deleted: false,
-                                },
-                            );
-                            return;
-                        }
-                    }
-                }
-            };
-
-            let stdout = stdout_acc.lock().map(|s| s.clone()).unwrap_or_default();
-            let stderr = stderr_acc.lock().map(|s| s.clone()).unwrap_or_default();
+    spawn_metadata_download(
+        app,
+        state.inner(),
+        id,
+        url,
+        output_dir,
+        cookies_file,
+        cookies_source,
+        cookies_browser,
+        remote_components,
+        yt_dlp_path,
+        ffmpeg_path,
+        subtitle_langs,
+        auto_subtitles,
+    );
+    Ok(())
+}
 
-            last_stdout = stdout;
-            last_stderr = stderr;
-            last_success = output.success();
+/// メタデータ取得をバックグラウンドスレッドで開始する。終了を待つ場合は戻り値を join する。
+pub(crate) fn spawn_metadata_download(
+    app: AppHandle,
+    state: &JobProcessState,
+    id: String,
+    url: String,
+    output_dir: String,
+    cookies_file: Option<String>,
+    cookies_source: Option<String>,
+    cookies_browser: Option<String>,
+    remote_components: Option<String>,
+    yt_dlp_path: Option<String>,
+    ffmpeg_path: Option<String>,
+    subtitle_langs: Option<Vec<String>>,
+    auto_subtitles: Option<bool>,
+) -> JoinHandle<()> {
+    let output_dir_path = library_metadata_dir(&output_dir);
+    let output_path = output_dir_path
+        .join("%(uploader_id)s/%(title)s [%(id)s].%(ext)s")
+        .to_string_lossy()
+        .to_string();
+    let subtitle_args = build_subtitle_args(
+        subtitle_langs.as_deref().unwrap_or_default(),
+        auto_subtitles.unwrap_or(false),
+    );
+    let settings = read_settings(&app);
+    let base = MetadataCommand {
+        yt_dlp: resolve_override(yt_dlp_path).unwrap_or_else(resolve_yt_dlp),
+        url,
+        output_path,
+        ffmpeg_location: resolve_override(ffmpeg_path).or_else(|| Some(resolve_ffmpeg())),
+        cookies_source,
+        cookies_file,
+        cookies_browser,
+        remote_components,
+        network: effective_network_settings(&settings),
+    };
+    let state = state.clone();
+    let key = job_process_key(JobKind::Metadata, &id);
+    let active = state.begin(&key);
 
-            #[cfg(debug_assertions)]
-            println!("[metadata:{}] attempt {} result: success={}, timed_out={}", id, attempt, last_success, timed_out);
+    std::thread::spawn(move || {
+        let _active = active;
+        let runner = ProcessRunner;
+        let started_at_ms = now_ms();
 
-            // 非公開動画の検出
-            if !last_success {
-                let combined = format!("{} {}", &last_stderr, &last_stdout).to_lowercase();
-                if combined.contains("video is private") || combined.contains("private video") {
-                    private_detected = true;
-                }
-                if combined.contains("has been removed") || combined.contains("account associated with this video has been terminated") {
-                    deleted_detected = true;
-                }
-            }
+        if let Err(err) = fs::create_dir_all(&output_dir_path) {
+            let _ = app.emit(
+                "metadata-finished",
+                MetadataFinished {
+                    id,
+                    success: false,
+                    stdout: "".to_string(),
+                    stderr: format!("保存先フォルダの作成に失敗しました: {}", err),
+                    cancelled: false,
+                    metadata: None,
+                    has_live_chat: None,
+                    is_private: false,
+                    is_deleted: false,
+                    failure_kind: Some(FailureKind::Unknown),
+                },
+            );
+            return;
+        }
 
-            // If timed out during info.json download, treat as error
-            if timed_out {
-                last_success = false;
-                last_stderr = format!("{}\nメタデータ取得がタイムアウトしました (30秒)", last_stderr);
-            }
+        let progress = |line: &str| {
+            let _ = app.emit(
+                "metadata-progress",
+                serde_json::json!({ "id": id, "line": line }),
+            );
+        };
 
-            // If upcoming live event detected (e.g. "This live event will begin in N minutes"),
-            // skip metadata fetch and mark as upcoming.
-            // Check both the atomic flag AND the stderr text to avoid race conditions
-            // where the flag hasn't been set yet when we check it.
-            if upcoming_stream_detected.load(Ordering::Relaxed)
-                || last_stderr.contains("This live event will begin") {
-                upcoming_detected = true;
+        // Step 1: Download info.json only (fast, no comments)
+        let mut emit = progress;
+        let mut job = InfoJsonJob::new(&id, &output_dir, &base, effective_retry_policy(&settings), &mut emit);
+        let options = RunOptions {
+            timeout: Some(Duration::from_secs(30)), // 30 second timeout for info.json only
+            process: Some(state.handle(&key)),
+        };
+        let run = match run_job(&runner, &options, &mut job) {
+            Ok(run) => run,
+            Err(failure) => {
+                let _ = write_error_log(&app, "metadata_download", &id, "", &failure.message);
                 let _ = app.emit(
-                    "metadata-progress",
-                    serde_json::json!({
-                        "id": id.clone(),
-                        "line": "配信予定の動画を検出しました。メタデータ取得をスキップします。"
-                    }),
+                    "metadata-finished",
+                    MetadataFinished {
+                        id: id.clone(),
+                        success: false,
+                        stdout: "".to_string(),
+                        stderr: failure.message,
+                        cancelled: false,
+                        metadata: None,
+                        has_live_chat: None,
+                        is_private: false,
+                        is_deleted: false,
+                        failure_kind: Some(FailureKind::Unknown),
+                    },
                 );
-                break;
-            }
-
-            // If live stream detected, exit retry loop immediately
-            if live_detected {
-                break;
+                return;
             }
+        };
+        let InfoJsonJob {
+            mut live_detected,
+            upcoming_detected,
+            private_detected,
+            deleted_detected,
+            failure_kind,
+            ..
+        } = job;
+        let attempts = run.attempts;
+        let mut cancelled = run.output.cancelled;
+        // 配信中を検出して止めた場合は目的を果たしているため成功として扱う
+        let mut last_success = run.output.success || live_detected;
+        let last_stdout = run.output.stdout;
+        let mut last_stderr = run.output.stderr;
+        // If timed out during info.json download, treat as error
+        if run.output.timed_out {
+            last_success = false;
+            last_stderr = format!("{}\nメタデータ取得がタイムアウトしました (30秒)", last_stderr);
+        }
 
-            // Step 2: Check if it's a live stream from info.json (inside retry loop)
-            
-            if last_success && !live_detected {
-                // 判定前に古いタイムスタンプ付きinfo.jsonを掃除して、最新のファイルだけ残す
-                let cleaned = cleanup_old_live_metadata_files(&id, &output_dir);
-                #[cfg(debug_assertions)]
-                println!("[metadata:{}] cleanup before judgment: {} files deleted", id, cleaned);
-                let info_path = find_info_json(&output_dir_path, &id);
-                #[cfg(debug_assertions)]
-                println!("[metadata:{}] find_info_json result: {:?}", id, info_path.as_ref().and_then(|p| p.file_name()));
-                if let Some(ref info_file) = info_path {
-                    
-                    match fs::read_to_string(&info_file) {
-                        Ok(info_data) => {
-                            match serde_json::from_str::<serde_json::Value>(&info_data) {
-                                Ok(json_value) => {
-                                    // Check both is_live (boolean) and live_status/liveStatus (string)
-                                    let is_live_bool = json_value.get("is_live").and_then(|v| v.as_bool()).unwrap_or(false);
-                                    let live_status_str = json_value.get("live_status")
-                                        .or_else(|| json_value.get("liveStatus"))
-                                        .and_then(|v| v.as_str());
-                                    let is_live_status = live_status_str
-                                        .map(|s| s == "is_live" || s == "is_upcoming")
-                                        .unwrap_or(false);
-                                    
-                                    #[cfg(debug_assertions)]
-                                    println!("[metadata:{}] info.json check: is_live={}, live_status={:?}", id, is_live_bool, live_status_str);
-
-                                    if is_live_bool || is_live_status {
-                                        // It's a live stream, mark as detected and skip comments
-                                        live_detected = true;
-                                        let _ = app.emit(
-                                            "metadata-progress",
-                                            serde_json::json!({
-                                                "id": id.clone(),
-                                                "line": format!("ライブ配信を検出しました (is_live: {}, live_status: {:?}). コメント取得をスキップします。", 
-                                                    is_live_bool,
-                                                    live_status_str)
-                                            }),
-                                        );
-                                    } else {
-                                        let _ = app.emit(
-                                            "metadata-progress",
-                                            serde_json::json!({
-                                                "id": id.clone(),
-                                                "line": format!("通常動画を確認 (is_live: {}, live_status: {:?}). info.json取得完了。", 
-                                                    is_live_bool,
-                                                    live_status_str)
-                                            }),
-                                        );
-                                    }
-                                    // Exit retry loop immediately after successfully reading info.json
-                                    break;
-                                }
-                                Err(_e) => {
-                                    // Failed to parse JSON
-                                }
-                            }
-                        }
-                        Err(_e) => {
-                            // Failed to read file
-                        }
-                    }
+        // Step 3: If not live, download comments (after retry loop)
+        if last_success && !live_detected && !cancelled {
+            progress("コメントをダウンロード中...");
+
+            let comment_command = base.build([
+                "--no-playlist",
+                "--newline",
+                "--progress",
+                "--skip-download",
+                "--write-comments",
+                "--write-subs",
+                "--sub-langs",
+                "live_chat",
+                "--sub-format",
+                "json",
+            ]);
+            // Run comment download with timeout and live detection
+            let options = RunOptions {
+                timeout: Some(Duration::from_secs(120)), // 120 second timeout for comments
+                process: Some(state.handle(&key)),
+            };
+            let comment_run = runner.run(comment_command, &options, &mut |stream, line| {
+                if is_live_stream_line(stream, line) {
+                    LineAction::Stop
                 } else {
-                    // info.json not found
+                    LineAction::Continue
                 }
+            });
+            if let Ok(output) = comment_run {
+                if output.stopped {
+                    #[cfg(debug_assertions)]
+                    println!("[metadata:{}] comment step: live detected in stdout/stderr, killing process", id);
+                    live_detected = true;
+                }
+                cancelled |= output.cancelled;
             }
-
-            let warning_retry = warning_seen.load(Ordering::Relaxed);
-            if warning_retry && attempt < YTDLP_WARNING_RETRY_MAX {
-                let _ = app.emit(
-                    "metadata-progress",
-                    serde_json::json!({
-                        "id": id.clone(),
-                        "line": format!(
-                            "警告を検知したためリトライします ({}/{})",
-                            attempt,
-                            YTDLP_WARNING_RETRY_MAX
-                        )
-                    }),
-                );
-                std::thread::sleep(Duration::from_millis(YTDLP_WARNING_RETRY_SLEEP_MS));
-                continue;
-            }
-
-            break;
         }
 
-        // Step 3: If not live, download comments (after retry loop)
-        if last_success && !live_detected {
-            let _ = app.emit(
-                "metadata-progress",
-                serde_json::json!({
-                    "id": id.clone(),
-                    "line": "コメントをダウンロード中..."
-                }),
-            );
+        // Step 4: Download chosen subtitles / auto captions
+        if let Some(subtitle_args) = subtitle_args.as_ref().filter(|_| last_success && !live_detected && !cancelled) {
+            progress("字幕をダウンロード中...");
 
-            let mut comment_command = Command::new(&yt_dlp);
-            #[cfg(windows)]
-            comment_command.creation_flags(0x08000000); // CREATE_NO_WINDOW
-            comment_command
-                .arg("--no-playlist")
-                .arg("--newline")
-                .arg("--progress")
-                .arg("--skip-download")
-                .arg("--write-comments")
-                .arg("--write-subs")
-                .arg("--sub-langs")
-                .arg("live_chat")
-                .arg("--sub-format")
-                .arg("json")
-                .arg("-o")
-                .arg(&output_path);
-            if let Some(location) = &ffmpeg_location {
-                comment_command.arg("--ffmpeg-location").arg(location);
-            }
-            apply_cookies_args(
-                &mut comment_command,
-                cookies_source.as_deref(),
-                cookies_file.as_deref(),
-                cookies_browser.as_deref(),
-            );
-            if let Some(remote) = &remote_components {
-                if !remote.trim().is_empty() {
-                    comment_command.arg("--remote-components").arg(remote);
-                }
+            let mut args = vec!["--no-playlist".to_string(), "--skip-download".to_string()];
+            args.extend(subtitle_args.iter().cloned());
+            let options = RunOptions {
+                timeout: Some(Duration::from_secs(SUBTITLE_DOWNLOAD_TIMEOUT_SECS)),
+                process: Some(state.handle(&key)),
+            };
+            if let Ok(output) = runner.run(base.build(args), &options, &mut |_, _| LineAction::Continue) {
+                cancelled |= output.cancelled;
             }
-            comment_command.arg(&url).stdout(Stdio::piped()).stderr(Stdio::piped());
-
-            // Run comment download with timeout and live detection
-            if let Ok(mut child) = comment_command.spawn() {
-                let _stdout_acc = Arc::new(Mutex::new(String::new()));
-                let live_detected_flag = Arc::new(AtomicBool::new(false));
-
-                // Monitor stdout for live patterns
-                if let Some(stdout) = child.stdout.take() {
-                    let live_flag_clone = live_detected_flag.clone();
-                    std::thread::spawn(move || {
-                        let reader = BufReader::new(stdout);
-                        for line in reader.lines().flatten() {
-                            if line.contains("live/1") 
-                                || line.contains("live_broadcast") 
-                                || line.contains("/live_")
-                                || line.contains("playlist_type/DVR")
-                                || line.starts_with("frame=")
-                                || line.contains("Output #0, mpegts,") {
-                                live_flag_clone.store(true, Ordering::Relaxed);
-                            }
-                        }
-                    });
-                }
-
-                // Monitor stderr for live patterns
-                if let Some(stderr) = child.stderr.take() {
-                    let live_flag_clone = live_detected_flag.clone();
-                    std::thread::spawn(move || {
-                        let reader = BufReader::new(stderr);
-                        for line in reader.lines().flatten() {
-                            if line.contains("live/1") 
-                                || line.contains("live_broadcast") 
-                                || line.contains("/live_")
-                                || line.contains("playlist_type/DVR") {
-                                live_flag_clone.store(true, Ordering::Relaxed);
-                            }
-                        }
-                    });
-                }
-                
-                let start_time = std::time::Instant::now();
-                let comment_timeout = Duration::from_secs(120); // 120 second timeout for comments
-                
-                loop {
-                    // Check if live stream detected
-                    if live_detected_flag.load(Ordering::Relaxed) {
-                        let _ = child.kill();
-                        let _ = child.wait();
-                        live_detected = true;
-                        #[cfg(debug_assertions)]
-                        println!("[metadata:{}] comment step: live detected in stdout/stderr, killing process", id);
-                        break;
-                    }
+        }
 
-                    if start_time.elapsed() > comment_timeout {
-                        let _ = child.kill();
-                        let _ = child.wait();
-                        break;
-                    }
-                    
-                    match child.try_wait() {
-                        Ok(Some(_)) => break,
-                        Ok(None) => std::thread::sleep(Duration::from_millis(100)),
-                        Err(_) => break,
-                    }
-                }
+        // Step 5: Fetch SponsorBlock segments (when enabled in settings)
+        if last_success && !live_detected && !cancelled {
+            if let Err(err) = store_sponsor_segments_blocking(&app, &output_dir, &id) {
+                let _ = write_error_log(&app, "sponsorblock", &id, "", &err);
             }
         }
 
-        if !last_success && !upcoming_detected {
+        if !last_success && !upcoming_detected && !cancelled {
             let _ = write_error_log(&app, "metadata_download", &id, &last_stdout, &last_stderr);
         }
 
@@ -922,6 +1128,30 @@ pub fn start_metadata_download(
 
         // If upcoming live event detected, create metadata with is_upcoming status
         if upcoming_detected {
+            let release_timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .ok()
+                .and_then(|now| parse_upcoming_start(&last_stderr, now.as_secs() as i64));
+            schedule_upcoming_if_enabled(
+                &app,
+                DownloadRequest {
+                    id: id.clone(),
+                    url: base.url.clone(),
+                    output_dir: output_dir.clone(),
+                    cookies_file: base.cookies_file.clone(),
+                    cookies_source: base.cookies_source.clone(),
+                    cookies_browser: base.cookies_browser.clone(),
+                    remote_components: base.remote_components.clone(),
+                    yt_dlp_path: Some(base.yt_dlp.clone()),
+                    ffmpeg_path: base.ffmpeg_location.clone(),
+                    quality: None,
+                    is_live: Some(true),
+                    format_preset: None,
+                    audio_format: None,
+                    resume: None,
+                },
+                release_timestamp,
+            );
             metadata = Some(VideoMetadata {
                 id: Some(id.clone()),
                 title: None,
@@ -931,7 +1161,7 @@ pub fn start_metadata_download(
                 webpage_url: None,
                 duration_sec: None,
                 upload_date: None,
-                release_timestamp: None,
+                release_timestamp,
                 timestamp: None,
                 live_status: Some("is_upcoming".to_string()),
                 is_live: None,
@@ -950,6 +1180,7 @@ pub fn start_metadata_download(
                 language: None,
                 audio_language: None,
                 age_limit: None,
+                chapters: None,
             });
             has_live_chat = None;
         } else if live_detected {
@@ -981,6 +1212,7 @@ pub fn start_metadata_download(
                 language: None,
                 audio_language: None,
                 age_limit: None,
+                chapters: None,
             });
             has_live_chat = None;
         } else if last_success {
@@ -996,6 +1228,9 @@ pub fn start_metadata_download(
                             "[metadata:{}] parsed metadata: is_live={:?}, live_status={:?}, was_live={:?}",
                             id, meta.is_live, meta.live_status, meta.was_live
                         );
+                        if let Err(err) = record_metadata_revision(&app, &id, &meta) {
+                            let _ = write_error_log(&app, "metadata_history", &id, "", &err);
+                        }
                         metadata = Some(meta);
                     }
                 }
@@ -1021,26 +1256,53 @@ pub fn start_metadata_download(
             );
         }
 
+        // 停止した場合は途中まで取得した内容があっても成功とは扱わない
+        let success = !cancelled
+            && (private_detected || deleted_detected || upcoming_detected || live_detected || last_success);
+        let failure_kind = if upcoming_detected || live_detected || cancelled { None } else { failure_kind };
+        record_job(
+            &app,
+            JournalEntry {
+                kind: JobKind::Metadata,
+                id: id.clone(),
+                started_at_ms,
+                finished_at_ms: now_ms(),
+                success,
+                cancelled,
+                bytes: None,
+                format_id: None,
+                container: None,
+                attempts,
+                failure_kind,
+            },
+        );
         let _ = app.emit(
             "metadata-finished",
             MetadataFinished {
                 id,
-                success: private_detected || deleted_detected || upcoming_detected || live_detected || last_success,
+                success,
                 stdout: last_stdout,
                 stderr: last_stderr,
+                cancelled,
                 metadata,
                 has_live_chat,
                 is_private: private_detected,
                 is_deleted: deleted_detected,
+                failure_kind,
             },
         );
-    });
+    })
+}
 
-    Ok(())
+/// 実行中のメタデータ取得を止める。終了時に cancelled 付きで metadata-finished が送られる。
+#[tauri::command]
+pub fn stop_metadata_download(state: State<JobProcessState>, id: String) -> Result<(), String> {
+    state.stop(&job_process_key(JobKind::Metadata, &id))
 }
 
 #[tauri::command]
 pub fn get_video_metadata(
+    app: AppHandle,
     url: String,
     cookies_file: Option<String>,
     cookies_source: Option<String>,
@@ -1049,6 +1311,7 @@ pub fn get_video_metadata(
     yt_dlp_path: Option<String>,
 ) -> Result<VideoMetadata, String> {
     let yt_dlp = resolve_override(yt_dlp_path).unwrap_or_else(resolve_yt_dlp);
+    let network = network_settings(&app);
     let mut command = Command::new(yt_dlp);
     #[cfg(windows)]
     command.creation_flags(0x08000000); // CREATE_NO_WINDOW
@@ -1068,6 +1331,7 @@ pub fn get_video_metadata(
             command.arg("--remote-components").arg(remote);
         }
     }
+    apply_network_args(&mut command, &network);
     command.arg(&url);
 
     let output = command
@@ -1089,8 +1353,25 @@ pub fn get_video_metadata(
     Ok(parse_video_metadata_value(&value))
 }
 
+/// ライブラリの info.json から形式・サムネイル・ヒートマップ・字幕などの詳細を読み取る。info.json が無い場合は None。
+#[tauri::command]
+pub fn get_extended_video_metadata(id: String, output_dir: String) -> Result<Option<ExtendedVideoMetadata>, String> {
+    // 配信のメタデータはコメントと同じフォルダに保存されることがある
+    let Some(info_path) = find_info_json(&library_metadata_dir(&output_dir), &id)
+        .or_else(|| find_info_json(&library_comments_dir(&output_dir), &id))
+    else {
+        return Ok(None);
+    };
+    let content = fs::read_to_string(&info_path)
+        .map_err(|e| format!("info.jsonの読み込みに失敗しました: {}", e))?;
+    let value: serde_json::Value = serde_json::from_str(&content)
+        .map_err(|e| format!("info.jsonの解析に失敗しました: {}", e))?;
+    Ok(Some(parse_extended_metadata_value(&value)))
+}
+
 #[tauri::command]
 pub fn get_channel_metadata(
+    app: AppHandle,
     url: String,
     cookies_file: Option<String>,
     cookies_source: Option<String>,
@@ -1100,6 +1381,7 @@ pub fn get_channel_metadata(
     limit: Option<u32>,
 ) -> Result<Vec<VideoMetadata>, String> {
     let yt_dlp = resolve_override(yt_dlp_path).unwrap_or_else(resolve_yt_dlp);
+    let network = network_settings(&app);
     let mut command = Command::new(yt_dlp);
     #[cfg(windows)]
     command.creation_flags(0x08000000); // CREATE_NO_WINDOW
@@ -1125,6 +1407,7 @@ pub fn get_channel_metadata(
             command.arg("--remote-components").arg(remote);
         }
     }
+    apply_network_args(&mut command, &network);
     command.arg(&url);
 
     let output = command
@@ -1272,6 +1555,7 @@ pub fn get_channel_metadata(
                 .and_then(|v| v.as_str())
                 .map(|s| s.to_string()),
             age_limit: entry.get("age_limit").and_then(|v| v.as_u64()),
+            chapters: None,
         });
     }
 
@@ -1280,6 +1564,7 @@ pub fn get_channel_metadata(
 
 #[tauri::command]
 pub fn list_channel_videos(
+    app: AppHandle,
     url: String,
     cookies_file: Option<String>,
     cookies_source: Option<String>,
@@ -1287,35 +1572,109 @@ pub fn list_channel_videos(
     remote_components: Option<String>,
     yt_dlp_path: Option<String>,
     limit: Option<u32>,
+    known_ids: Option<Vec<String>>,
 ) -> Result<Vec<ChannelVideoItem>, String> {
     let yt_dlp = resolve_override(yt_dlp_path).unwrap_or_else(resolve_yt_dlp);
+    let network = network_settings(&app);
     let base_url = normalize_channel_base_url(&url);
     let section_urls = build_channel_section_urls(&base_url);
-
-    let mut merged: Vec<ChannelVideoItem> = Vec::new();
-    let mut seen: std::collections::HashSet<String> = std::collections::HashSet::new();
-
-    for section_url in section_urls {
-        let mut items = match fetch_channel_section(
+    let known_ids: Option<HashSet<String>> = known_ids.map(|ids| ids.into_iter().collect());
+    let state = app.state::<JobProcessState>();
+    // タブごとに yt-dlp を起動するが、停止はチャンネル単位で受け付ける
+    let key = channel_listing_key(&base_url);
+    let _active = state.begin(&key);
+
+    let sections = section_urls.iter().map(|section_url| {
+        fetch_channel_section(
             &yt_dlp,
-            &section_url,
+            section_url,
             cookies_file.as_ref(),
             cookies_source.as_deref(),
             cookies_browser.as_deref(),
             remote_components.as_ref(),
             limit,
-        ) {
+            &network,
+            known_ids.as_ref(),
+            state.handle(&key),
+        )
+    });
+    merge_channel_sections(sections, known_ids.is_some())
+}
+
+fn channel_listing_key(base_url: &str) -> String {
+    job_process_key(JobKind::Metadata, base_url)
+}
+
+/// タブごとの取得結果を重複を除いてまとめる。
+/// 差分取得 (incremental) では取りこぼしを既知扱いにしないよう、1つでも失敗したらその時点でエラーにする。
+/// 全件取得では存在しないタブの失敗を許し、すべてのタブが失敗した場合だけエラーにする。
+pub(crate) fn merge_channel_sections(
+    sections: impl IntoIterator<Item = Result<Vec<ChannelVideoItem>, String>>,
+    incremental: bool,
+) -> Result<Vec<ChannelVideoItem>, String> {
+    let mut merged: Vec<ChannelVideoItem> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut first_error: Option<String> = None;
+    let mut any_succeeded = false;
+
+    for section in sections {
+        let items = match section {
             Ok(items) => items,
-            Err(_) => Vec::new(),
+            Err(err) if incremental => return Err(err),
+            Err(err) => {
+                first_error.get_or_insert(err);
+                continue;
+            }
         };
-        for item in items.drain(..) {
+        any_succeeded = true;
+        for item in items {
             if seen.insert(item.id.clone()) {
                 merged.push(item);
             }
         }
     }
 
-    Ok(merged)
+    match first_error {
+        Some(err) if !any_succeeded => Err(err),
+        _ => Ok(merged),
+    }
+}
+
+/// 実行中のチャンネル動画一覧の取得 (list_channel_videos) を止める
+#[tauri::command]
+pub fn stop_channel_videos_listing(state: State<JobProcessState>, url: String) -> Result<(), String> {
+    state.stop(&channel_listing_key(&normalize_channel_base_url(&url)))
+}
+
+#[tauri::command]
+pub fn list_playlist_videos(
+    app: AppHandle,
+    url: String,
+    cookies_file: Option<String>,
+    cookies_source: Option<String>,
+    cookies_browser: Option<String>,
+    remote_components: Option<String>,
+    yt_dlp_path: Option<String>,
+    limit: Option<u32>,
+) -> Result<PlaylistVideos, String> {
+    let playlist_id = extract_playlist_id(&url)
+        .ok_or_else(|| "再生リストのURLではありません（list= が見つかりません）。".to_string())?;
+    let yt_dlp = resolve_override(yt_dlp_path).unwrap_or_else(resolve_yt_dlp);
+    let network = network_settings(&app);
+    // watch?v=...&list=... の形式でも再生リスト全体を取得する
+    let playlist_url = format!("https://www.youtube.com/playlist?list={}", playlist_id);
+    let value = dump_flat_playlist(
+        &yt_dlp,
+        &playlist_url,
+        cookies_file.as_ref(),
+        cookies_source.as_deref(),
+        cookies_browser.as_deref(),
+        remote_components.as_ref(),
+        limit,
+        &network,
+    )?
+    .ok_or_else(|| "再生リストが取得できませんでした。".to_string())?;
+    parse_playlist_videos(&value, &playlist_id)
 }
 
 #[cfg(test)]
@@ -1370,6 +1729,46 @@ mod tests {
         assert_eq!(meta.language, Some("ja".to_string()));
     }
 
+    #[test]
+    fn parse_chapters_with_end_times() {
+        let value = json!({
+            "id": "ch1",
+            "duration": 600,
+            "chapters": [
+                { "start_time": 0.0, "end_time": 120.5, "title": "Intro" },
+                { "start_time": 120.5, "end_time": 600.0, "title": "Song 1" }
+            ]
+        });
+        let meta = parse_video_metadata_value(&value);
+        let chapters = meta.chapters.unwrap();
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[1].start_time, 120.5);
+        assert_eq!(chapters[1].end_time, 600.0);
+        assert_eq!(chapters[1].title, "Song 1");
+    }
+
+    #[test]
+    fn parse_chapters_fills_missing_end_time() {
+        let value = json!({
+            "duration": 300,
+            "chapters": [
+                { "start_time": 0, "title": "A" },
+                { "start_time": 100 }
+            ]
+        });
+        let chapters = parse_chapters(&value).unwrap();
+        assert_eq!(chapters[0].end_time, 100.0);
+        assert_eq!(chapters[1].end_time, 300.0);
+        assert_eq!(chapters[1].title, "");
+    }
+
+    #[test]
+    fn parse_chapters_absent_or_empty() {
+        assert!(parse_chapters(&json!({ "chapters": null })).is_none());
+        assert!(parse_chapters(&json!({ "chapters": [] })).is_none());
+        assert!(parse_video_metadata_value(&json!({ "id": "x" })).chapters.is_none());
+    }
+
     #[test]
     fn parse_private_video() {
         let value = json!({
@@ -1511,6 +1910,57 @@ mod tests {
         assert_eq!(urls[0], "https://youtube.com/@user/videos");
     }
 
+    // =========================================================
+    // playlist ingestion
+    // =========================================================
+
+    #[test]
+    fn extract_playlist_id_from_urls() {
+        assert_eq!(
+            extract_playlist_id("https://www.youtube.com/playlist?list=PLabc123"),
+            Some("PLabc123".to_string())
+        );
+        assert_eq!(
+            extract_playlist_id("https://www.youtube.com/watch?v=xyz&list=PLabc123&index=3"),
+            Some("PLabc123".to_string())
+        );
+        assert_eq!(extract_playlist_id("https://www.youtube.com/@user/playlists"), None);
+        assert_eq!(extract_playlist_id("https://www.youtube.com/playlist?list="), None);
+        assert_eq!(extract_playlist_id("not a url"), None);
+    }
+
+    #[test]
+    fn parse_playlist_with_placeholders() {
+        let value = json!({
+            "id": "PLabc123",
+            "title": "歌枠まとめ",
+            "channel": "FanChannel",
+            "channel_id": "UCfan",
+            "entries": [
+                { "id": "v1", "title": "Song A", "channel": "Singer", "duration": 300 },
+                { "id": "v2", "title": "[Private video]", "url": "https://www.youtube.com/watch?v=v2" },
+                { "id": "v3", "title": "[Deleted video]" },
+                { "id": "v4", "title": "Song B", "availability": "needs_auth" }
+            ]
+        });
+        let playlist = parse_playlist_videos(&value, "fallback").unwrap();
+        assert_eq!(playlist.playlist_id, "PLabc123");
+        assert_eq!(playlist.playlist_title.as_deref(), Some("歌枠まとめ"));
+        assert_eq!(playlist.channel.as_deref(), Some("FanChannel"));
+        assert_eq!(playlist.items.len(), 4);
+        assert_eq!(playlist.items[0].availability, None);
+        assert_eq!(playlist.items[1].availability.as_deref(), Some("private"));
+        assert_eq!(playlist.items[1].url, "https://www.youtube.com/watch?v=v2");
+        assert_eq!(playlist.items[2].availability.as_deref(), Some("unavailable"));
+        assert_eq!(playlist.items[2].url, "https://www.youtube.com/watch?v=v3");
+        assert_eq!(playlist.items[3].availability.as_deref(), Some("needs_auth"));
+    }
+
+    #[test]
+    fn parse_playlist_without_entries() {
+        assert!(parse_playlist_videos(&json!({ "id": "PL" }), "PL").is_err());
+    }
+
     // =========================================================
     // D-2. is_private detection (inline logic test)
     // =========================================================
@@ -1572,4 +2022,337 @@ mod tests {
         let is_deleted = combined.contains("has been removed") || combined.contains("account associated with this video has been terminated");
         assert!(!is_deleted);
     }
+
+    // =========================================================
+    // InfoJsonJob (FakeRunner)
+    // =========================================================
+
+    use crate::runner::fake::{FakeRun, FakeRunner};
+
+    const INFO_UPCOMING: &str = "\
+[youtube] Extracting URL: https://www.youtube.com/watch?v=abc
+ERROR: [youtube] abc: This live event will begin in 3 hours.";
+    const INFO_LIVE: &str = "\
+[youtube] abc: Downloading m3u8 information
+[hlsnative] Downloading m3u8 manifest
+[download] https://manifest.googlevideo.com/api/manifest/hls_playlist/live/1/playlist_type/DVR/index.m3u8
+[download] should not be read";
+    const INFO_PRIVATE: &str =
+        "ERROR: [youtube] abc: Private video. Sign in if you've been granted access to this video";
+    const INFO_OK: &str = "\
+[youtube] Extracting URL: https://www.youtube.com/watch?v=abc
+[info] Writing video metadata as JSON to: title [abc].info.json";
+
+    fn base_command() -> MetadataCommand {
+        MetadataCommand {
+            yt_dlp: "yt-dlp".to_string(),
+            url: "https://www.youtube.com/watch?v=abc".to_string(),
+            output_path: "out.%(ext)s".to_string(),
+            ffmpeg_location: None,
+            cookies_source: None,
+            cookies_file: None,
+            cookies_browser: None,
+            remote_components: None,
+            network: NetworkSettings::default(),
+        }
+    }
+
+    fn test_policy() -> RetryPolicy {
+        RetryPolicy {
+            jitter_ratio: 0.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn info_json_job_detects_upcoming() {
+        let runner = FakeRunner::new(vec![FakeRun::failed(INFO_UPCOMING)]);
+        let base = base_command();
+        let mut lines = Vec::new();
+        let mut emit = |line: &str| lines.push(line.to_string());
+        let mut job = InfoJsonJob::new("abc", "/nonexistent/lib", &base, test_policy(), &mut emit);
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert_eq!(run.attempts, 1);
+        assert!(job.upcoming_detected);
+        assert!(!job.live_detected);
+        assert_eq!(lines.last().map(String::as_str), Some("配信予定の動画を検出しました。メタデータ取得をスキップします。"));
+    }
+
+    #[test]
+    fn info_json_job_stops_on_live_stream() {
+        let runner = FakeRunner::new(vec![FakeRun::ok(INFO_LIVE)]);
+        let base = base_command();
+        let mut emit = |_: &str| {};
+        let mut job = InfoJsonJob::new("abc", "/nonexistent/lib", &base, test_policy(), &mut emit);
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert!(run.output.stopped);
+        assert!(!run.output.stdout.contains("should not be read"));
+        assert!(job.live_detected);
+        assert_eq!(run.attempts, 1);
+    }
+
+    #[test]
+    fn info_json_job_private_is_not_retried() {
+        let runner = FakeRunner::new(vec![FakeRun::failed(INFO_PRIVATE)]);
+        let base = base_command();
+        let mut emit = |_: &str| {};
+        let mut job = InfoJsonJob::new("abc", "/nonexistent/lib", &base, test_policy(), &mut emit);
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert_eq!(run.attempts, 1);
+        assert!(job.private_detected);
+        assert_eq!(job.failure_kind, Some(FailureKind::Private));
+        assert!(runner.sleeps.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn info_json_job_cancelled_is_not_classified() {
+        let runner = FakeRunner::new(vec![FakeRun {
+            stdout: "[youtube] Extracting URL: https://www.youtube.com/watch?v=abc".into(),
+            cancelled: true,
+            ..Default::default()
+        }]);
+        let base = base_command();
+        let mut emit = |_: &str| {};
+        let mut job = InfoJsonJob::new("abc", "/nonexistent/lib", &base, test_policy(), &mut emit);
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert!(run.output.cancelled);
+        assert_eq!(run.attempts, 1);
+        assert_eq!(job.failure_kind, None);
+    }
+
+    #[test]
+    fn info_json_job_reads_written_info_json() {
+        let output_dir = std::env::temp_dir().join("ylv_test_info_json_job");
+        let _ = fs::remove_dir_all(&output_dir);
+        let output_dir = output_dir.to_string_lossy().to_string();
+        let metadata_dir = library_metadata_dir(&output_dir).join("ch");
+        fs::create_dir_all(&metadata_dir).unwrap();
+        fs::write(
+            metadata_dir.join("title [abc].info.json"),
+            r#"{"id":"abc","title":"t","is_live":false,"live_status":"was_live"}"#,
+        )
+        .unwrap();
+
+        let runner = FakeRunner::new(vec![FakeRun::ok(INFO_OK)]);
+        let base = base_command();
+        let mut lines = Vec::new();
+        let mut emit = |line: &str| lines.push(line.to_string());
+        let mut job = InfoJsonJob::new("abc", &output_dir, &base, test_policy(), &mut emit);
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert!(run.output.success);
+        assert!(!job.live_detected);
+        assert!(lines.last().is_some_and(|line| line.starts_with("通常動画を確認")));
+        let args = runner.command_args(0);
+        assert_eq!(&args[..5], ["--no-playlist", "--newline", "--progress", "--skip-download", "--write-info-json"]);
+        let _ = fs::remove_dir_all(&output_dir);
+    }
+
+    // =========================================================
+    // read_flat_playlist_until_known (FakeRunner)
+    // =========================================================
+
+    const FLAT_LINES: &str = concat!(
+        r#"{"id":"new1","title":"New 1","url":"https://www.youtube.com/watch?v=new1","playlist_channel_id":"UCx"}"#, "\n",
+        r#"{"id":"pinned","title":"Pinned","url":"pinned","playlist_channel_id":"UCx"}"#, "\n",
+        r#"{"id":"new2","title":"New 2","url":"new2","playlist_channel_id":"UCx"}"#, "\n",
+        "not json\n",
+        r#"{"id":"k1","title":"K1","url":"k1"}"#, "\n",
+        r#"{"id":"k2","title":"K2","url":"k2"}"#, "\n",
+        r#"{"id":"k3","title":"K3","url":"k3"}"#, "\n",
+        r#"{"id":"k4","title":"K4","url":"k4"}"#, "\n",
+        r#"{"id":"k5","title":"K5","url":"k5"}"#, "\n",
+        r#"{"id":"older","title":"Older","url":"older"}"#, "\n",
+    );
+
+    fn known(ids: &[&str]) -> HashSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn incremental_sync_stops_after_known_streak() {
+        let runner = FakeRunner::new(vec![FakeRun::ok(FLAT_LINES)]);
+        let known_ids = known(&["pinned", "k1", "k2", "k3", "k4", "k5", "older"]);
+        let items = read_flat_playlist_until_known(&runner, Command::new("yt-dlp"), &RunOptions::default(), &known_ids).unwrap();
+        let ids: Vec<&str> = items.iter().map(|item| item.id.as_str()).collect();
+        // 先頭付近の既知1件では止まらず、5件続いたところで打ち切る
+        assert_eq!(ids, vec!["new1", "pinned", "new2", "k1", "k2", "k3", "k4", "k5"]);
+        assert_eq!(items[2].url, "https://www.youtube.com/watch?v=new2");
+    }
+
+    #[test]
+    fn incremental_sync_skips_channel_entry() {
+        let lines = r#"{"id":"UCx","title":"ch","url":"https://www.youtube.com/channel/UCx","playlist_channel_id":"UCx"}"#;
+        let runner = FakeRunner::new(vec![FakeRun::ok(lines)]);
+        let items = read_flat_playlist_until_known(&runner, Command::new("yt-dlp"), &RunOptions::default(), &known(&["x"])).unwrap();
+        assert!(items.is_empty());
+    }
+
+    #[test]
+    fn incremental_sync_reports_failure_without_entries() {
+        let runner = FakeRunner::new(vec![FakeRun::failed("ERROR: This channel does not have a streams tab")]);
+        let result = read_flat_playlist_until_known(&runner, Command::new("yt-dlp"), &RunOptions::default(), &known(&["x"]));
+        assert!(result.is_err_and(|err| err.contains("streams tab")));
+    }
+
+    #[test]
+    fn incremental_sync_fails_before_known_streak() {
+        let runner = FakeRunner::new(vec![FakeRun {
+            stdout: FLAT_LINES.into(),
+            stderr: "ERROR: unable to download API page: HTTP Error 503".into(),
+            ..Default::default()
+        }]);
+        // 既知の動画が続く前に終了した場合は、一部の動画が読めていても失敗にする
+        let result = read_flat_playlist_until_known(&runner, Command::new("yt-dlp"), &RunOptions::default(), &known(&["x"]));
+        assert!(result.is_err_and(|err| err.contains("HTTP Error 503")));
+
+        let runner = FakeRunner::new(vec![FakeRun {
+            stdout: FLAT_LINES.into(),
+            timed_out: true,
+            ..Default::default()
+        }]);
+        let result = read_flat_playlist_until_known(&runner, Command::new("yt-dlp"), &RunOptions::default(), &known(&["x"]));
+        assert!(result.is_err_and(|err| err.contains("タイムアウト")));
+    }
+
+    #[test]
+    fn flat_playlist_command_applies_limit() {
+        let command = flat_playlist_command("yt-dlp", None, None, None, None, Some(30), &NetworkSettings::default());
+        let args: Vec<String> = command.get_args().map(|arg| arg.to_string_lossy().to_string()).collect();
+        assert_eq!(&args[..2], ["--flat-playlist", "--yes-playlist"]);
+        assert!(args.windows(2).any(|pair| pair == ["--playlist-end", "30"]));
+        assert!(!args.iter().any(|arg| arg.starts_with("--dump")));
+    }
+
+    // =========================================================
+    // merge_channel_sections
+    // =========================================================
+
+    fn section(ids: &[&str]) -> Result<Vec<ChannelVideoItem>, String> {
+        Ok(ids
+            .iter()
+            .filter_map(|id| parse_flat_playlist_entry(&json!({ "id": id, "url": id }), None))
+            .collect())
+    }
+
+    fn merged_ids(result: Result<Vec<ChannelVideoItem>, String>) -> Vec<String> {
+        result.unwrap().into_iter().map(|item| item.id).collect()
+    }
+
+    #[test]
+    fn merge_sections_dedupes_in_order() {
+        let result = merge_channel_sections(vec![section(&["a", "b"]), section(&["b", "c"])], false);
+        assert_eq!(merged_ids(result), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn merge_sections_full_listing_tolerates_missing_tab() {
+        let sections = vec![section(&["a"]), Err("ERROR: This channel does not have a streams tab".to_string())];
+        assert_eq!(merged_ids(merge_channel_sections(sections, false)), vec!["a"]);
+
+        // すべてのタブが失敗した場合は空の一覧ではなくエラーを返す
+        let sections = vec![Err("タイムアウト".to_string()), Err("HTTP Error 503".to_string())];
+        assert_eq!(merge_channel_sections(sections, false).err().as_deref(), Some("タイムアウト"));
+    }
+
+    #[test]
+    fn merge_sections_incremental_stops_at_first_error() {
+        let mut fetched = 0;
+        let sections = [section(&["a"]), Err("タイムアウト".to_string()), section(&["b"])]
+            .into_iter()
+            .inspect(|_| fetched += 1);
+        assert_eq!(merge_channel_sections(sections, true).err().as_deref(), Some("タイムアウト"));
+        // 失敗した後のタブは取得しない
+        assert_eq!(fetched, 2);
+    }
+
+    #[test]
+    fn channel_listing_key_matches_stop_command_normalization() {
+        let base = normalize_channel_base_url("https://www.youtube.com/@channel/videos");
+        assert_eq!(channel_listing_key(&base), channel_listing_key(&normalize_channel_base_url(&base)));
+    }
+
+    // =========================================================
+    // parse_extended_metadata_value
+    // =========================================================
+
+    fn extended_fixture() -> serde_json::Value {
+        json!({
+            "id": "abc",
+            "title": "Archive",
+            "release_date": "20240115",
+            "modified_date": "20240301",
+            "formats": [
+                { "format_id": "sb0", "ext": "mhtml", "format_note": "storyboard", "vcodec": "none", "acodec": "none" },
+                { "format_id": "140", "ext": "m4a", "format_note": "medium", "resolution": "audio only",
+                  "vcodec": "none", "acodec": "mp4a.40.2", "tbr": 129.5, "filesize": 1000 },
+                { "format_id": "137", "ext": "mp4", "resolution": "1920x1080", "width": 1920, "height": 1080,
+                  "fps": 29.97, "vcodec": "avc1.640028", "acodec": "none", "filesize_approx": 5000 }
+            ],
+            "thumbnails": [
+                { "id": "0", "url": "https://i.ytimg.com/vi/abc/default.jpg", "width": 120, "height": 90, "preference": -10 },
+                { "id": "1" },
+                { "id": "2", "url": "https://i.ytimg.com/vi/abc/maxresdefault.jpg" }
+            ],
+            "heatmap": [
+                { "start_time": 0.0, "end_time": 10.0, "value": 1.0 },
+                { "start_time": 10.0, "end_time": 20.0, "value": 0.25 },
+                { "start_time": 20.0 }
+            ],
+            "subtitles": {
+                "live_chat": [{ "ext": "json", "url": "https://example.com/chat" }],
+                "ja": [{ "ext": "json3", "name": "Japanese" }, { "ext": "vtt", "name": "Japanese" }, { "ext": "vtt" }],
+                "en": [{ "ext": "vtt", "name": "English" }]
+            },
+            "automatic_captions": {
+                "ja": [{ "ext": "srv3", "name": "Japanese (auto)" }]
+            }
+        })
+    }
+
+    #[test]
+    fn extended_formats_skip_storyboards() {
+        let extended = parse_extended_metadata_value(&extended_fixture());
+        assert_eq!(extended.metadata.id.as_deref(), Some("abc"));
+        assert_eq!(extended.formats.len(), 2);
+        let audio = &extended.formats[0];
+        assert_eq!(audio.resolution.as_deref(), Some("audio only"));
+        assert_eq!(audio.vcodec, None);
+        assert_eq!(audio.acodec.as_deref(), Some("mp4a.40.2"));
+        assert_eq!((audio.filesize, audio.filesize_approx), (Some(1000), false));
+        let video = &extended.formats[1];
+        assert_eq!((video.width, video.height, video.fps), (Some(1920), Some(1080), Some(29.97)));
+        assert_eq!((video.filesize, video.filesize_approx), (Some(5000), true));
+    }
+
+    #[test]
+    fn extended_thumbnails_heatmap_and_dates() {
+        let extended = parse_extended_metadata_value(&extended_fixture());
+        assert_eq!(extended.thumbnails.len(), 2);
+        assert_eq!(extended.thumbnails[0].preference, Some(-10));
+        let heatmap = extended.heatmap.unwrap();
+        assert_eq!(heatmap.len(), 2);
+        assert_eq!(heatmap[1].value, 0.25);
+        assert_eq!(extended.release_date.as_deref(), Some("20240115"));
+        assert_eq!(extended.modified_date.as_deref(), Some("20240301"));
+    }
+
+    #[test]
+    fn extended_subtitle_inventory() {
+        let subtitles = parse_subtitle_languages(&extended_fixture());
+        let langs: Vec<(&str, bool)> = subtitles.iter().map(|sub| (sub.lang.as_str(), sub.automatic)).collect();
+        assert_eq!(langs, vec![("en", false), ("ja", false), ("ja", true)]);
+        assert_eq!(subtitles[1].exts, vec!["json3", "vtt"]);
+        assert_eq!(subtitles[1].name.as_deref(), Some("Japanese"));
+    }
+
+    #[test]
+    fn extended_missing_sections_are_empty() {
+        let extended = parse_extended_metadata_value(&json!({ "id": "x" }));
+        assert!(extended.formats.is_empty());
+        assert!(extended.thumbnails.is_empty());
+        assert!(extended.heatmap.is_none());
+        assert!(extended.subtitles.is_empty());
+        assert!(extended.release_date.is_none());
+    }
 }
+
@@ -0,0 +1,217 @@
+use std::fs;
+use tauri::AppHandle;
+use crate::models::{MetadataHistory, MetadataRevision, VersionedMetadataHistory, VideoMetadata};
+use crate::journal::now_ms;
+use crate::paths::{atomic_write, metadata_history_file_path};
+use crate::{METADATA_HISTORY_FIELDS, METADATA_HISTORY_LIMIT, METADATA_HISTORY_SCHEMA_VERSION};
+
+type FieldMap = serde_json::Map<String, serde_json::Value>;
+
+pub(crate) fn parse_versioned_metadata_history(content: &str) -> MetadataHistory {
+    if let Ok(wrapper) = serde_json::from_str::<VersionedMetadataHistory>(content) {
+        if wrapper.version <= METADATA_HISTORY_SCHEMA_VERSION {
+            return wrapper.data;
+        }
+        return MetadataHistory::default();
+    }
+    serde_json::from_str::<MetadataHistory>(content).unwrap_or_default()
+}
+
+/// VideoMetadata から履歴に残す項目だけを取り出す
+fn tracked_fields(metadata: &VideoMetadata) -> FieldMap {
+    let value = serde_json::to_value(metadata).unwrap_or_default();
+    METADATA_HISTORY_FIELDS
+        .iter()
+        .map(|field| {
+            let key = field_key(field);
+            let current = value.get(&key).cloned().unwrap_or(serde_json::Value::Null);
+            (key, current)
+        })
+        .collect()
+}
+
+/// snake_case の項目名を VideoMetadata のシリアライズ後のキー名にする
+fn field_key(field: &str) -> String {
+    let mut key = String::with_capacity(field.len());
+    let mut upper = false;
+    for ch in field.chars() {
+        if ch == '_' {
+            upper = true;
+        } else if upper {
+            key.extend(ch.to_uppercase());
+            upper = false;
+        } else {
+            key.push(ch);
+        }
+    }
+    key
+}
+
+/// 変更点を古い順に重ねて、最新の状態を組み立てる
+pub(crate) fn latest_fields(history: &MetadataHistory) -> FieldMap {
+    let mut fields = FieldMap::new();
+    for revision in &history.revisions {
+        for (key, value) in &revision.changes {
+            fields.insert(key.clone(), value.clone());
+        }
+    }
+    fields
+}
+
+/// 取得したメタデータを履歴に加える。前回から変わった項目が無ければ何もせず false。
+pub(crate) fn append_revision(history: &mut MetadataHistory, metadata: &VideoMetadata, fetched_at_ms: u64) -> bool {
+    let latest = latest_fields(history);
+    let changes: FieldMap = tracked_fields(metadata)
+        .into_iter()
+        .filter(|(key, value)| {
+            // 取得できなかった項目は、以前に値があった場合だけ「消えた」として残す
+            match latest.get(key) {
+                Some(previous) => previous != value,
+                None => !value.is_null(),
+            }
+        })
+        .collect();
+    if changes.is_empty() {
+        return false;
+    }
+    history.revisions.push(MetadataRevision {
+        fetched_at_ms,
+        changes,
+    });
+    trim_revisions(history, METADATA_HISTORY_LIMIT);
+    true
+}
+
+/// 上限を超えた古い変更点は最初の1件にまとめ、最新の状態を復元できるようにする
+fn trim_revisions(history: &mut MetadataHistory, limit: usize) {
+    while history.revisions.len() > limit.max(2) {
+        let oldest = history.revisions.remove(0);
+        let base = &mut history.revisions[0];
+        for (key, value) in oldest.changes {
+            base.changes.entry(key).or_insert(value);
+        }
+    }
+}
+
+fn read_metadata_history(app: &AppHandle, id: &str) -> Result<MetadataHistory, String> {
+    let path = metadata_history_file_path(app, id)?;
+    let mut history = if path.exists() {
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("メタデータ履歴の読み込みに失敗しました: {}", e))?;
+        parse_versioned_metadata_history(&content)
+    } else {
+        MetadataHistory::default()
+    };
+    history.video_id = id.to_string();
+    Ok(history)
+}
+
+/// メタデータ取得の完了時に呼び、タイトルや概要欄などの変更を記録する
+pub(crate) fn record_metadata_revision(app: &AppHandle, id: &str, metadata: &VideoMetadata) -> Result<(), String> {
+    let mut history = read_metadata_history(app, id)?;
+    if !append_revision(&mut history, metadata, now_ms()) {
+        return Ok(());
+    }
+    let path = metadata_history_file_path(app, id)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("メタデータ履歴フォルダの作成に失敗しました: {}", e))?;
+    }
+    let wrapper = VersionedMetadataHistory {
+        version: METADATA_HISTORY_SCHEMA_VERSION,
+        data: history,
+    };
+    let content = serde_json::to_string_pretty(&wrapper)
+        .map_err(|e| format!("メタデータ履歴の整形に失敗しました: {}", e))?;
+    atomic_write(&path, content.as_bytes())
+}
+
+/// 動画のメタデータ履歴を古い順に返す。各リビジョンは前回からの変更点のみを持つ。
+#[tauri::command]
+pub fn get_metadata_history(app: AppHandle, id: String) -> Result<MetadataHistory, String> {
+    read_metadata_history(&app, &id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::parse_video_metadata_value;
+    use serde_json::json;
+
+    fn metadata(value: serde_json::Value) -> VideoMetadata {
+        parse_video_metadata_value(&value)
+    }
+
+    // =========================================================
+    // append_revision
+    // =========================================================
+
+    #[test]
+    fn first_revision_holds_all_tracked_fields() {
+        let mut history = MetadataHistory::default();
+        let meta = metadata(json!({ "id": "abc", "title": "Stream", "description": "setlist", "view_count": 10 }));
+        assert!(append_revision(&mut history, &meta, 1));
+        let changes = &history.revisions[0].changes;
+        assert_eq!(changes["title"], "Stream");
+        assert_eq!(changes["description"], "setlist");
+        assert!(!changes.contains_key("viewCount"));
+        // 未取得の項目は最初のリビジョンに含めない
+        assert!(!changes.contains_key("chapters"));
+    }
+
+    #[test]
+    fn only_changed_fields_are_recorded() {
+        let mut history = MetadataHistory::default();
+        append_revision(&mut history, &metadata(json!({ "title": "A", "description": "d", "view_count": 1 })), 1);
+        // 再生数だけの変化は記録しない
+        assert!(!append_revision(&mut history, &metadata(json!({ "title": "A", "description": "d", "view_count": 9 })), 2));
+        assert!(append_revision(&mut history, &metadata(json!({ "title": "B", "description": "d" })), 3));
+        assert_eq!(history.revisions.len(), 2);
+        let latest = &history.revisions[1];
+        assert_eq!(latest.fetched_at_ms, 3);
+        assert_eq!(latest.changes.len(), 1);
+        assert_eq!(latest.changes["title"], "B");
+    }
+
+    #[test]
+    fn removed_field_is_recorded_as_null() {
+        let mut history = MetadataHistory::default();
+        append_revision(&mut history, &metadata(json!({ "title": "A", "description": "d" })), 1);
+        assert!(append_revision(&mut history, &metadata(json!({ "title": "A" })), 2));
+        assert!(history.revisions[1].changes["description"].is_null());
+        assert!(latest_fields(&history)["description"].is_null());
+    }
+
+    #[test]
+    fn trim_folds_oldest_into_base() {
+        let mut history = MetadataHistory::default();
+        append_revision(&mut history, &metadata(json!({ "title": "t0", "description": "d" })), 0);
+        for i in 1..=METADATA_HISTORY_LIMIT as u64 + 5 {
+            append_revision(&mut history, &metadata(json!({ "title": format!("t{}", i), "description": "d" })), i);
+        }
+        assert_eq!(history.revisions.len(), METADATA_HISTORY_LIMIT);
+        // まとめた後も最初のリビジョンから最新の状態を組み立てられる
+        assert_eq!(history.revisions[0].changes["description"], "d");
+        assert_eq!(latest_fields(&history)["title"], format!("t{}", METADATA_HISTORY_LIMIT + 5));
+    }
+
+    #[test]
+    fn field_key_matches_serialized_name() {
+        assert_eq!(field_key("title"), "title");
+        assert_eq!(field_key("webpage_url"), "webpageUrl");
+    }
+
+    // =========================================================
+    // parse_versioned_metadata_history
+    // =========================================================
+
+    #[test]
+    fn parse_history_versioned_and_invalid() {
+        let content = r#"{"version":1,"data":{"videoId":"abc","revisions":[{"fetchedAtMs":5,"changes":{"title":"A"}}]}}"#;
+        let history = parse_versioned_metadata_history(content);
+        assert_eq!(history.video_id, "abc");
+        assert_eq!(history.revisions[0].changes["title"], "A");
+        assert!(parse_versioned_metadata_history(r#"{"version":99,"data":{"videoId":"abc","revisions":[]}}"#).video_id.is_empty());
+        assert!(parse_versioned_metadata_history("not json").revisions.is_empty());
+    }
+}
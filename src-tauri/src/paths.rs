@@ -1,7 +1,7 @@
 use std::{fs, io::Write, path::{Path, PathBuf}};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tauri::{AppHandle, Manager};
-use crate::{SETTINGS_DIR_NAME, SETTINGS_FILE_NAME, INDEX_DIR_NAME, VIDEOS_FILE_NAME,
+use crate::{SETTINGS_DIR_NAME, SETTINGS_FILE_NAME, INDEX_DIR_NAME, VIDEOS_FILE_NAME, QUEUE_FILE_NAME, SCHEDULE_FILE_NAME, SUBSCRIPTIONS_FILE_NAME, AVAILABILITY_FILE_NAME, METADATA_HISTORY_DIR_NAME, JOURNAL_FILE_NAME,
             LIBRARY_VIDEOS_DIR_NAME, LIBRARY_COMMENTS_DIR_NAME, LIBRARY_METADATA_DIR_NAME, LIBRARY_THUMBNAILS_DIR_NAME};
 
 pub(crate) fn resolve_library_root_dir(output_dir: &str) -> PathBuf {
@@ -115,13 +115,14 @@ pub(crate) fn collect_files_recursive(dir: &Path) -> Vec<PathBuf> {
     out
 }
 
-pub(crate) fn settings_file_path(app: &AppHandle) -> Result<PathBuf, String> {
+/// 設定・インデックス類を保存するベースディレクトリ（開発モードでは -dev 付き）
+pub(crate) fn app_config_base_dir(app: &AppHandle) -> Result<PathBuf, String> {
     #[allow(unused_mut)]
     let mut dir = app
         .path()
         .app_config_dir()
         .map_err(|e| format!("保存先ディレクトリの取得に失敗しました: {}", e))?;
-    
+
     // 開発モードでは -dev サフィックスを付与してディレクトリを分離
     #[cfg(debug_assertions)]
     {
@@ -132,29 +133,43 @@ pub(crate) fn settings_file_path(app: &AppHandle) -> Result<PathBuf, String> {
         let parent = dir.parent().unwrap_or(dir.as_path());
         dir = parent.join(format!("{}-dev", dir_name));
     }
-    
-    Ok(dir.join(SETTINGS_DIR_NAME).join(SETTINGS_FILE_NAME))
+
+    Ok(dir)
+}
+
+pub(crate) fn settings_file_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app_config_base_dir(app)?.join(SETTINGS_DIR_NAME).join(SETTINGS_FILE_NAME))
 }
 
 pub(crate) fn videos_file_path(app: &AppHandle) -> Result<PathBuf, String> {
-    #[allow(unused_mut)]
-    let mut dir = app
-        .path()
-        .app_config_dir()
-        .map_err(|e| format!("保存先ディレクトリの取得に失敗しました: {}", e))?;
-    
-    // 開発モードでは -dev サフィックスを付与してディレクトリを分離
-    #[cfg(debug_assertions)]
-    {
-        let dir_name = dir
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("config");
-        let parent = dir.parent().unwrap_or(dir.as_path());
-        dir = parent.join(format!("{}-dev", dir_name));
-    }
-    
-    Ok(dir.join(INDEX_DIR_NAME).join(VIDEOS_FILE_NAME))
+    Ok(app_config_base_dir(app)?.join(INDEX_DIR_NAME).join(VIDEOS_FILE_NAME))
+}
+
+pub(crate) fn queue_file_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app_config_base_dir(app)?.join(INDEX_DIR_NAME).join(QUEUE_FILE_NAME))
+}
+
+pub(crate) fn schedule_file_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app_config_base_dir(app)?.join(INDEX_DIR_NAME).join(SCHEDULE_FILE_NAME))
+}
+
+pub(crate) fn subscriptions_file_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app_config_base_dir(app)?.join(INDEX_DIR_NAME).join(SUBSCRIPTIONS_FILE_NAME))
+}
+
+pub(crate) fn availability_file_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app_config_base_dir(app)?.join(INDEX_DIR_NAME).join(AVAILABILITY_FILE_NAME))
+}
+
+pub(crate) fn metadata_history_file_path(app: &AppHandle, id: &str) -> Result<PathBuf, String> {
+    Ok(app_config_base_dir(app)?
+        .join(INDEX_DIR_NAME)
+        .join(METADATA_HISTORY_DIR_NAME)
+        .join(format!("{}.json", sanitize_filename_component(id))))
+}
+
+pub(crate) fn journal_file_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app_config_base_dir(app)?.join(INDEX_DIR_NAME).join(JOURNAL_FILE_NAME))
 }
 
 pub(crate) fn write_error_log(
@@ -0,0 +1,783 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager, State};
+use crate::models::{
+    DownloadFinished, DownloadProcessState, FailureKind, DownloadQueueSnapshot, DownloadQueueState, DownloadRequest,
+    DownloadStartError, PersistedQueue, QueueItemStatus, QueuedDownload, VersionedQueue,
+};
+use crate::paths::{atomic_write, queue_file_path};
+use crate::state::{read_settings, write_settings};
+use crate::audio::resolve_audio_format;
+use crate::download::spawn_download;
+use crate::scheduler::on_recording_finished;
+use crate::{QUEUE_SCHEMA_VERSION, DEFAULT_MAX_CONCURRENT_DOWNLOADS, MAX_CONCURRENT_DOWNLOADS_LIMIT, VERIFY_RETRY_MAX};
+
+pub(crate) fn parse_versioned_queue(content: &str) -> PersistedQueue {
+    if let Ok(wrapper) = serde_json::from_str::<VersionedQueue>(content) {
+        if wrapper.version <= QUEUE_SCHEMA_VERSION {
+            return wrapper.data;
+        }
+        return PersistedQueue::default();
+    }
+    serde_json::from_str::<PersistedQueue>(content).unwrap_or_default()
+}
+
+pub(crate) fn effective_max_concurrent(value: Option<u32>) -> u32 {
+    value
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_DOWNLOADS)
+        .clamp(1, MAX_CONCURRENT_DOWNLOADS_LIMIT)
+}
+
+/// 同じIDがあれば要求内容と優先度を更新し、なければ末尾に追加する。
+/// 実行中のジョブは更新できない。
+pub(crate) fn upsert_queue_item(
+    items: &mut Vec<QueuedDownload>,
+    request: DownloadRequest,
+    priority: i32,
+    now_ms: u64,
+) -> Result<(), String> {
+    if let Some(existing) = items.iter_mut().find(|item| item.request.id == request.id) {
+        if existing.status == QueueItemStatus::Running {
+            return Err("このダウンロードは既に実行中です。".to_string());
+        }
+        existing.request = request;
+        existing.priority = priority;
+        return Ok(());
+    }
+    items.push(QueuedDownload {
+        request,
+        priority,
+        status: QueueItemStatus::Queued,
+        enqueued_at_ms: now_ms,
+    });
+    Ok(())
+}
+
+pub(crate) fn remove_queued_item(items: &mut Vec<QueuedDownload>, id: &str) -> Result<(), String> {
+    let Some(index) = items.iter().position(|item| item.request.id == id) else {
+        return Err("キューに対象のダウンロードが見つかりませんでした。".to_string());
+    };
+    if items[index].status == QueueItemStatus::Running {
+        return Err("実行中のダウンロードはキューから外せません。停止してください。".to_string());
+    }
+    items.remove(index);
+    Ok(())
+}
+
+/// 一時停止したジョブを再開用の要求とともにキューに残す。キュー外で開始したジョブも追加する。
+pub(crate) fn mark_queue_item_paused(items: &mut Vec<QueuedDownload>, request: DownloadRequest, now_ms: u64) {
+    if let Some(existing) = items.iter_mut().find(|item| item.request.id == request.id) {
+        existing.request = request;
+        existing.status = QueueItemStatus::Paused;
+        return;
+    }
+    items.push(QueuedDownload {
+        request,
+        priority: 0,
+        status: QueueItemStatus::Paused,
+        enqueued_at_ms: now_ms,
+    });
+}
+
+/// 再開するジョブは実行中として枠を使い、破棄するジョブはキューから外す
+pub(crate) fn release_paused_item(items: &mut Vec<QueuedDownload>, id: &str, resumed: bool) -> bool {
+    let Some(index) = items
+        .iter()
+        .position(|item| item.request.id == id && item.status == QueueItemStatus::Paused)
+    else {
+        return false;
+    };
+    if resumed {
+        items[index].status = QueueItemStatus::Running;
+    } else {
+        items.remove(index);
+    }
+    true
+}
+
+/// 指定されたIDの順に並べ替える。指定されなかった項目は元の順序のまま後ろに続く。
+pub(crate) fn reorder_queue_items(items: &mut Vec<QueuedDownload>, ids: &[String]) {
+    let mut ordered: Vec<QueuedDownload> = Vec::with_capacity(items.len());
+    for id in ids {
+        if let Some(index) = items.iter().position(|item| &item.request.id == id) {
+            ordered.push(items.remove(index));
+        }
+    }
+    ordered.append(items);
+    *items = ordered;
+}
+
+pub(crate) fn set_queue_item_priority(
+    items: &mut [QueuedDownload],
+    id: &str,
+    priority: i32,
+) -> Result<(), String> {
+    let item = items
+        .iter_mut()
+        .find(|item| item.request.id == id)
+        .ok_or_else(|| "キューに対象のダウンロードが見つかりませんでした。".to_string())?;
+    item.priority = priority;
+    Ok(())
+}
+
+/// 空いている枠の数だけ待機中のジョブを実行中にして返す。
+/// 優先度の高い順、同じ優先度ならキュー内の順序で選ぶ。
+pub(crate) fn take_runnable(items: &mut [QueuedDownload], max_concurrent: u32) -> Vec<DownloadRequest> {
+    let running = items
+        .iter()
+        .filter(|item| item.status == QueueItemStatus::Running)
+        .count();
+    let mut free = (max_concurrent as usize).saturating_sub(running);
+    let mut started = Vec::new();
+    while free > 0 {
+        let next = items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.status == QueueItemStatus::Queued)
+            .max_by(|(a_idx, a), (b_idx, b)| a.priority.cmp(&b.priority).then(b_idx.cmp(a_idx)))
+            .map(|(index, _)| index);
+        let Some(index) = next else {
+            break;
+        };
+        items[index].status = QueueItemStatus::Running;
+        started.push(items[index].request.clone());
+        free -= 1;
+    }
+    started
+}
+
+/// 直接開始の要求をキューに登録する。空き枠があれば実行中にして true、なければ待機中にして false を返す。
+/// 一時停止中の同じジョブは最初からやり直す。実行中のジョブは登録できない。
+pub(crate) fn claim_queue_slot(
+    items: &mut Vec<QueuedDownload>,
+    request: DownloadRequest,
+    max_concurrent: u32,
+    now_ms: u64,
+) -> Result<bool, String> {
+    let id = request.id.clone();
+    upsert_queue_item(items, request, 0, now_ms)?;
+    let running = items
+        .iter()
+        .filter(|item| item.status == QueueItemStatus::Running)
+        .count();
+    let start_now = running < max_concurrent as usize;
+    if let Some(item) = items.iter_mut().find(|item| item.request.id == id) {
+        item.status = if start_now {
+            QueueItemStatus::Running
+        } else {
+            QueueItemStatus::Queued
+        };
+    }
+    Ok(start_now)
+}
+
+/// 保存されていたキューを起動時の状態に戻す。実行中だったジョブは待機中にする。
+pub(crate) fn restore_queue_items(items: Vec<QueuedDownload>) -> Vec<QueuedDownload> {
+    items
+        .into_iter()
+        .map(|mut item| {
+            if item.status == QueueItemStatus::Running {
+                item.status = QueueItemStatus::Queued;
+            }
+            item
+        })
+        .collect()
+}
+
+/// 検証失敗時の再試行回数を数え、上限内なら再キューしてよいかを返す。
+/// 検証に成功した（または検証しなかった）場合は回数をリセットする。
+pub(crate) fn record_verify_result(retries: &mut HashMap<String, u32>, id: &str, verified: Option<bool>) -> bool {
+    if verified != Some(false) {
+        retries.remove(id);
+        return false;
+    }
+    let count = retries.entry(id.to_string()).or_insert(0);
+    if *count >= VERIFY_RETRY_MAX {
+        retries.remove(id);
+        return false;
+    }
+    *count += 1;
+    true
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn persist_queue(app: &AppHandle, items: &[QueuedDownload]) {
+    let Ok(path) = queue_file_path(app) else {
+        return;
+    };
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let wrapper = VersionedQueue {
+        version: QUEUE_SCHEMA_VERSION,
+        data: PersistedQueue {
+            items: items.to_vec(),
+        },
+    };
+    if let Ok(content) = serde_json::to_string_pretty(&wrapper) {
+        let _ = atomic_write(&path, content.as_bytes());
+    }
+}
+
+fn snapshot(state: &DownloadQueueState) -> DownloadQueueSnapshot {
+    let max_concurrent = effective_max_concurrent(state.max_concurrent.lock().map(|v| *v).unwrap_or(None));
+    let items = state.items.lock().map(|items| items.clone()).unwrap_or_default();
+    DownloadQueueSnapshot { max_concurrent, items }
+}
+
+/// キューを保存して変更イベントを通知する
+fn commit_queue(app: &AppHandle, state: &DownloadQueueState) -> DownloadQueueSnapshot {
+    let snapshot = snapshot(state);
+    persist_queue(app, &snapshot.items);
+    let _ = app.emit("download-queue-updated", snapshot.clone());
+    snapshot
+}
+
+/// 空き枠があれば待機中のジョブを起動する
+pub(crate) fn pump_download_queue(app: &AppHandle) {
+    let queue_state = app.state::<DownloadQueueState>();
+    let max_concurrent = effective_max_concurrent(queue_state.max_concurrent.lock().map(|v| *v).unwrap_or(None));
+    let started = match queue_state.items.lock() {
+        Ok(mut items) => take_runnable(&mut items, max_concurrent),
+        Err(_) => return,
+    };
+    if started.is_empty() {
+        return;
+    }
+    commit_queue(app, &queue_state);
+
+    let process_state = app.state::<DownloadProcessState>().inner().clone();
+    for request in started {
+        let id = request.id.clone();
+        if let Err(err) = spawn_download(app.clone(), process_state.clone(), request) {
+            let _ = app.emit(
+                "download-finished",
+                DownloadFinished {
+                    id: id.clone(),
+                    success: false,
+                    stdout: "".to_string(),
+                    stderr: err.to_string(),
+                    cancelled: false,
+                    is_private: false,
+                    is_deleted: false,
+                    paused: false,
+                    verified: None,
+                    failure_kind: Some(err.failure_kind()),
+                },
+            );
+            on_recording_finished(app, &id, false);
+            on_download_finished(app, &id);
+        }
+    }
+}
+
+/// キューにある（待機中・実行中・一時停止中の）ダウンロードのID
+pub(crate) fn queued_download_ids(app: &AppHandle) -> HashSet<String> {
+    app.state::<DownloadQueueState>()
+        .items
+        .lock()
+        .map(|items| items.iter().map(|item| item.request.id.clone()).collect())
+        .unwrap_or_default()
+}
+
+/// バックエンドから要求を待機中に追加し、空き枠があれば起動する
+pub(crate) fn queue_download(app: &AppHandle, request: DownloadRequest, priority: i32) -> Result<(), String> {
+    let queue_state = app.state::<DownloadQueueState>();
+    {
+        let mut items = queue_state
+            .items
+            .lock()
+            .map_err(|e| format!("キューの更新に失敗しました: {}", e))?;
+        upsert_queue_item(&mut items, request, priority, now_ms())?;
+    }
+    commit_queue(app, &queue_state);
+    pump_download_queue(app);
+    Ok(())
+}
+
+/// 空き枠があればすぐに開始し、なければ待機中に追加して空きしだい開始する。
+/// すぐに開始する場合は、空き容量不足など開始前のエラーをそのまま返す。
+pub(crate) fn start_or_queue_download(app: &AppHandle, request: DownloadRequest) -> Result<(), DownloadStartError> {
+    let queue_state = app.state::<DownloadQueueState>();
+    let process_state = app.state::<DownloadProcessState>().inner().clone();
+    let max_concurrent = effective_max_concurrent(queue_state.max_concurrent.lock().map(|v| *v).unwrap_or(None));
+    let id = request.id.clone();
+    let start_now = {
+        let mut items = queue_state
+            .items
+            .lock()
+            .map_err(|e| format!("キューの更新に失敗しました: {}", e))?;
+        claim_queue_slot(&mut items, request.clone(), max_concurrent, now_ms())?
+    };
+    // 新しい要求で最初から取り直すため、一時停止中の再開情報は破棄する
+    if let Ok(mut paused) = process_state.paused.lock() {
+        paused.remove(&id);
+    }
+    commit_queue(app, &queue_state);
+    if !start_now {
+        return Ok(());
+    }
+    spawn_download(app.clone(), process_state, request).inspect_err(|_| {
+        if let Ok(mut items) = queue_state.items.lock() {
+            items.retain(|item| item.request.id != id);
+        }
+        commit_queue(app, &queue_state);
+    })
+}
+
+/// ダウンロードスレッドの終了時に呼ばれる。キューから外して次のジョブを起動する。
+/// 一時停止した場合は再開用の要求とともにキューに残す。
+pub(crate) fn on_download_finished(app: &AppHandle, id: &str) {
+    let queue_state = app.state::<DownloadQueueState>();
+    let paused = app
+        .state::<DownloadProcessState>()
+        .paused
+        .lock()
+        .ok()
+        .and_then(|map| map.get(id).cloned());
+    let changed = match queue_state.items.lock() {
+        Ok(mut items) => match paused {
+            Some(request) => {
+                mark_queue_item_paused(&mut items, request, now_ms());
+                true
+            }
+            None => {
+                let before = items.len();
+                items.retain(|item| !(item.request.id == id && item.status == QueueItemStatus::Running));
+                before != items.len()
+            }
+        },
+        Err(_) => false,
+    };
+    if changed {
+        commit_queue(app, &queue_state);
+    }
+    pump_download_queue(app);
+}
+
+/// 一時停止中のジョブを再開したときは実行中に、破棄したときはキューから外す
+pub(crate) fn release_paused_download(app: &AppHandle, id: &str, resumed: bool) {
+    let queue_state = app.state::<DownloadQueueState>();
+    let changed = match queue_state.items.lock() {
+        Ok(mut items) => release_paused_item(&mut items, id, resumed),
+        Err(_) => false,
+    };
+    if changed {
+        commit_queue(app, &queue_state);
+    }
+}
+
+/// 検証に失敗したダウンロードを上限回数まで自動で再キューする
+pub(crate) fn on_download_verified(app: &AppHandle, request: DownloadRequest, verified: Option<bool>) {
+    let queue_state = app.state::<DownloadQueueState>();
+    let requeue = match queue_state.verify_retries.lock() {
+        Ok(mut retries) => record_verify_result(&mut retries, &request.id, verified),
+        Err(_) => false,
+    };
+    if !requeue {
+        return;
+    }
+    let mut request = request;
+    // 不完全なファイルは削除済みなので最初から取り直す
+    request.resume = None;
+    let _ = queue_download(app, request, 0);
+}
+
+/// 起動時にキューファイルを読み込む。実行中のまま終了したジョブは待機中に戻して再開する。
+/// 一時停止中のジョブは一時停止のまま戻し、resume_download で再開できるようにする。
+pub(crate) fn restore_download_queue(app: &AppHandle) {
+    let queue_state = app.state::<DownloadQueueState>();
+    if let Ok(mut max_concurrent) = queue_state.max_concurrent.lock() {
+        *max_concurrent = read_settings(app).max_concurrent_downloads;
+    }
+
+    let persisted = queue_file_path(app)
+        .ok()
+        .filter(|path| path.exists())
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|content| parse_versioned_queue(&content))
+        .unwrap_or_default();
+    let items = restore_queue_items(persisted.items);
+    if let Ok(mut paused) = app.state::<DownloadProcessState>().paused.lock() {
+        for item in items.iter().filter(|item| item.status == QueueItemStatus::Paused) {
+            paused.insert(item.request.id.clone(), item.request.clone());
+        }
+    }
+    if let Ok(mut current) = queue_state.items.lock() {
+        *current = items;
+    }
+    pump_download_queue(app);
+}
+
+#[tauri::command]
+pub fn enqueue_download(
+    app: AppHandle,
+    state: State<DownloadQueueState>,
+    request: DownloadRequest,
+    priority: Option<i32>,
+) -> Result<DownloadQueueSnapshot, String> {
+    resolve_audio_format(request.quality.as_deref(), request.audio_format.as_deref())?;
+    {
+        let mut items = state
+            .items
+            .lock()
+            .map_err(|e| format!("キューの更新に失敗しました: {}", e))?;
+        upsert_queue_item(&mut items, request, priority.unwrap_or(0), now_ms())?;
+    }
+    commit_queue(&app, &state);
+    pump_download_queue(&app);
+    Ok(snapshot(&state))
+}
+
+#[tauri::command]
+pub fn dequeue_download(
+    app: AppHandle,
+    state: State<DownloadQueueState>,
+    id: String,
+) -> Result<DownloadQueueSnapshot, String> {
+    {
+        let mut items = state
+            .items
+            .lock()
+            .map_err(|e| format!("キューの更新に失敗しました: {}", e))?;
+        remove_queued_item(&mut items, &id)?;
+    }
+    // 一時停止中の項目を外した場合は再開情報も破棄する
+    if let Ok(mut paused) = app.state::<DownloadProcessState>().paused.lock() {
+        paused.remove(&id);
+    }
+    Ok(commit_queue(&app, &state))
+}
+
+#[tauri::command]
+pub fn reorder_download_queue(
+    app: AppHandle,
+    state: State<DownloadQueueState>,
+    ids: Vec<String>,
+) -> Result<DownloadQueueSnapshot, String> {
+    {
+        let mut items = state
+            .items
+            .lock()
+            .map_err(|e| format!("キューの更新に失敗しました: {}", e))?;
+        reorder_queue_items(&mut items, &ids);
+    }
+    Ok(commit_queue(&app, &state))
+}
+
+#[tauri::command]
+pub fn set_download_priority(
+    app: AppHandle,
+    state: State<DownloadQueueState>,
+    id: String,
+    priority: i32,
+) -> Result<DownloadQueueSnapshot, String> {
+    {
+        let mut items = state
+            .items
+            .lock()
+            .map_err(|e| format!("キューの更新に失敗しました: {}", e))?;
+        set_queue_item_priority(&mut items, &id, priority)?;
+    }
+    Ok(commit_queue(&app, &state))
+}
+
+#[tauri::command]
+pub fn set_max_concurrent_downloads(
+    app: AppHandle,
+    state: State<DownloadQueueState>,
+    value: u32,
+) -> Result<DownloadQueueSnapshot, String> {
+    let value = effective_max_concurrent(Some(value));
+    let mut settings = read_settings(&app);
+    settings.max_concurrent_downloads = Some(value);
+    write_settings(&app, settings)?;
+    if let Ok(mut max_concurrent) = state.max_concurrent.lock() {
+        *max_concurrent = Some(value);
+    }
+    let snapshot = commit_queue(&app, &state);
+    pump_download_queue(&app);
+    Ok(snapshot)
+}
+
+#[tauri::command]
+pub fn get_download_queue(state: State<DownloadQueueState>) -> Result<DownloadQueueSnapshot, String> {
+    Ok(snapshot(&state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(id: &str) -> DownloadRequest {
+        DownloadRequest {
+            id: id.to_string(),
+            url: format!("https://www.youtube.com/watch?v={}", id),
+            output_dir: "/library".to_string(),
+            cookies_file: None,
+            cookies_source: None,
+            cookies_browser: None,
+            remote_components: None,
+            yt_dlp_path: None,
+            ffmpeg_path: None,
+            quality: None,
+            is_live: None,
+            format_preset: None,
+            audio_format: None,
+            resume: None,
+        }
+    }
+
+    fn queue_of(ids: &[&str]) -> Vec<QueuedDownload> {
+        let mut items = Vec::new();
+        for (i, id) in ids.iter().enumerate() {
+            upsert_queue_item(&mut items, request(id), 0, i as u64).unwrap();
+        }
+        items
+    }
+
+    fn ids(items: &[QueuedDownload]) -> Vec<&str> {
+        items.iter().map(|item| item.request.id.as_str()).collect()
+    }
+
+    // =========================================================
+    // parse_versioned_queue
+    // =========================================================
+
+    #[test]
+    fn parse_queue_versioned_v1() {
+        let content = serde_json::to_string(&json!({
+            "version": 1,
+            "data": {
+                "items": [{
+                    "request": { "id": "v1", "url": "u", "outputDir": "/lib" },
+                    "priority": 3,
+                    "status": "running",
+                    "enqueuedAtMs": 10
+                }]
+            }
+        })).unwrap();
+        let queue = parse_versioned_queue(&content);
+        assert_eq!(queue.items.len(), 1);
+        assert_eq!(queue.items[0].priority, 3);
+        assert_eq!(queue.items[0].status, QueueItemStatus::Running);
+    }
+
+    #[test]
+    fn parse_queue_future_version_returns_default() {
+        let content = serde_json::to_string(&json!({
+            "version": 999,
+            "data": { "items": [] }
+        })).unwrap();
+        assert!(parse_versioned_queue(&content).items.is_empty());
+    }
+
+    #[test]
+    fn parse_queue_invalid_json() {
+        assert!(parse_versioned_queue("broken").items.is_empty());
+    }
+
+    // =========================================================
+    // effective_max_concurrent
+    // =========================================================
+
+    #[test]
+    fn max_concurrent_default() {
+        assert_eq!(effective_max_concurrent(None), DEFAULT_MAX_CONCURRENT_DOWNLOADS);
+    }
+
+    #[test]
+    fn max_concurrent_clamped() {
+        assert_eq!(effective_max_concurrent(Some(0)), 1);
+        assert_eq!(effective_max_concurrent(Some(100)), MAX_CONCURRENT_DOWNLOADS_LIMIT);
+    }
+
+    // =========================================================
+    // upsert / remove / reorder / priority
+    // =========================================================
+
+    #[test]
+    fn upsert_updates_existing_item() {
+        let mut items = queue_of(&["a", "b"]);
+        upsert_queue_item(&mut items, request("a"), 5, 99).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].priority, 5);
+        assert_eq!(items[0].enqueued_at_ms, 0);
+    }
+
+    #[test]
+    fn upsert_running_item_rejected() {
+        let mut items = queue_of(&["a"]);
+        items[0].status = QueueItemStatus::Running;
+        assert!(upsert_queue_item(&mut items, request("a"), 1, 0).is_err());
+    }
+
+    #[test]
+    fn remove_queued_and_running() {
+        let mut items = queue_of(&["a", "b"]);
+        items[1].status = QueueItemStatus::Running;
+        assert!(remove_queued_item(&mut items, "a").is_ok());
+        assert!(remove_queued_item(&mut items, "b").is_err());
+        assert!(remove_queued_item(&mut items, "missing").is_err());
+        assert_eq!(ids(&items), vec!["b"]);
+    }
+
+    #[test]
+    fn reorder_moves_listed_ids_first() {
+        let mut items = queue_of(&["a", "b", "c", "d"]);
+        reorder_queue_items(&mut items, &["c".to_string(), "a".to_string(), "zzz".to_string()]);
+        assert_eq!(ids(&items), vec!["c", "a", "b", "d"]);
+    }
+
+    #[test]
+    fn set_priority_missing_id() {
+        let mut items = queue_of(&["a"]);
+        assert!(set_queue_item_priority(&mut items, "x", 1).is_err());
+        assert!(set_queue_item_priority(&mut items, "a", 7).is_ok());
+        assert_eq!(items[0].priority, 7);
+    }
+
+    // =========================================================
+    // take_runnable
+    // =========================================================
+
+    #[test]
+    fn take_runnable_respects_limit() {
+        let mut items = queue_of(&["a", "b", "c"]);
+        let started = take_runnable(&mut items, 2);
+        assert_eq!(started.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+        assert!(take_runnable(&mut items, 2).is_empty());
+        assert_eq!(items[2].status, QueueItemStatus::Queued);
+    }
+
+    #[test]
+    fn take_runnable_prefers_priority_then_order() {
+        let mut items = queue_of(&["a", "b", "c"]);
+        set_queue_item_priority(&mut items, "c", 10).unwrap();
+        let started = take_runnable(&mut items, 2);
+        assert_eq!(started.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(), vec!["c", "a"]);
+    }
+
+    #[test]
+    fn take_runnable_counts_running_jobs() {
+        let mut items = queue_of(&["a", "b"]);
+        items[0].status = QueueItemStatus::Running;
+        assert!(take_runnable(&mut items, 1).is_empty());
+        assert_eq!(take_runnable(&mut items, 2).len(), 1);
+    }
+
+    #[test]
+    fn recording_waits_for_slot_and_runs_first() {
+        let mut items = queue_of(&["a", "b"]);
+        take_runnable(&mut items, 1);
+        upsert_queue_item(&mut items, request("live"), crate::RECORDING_QUEUE_PRIORITY, 9).unwrap();
+        // 予約録画も同時実行数の上限を超えては起動しない
+        assert!(take_runnable(&mut items, 1).is_empty());
+        items.retain(|item| item.status != QueueItemStatus::Running);
+        assert_eq!(take_runnable(&mut items, 1)[0].id, "live");
+    }
+
+    #[test]
+    fn claim_slot_starts_when_free_and_queues_when_full() {
+        let mut items = queue_of(&["a"]);
+        take_runnable(&mut items, 2);
+        assert_eq!(claim_queue_slot(&mut items, request("b"), 2, 1), Ok(true));
+        assert_eq!(items[1].status, QueueItemStatus::Running);
+        // 上限に達している場合は直接開始でも待機中にする
+        assert_eq!(claim_queue_slot(&mut items, request("c"), 2, 2), Ok(false));
+        assert_eq!(items[2].status, QueueItemStatus::Queued);
+        assert!(claim_queue_slot(&mut items, request("a"), 3, 3).is_err());
+    }
+
+    #[test]
+    fn claim_slot_restarts_paused_item() {
+        let mut items = queue_of(&["a"]);
+        take_runnable(&mut items, 1);
+        mark_queue_item_paused(&mut items, request("a"), 0);
+        assert_eq!(claim_queue_slot(&mut items, request("a"), 1, 1), Ok(true));
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].status, QueueItemStatus::Running);
+    }
+
+    // =========================================================
+    // paused items
+    // =========================================================
+
+    #[test]
+    fn paused_item_kept_with_resume_request() {
+        let mut items = queue_of(&["a"]);
+        take_runnable(&mut items, 1);
+        let mut resume = request("a");
+        resume.resume = Some(true);
+        mark_queue_item_paused(&mut items, resume, 5);
+        assert_eq!(items[0].status, QueueItemStatus::Paused);
+        assert_eq!(items[0].request.resume, Some(true));
+        // キュー外で開始したジョブも一時停止中として追加する
+        mark_queue_item_paused(&mut items, request("direct"), 6);
+        assert_eq!(ids(&items), vec!["a", "direct"]);
+        assert_eq!(items[1].status, QueueItemStatus::Paused);
+        // 一時停止中のジョブは自動では起動しない
+        assert!(take_runnable(&mut items, 3).is_empty());
+    }
+
+    #[test]
+    fn release_paused_item_resumes_or_discards() {
+        let mut items = queue_of(&["a", "b"]);
+        mark_queue_item_paused(&mut items, request("a"), 0);
+        mark_queue_item_paused(&mut items, request("b"), 0);
+        assert!(release_paused_item(&mut items, "a", true));
+        assert_eq!(items[0].status, QueueItemStatus::Running);
+        assert!(release_paused_item(&mut items, "b", false));
+        assert_eq!(ids(&items), vec!["a"]);
+        assert!(!release_paused_item(&mut items, "a", false));
+    }
+
+    #[test]
+    fn restore_keeps_paused_and_requeues_running() {
+        let mut items = queue_of(&["a", "b", "c"]);
+        take_runnable(&mut items, 1);
+        mark_queue_item_paused(&mut items, request("b"), 0);
+        let content = serde_json::to_string(&VersionedQueue {
+            version: QUEUE_SCHEMA_VERSION,
+            data: PersistedQueue { items },
+        })
+        .unwrap();
+        let restored = restore_queue_items(parse_versioned_queue(&content).items);
+        let statuses: Vec<QueueItemStatus> = restored.iter().map(|item| item.status).collect();
+        assert_eq!(
+            statuses,
+            vec![QueueItemStatus::Queued, QueueItemStatus::Paused, QueueItemStatus::Queued]
+        );
+    }
+
+    // =========================================================
+    // record_verify_result
+    // =========================================================
+
+    #[test]
+    fn verify_retries_capped() {
+        let mut retries = HashMap::new();
+        for _ in 0..VERIFY_RETRY_MAX {
+            assert!(record_verify_result(&mut retries, "a", Some(false)));
+        }
+        assert!(!record_verify_result(&mut retries, "a", Some(false)));
+        assert!(!retries.contains_key("a"));
+    }
+
+    #[test]
+    fn verify_retries_reset_on_success() {
+        let mut retries = HashMap::new();
+        assert!(record_verify_result(&mut retries, "a", Some(false)));
+        assert!(!record_verify_result(&mut retries, "a", Some(true)));
+        assert!(!retries.contains_key("a"));
+        assert!(!record_verify_result(&mut retries, "b", None));
+    }
+}
@@ -0,0 +1,190 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::AppHandle;
+use crate::models::{FailureKind, PersistedSettings, RetryPolicy};
+use crate::state::{read_settings, write_settings};
+use crate::{
+    DEFAULT_RETRY_BASE_DELAY_MS, DEFAULT_RETRY_JITTER_RATIO, DEFAULT_RETRY_MAX_ATTEMPTS,
+    DEFAULT_RETRY_MAX_DELAY_MS, RETRY_MAX_ATTEMPTS_LIMIT,
+};
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_RETRY_MAX_ATTEMPTS,
+            base_delay_ms: DEFAULT_RETRY_BASE_DELAY_MS,
+            max_delay_ms: DEFAULT_RETRY_MAX_DELAY_MS,
+            jitter_ratio: DEFAULT_RETRY_JITTER_RATIO,
+            retry_on: vec![FailureKind::RateLimited, FailureKind::Network],
+        }
+    }
+}
+
+/// 保存値を安全な範囲に丸める
+pub(crate) fn normalize_retry_policy(policy: RetryPolicy) -> RetryPolicy {
+    let base_delay_ms = policy.base_delay_ms.max(1);
+    RetryPolicy {
+        max_attempts: policy.max_attempts.min(RETRY_MAX_ATTEMPTS_LIMIT),
+        base_delay_ms,
+        max_delay_ms: policy.max_delay_ms.max(base_delay_ms),
+        jitter_ratio: if policy.jitter_ratio.is_finite() {
+            policy.jitter_ratio.clamp(0.0, 1.0)
+        } else {
+            0.0
+        },
+        retry_on: policy.retry_on,
+    }
+}
+
+pub(crate) fn effective_retry_policy(settings: &PersistedSettings) -> RetryPolicy {
+    normalize_retry_policy(settings.retry_policy.clone().unwrap_or_default())
+}
+
+/// retries_done 回再試行した後に失敗した場合の待ち時間。再試行しない場合は None。
+/// jitter_sample は 0.0〜1.0 の値で、0.5 のときちょうど基準の待ち時間になる。
+pub(crate) fn next_retry_delay_ms(
+    policy: &RetryPolicy,
+    kind: FailureKind,
+    retries_done: u32,
+    jitter_sample: f64,
+) -> Option<u64> {
+    if retries_done >= policy.max_attempts || !policy.retry_on.contains(&kind) {
+        return None;
+    }
+    let exponent = retries_done.min(32);
+    let delay = policy
+        .base_delay_ms
+        .saturating_mul(1u64 << exponent)
+        .min(policy.max_delay_ms) as f64;
+    let factor = 1.0 + policy.jitter_ratio * (jitter_sample.clamp(0.0, 1.0) * 2.0 - 1.0);
+    Some(((delay * factor).round() as u64).min(policy.max_delay_ms))
+}
+
+pub(crate) fn jitter_sample() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as f64 / 1_000_000_000.0)
+        .unwrap_or(0.5)
+}
+
+/// ポリシーに従って再試行するか判定し、再試行する場合は待ち時間と進捗表示用の文言を返す
+pub(crate) fn plan_retry_with(
+    policy: &RetryPolicy,
+    kind: FailureKind,
+    retries_done: u32,
+    jitter_sample: f64,
+) -> Option<(Duration, String)> {
+    let delay_ms = next_retry_delay_ms(policy, kind, retries_done, jitter_sample)?;
+    let message = format!(
+        "{}を検知したため{}秒後にリトライします ({}/{})",
+        kind.label(),
+        (delay_ms as f64 / 1000.0).ceil() as u64,
+        retries_done + 1,
+        policy.max_attempts
+    );
+    Some((Duration::from_millis(delay_ms), message))
+}
+
+/// 保存済みの設定で plan_retry_with を呼ぶ
+pub(crate) fn plan_retry(app: &AppHandle, kind: FailureKind, retries_done: u32) -> Option<(Duration, String)> {
+    let policy = effective_retry_policy(&read_settings(app));
+    plan_retry_with(&policy, kind, retries_done, jitter_sample())
+}
+
+#[tauri::command]
+pub fn get_retry_policy(app: AppHandle) -> Result<RetryPolicy, String> {
+    Ok(effective_retry_policy(&read_settings(&app)))
+}
+
+#[tauri::command]
+pub fn save_retry_policy(app: AppHandle, policy: RetryPolicy) -> Result<RetryPolicy, String> {
+    let policy = normalize_retry_policy(policy);
+    let mut persisted = read_settings(&app);
+    persisted.retry_policy = Some(policy.clone());
+    write_settings(&app, persisted)?;
+    Ok(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 1_000,
+            max_delay_ms: 3_000,
+            jitter_ratio: 0.5,
+            retry_on: vec![FailureKind::RateLimited],
+        }
+    }
+
+    // =========================================================
+    // next_retry_delay_ms
+    // =========================================================
+
+    #[test]
+    fn delay_doubles_and_caps() {
+        let policy = policy();
+        assert_eq!(next_retry_delay_ms(&policy, FailureKind::RateLimited, 0, 0.5), Some(1_000));
+        assert_eq!(next_retry_delay_ms(&policy, FailureKind::RateLimited, 1, 0.5), Some(2_000));
+        assert_eq!(next_retry_delay_ms(&policy, FailureKind::RateLimited, 2, 0.5), Some(3_000));
+        assert_eq!(next_retry_delay_ms(&policy, FailureKind::RateLimited, 3, 0.5), None);
+    }
+
+    #[test]
+    fn delay_jitter_range() {
+        let policy = policy();
+        assert_eq!(next_retry_delay_ms(&policy, FailureKind::RateLimited, 0, 0.0), Some(500));
+        assert_eq!(next_retry_delay_ms(&policy, FailureKind::RateLimited, 0, 1.0), Some(1_500));
+        // 上限を超えるばらつきは上限に揃える
+        assert_eq!(next_retry_delay_ms(&policy, FailureKind::RateLimited, 2, 1.0), Some(3_000));
+    }
+
+    #[test]
+    fn delay_only_for_listed_kinds() {
+        assert_eq!(next_retry_delay_ms(&policy(), FailureKind::Private, 0, 0.5), None);
+        assert_eq!(next_retry_delay_ms(&policy(), FailureKind::Network, 0, 0.5), None);
+    }
+
+    #[test]
+    fn plan_retry_message() {
+        let (delay, message) = plan_retry_with(&policy(), FailureKind::RateLimited, 1, 0.5).unwrap();
+        assert_eq!(delay, Duration::from_millis(2_000));
+        assert_eq!(message, "アクセス制限を検知したため2秒後にリトライします (2/3)");
+        assert!(plan_retry_with(&policy(), FailureKind::Private, 0, 0.5).is_none());
+    }
+
+    #[test]
+    fn delay_large_retry_count_does_not_overflow() {
+        let mut policy = policy();
+        policy.max_attempts = u32::MAX;
+        assert_eq!(next_retry_delay_ms(&policy, FailureKind::RateLimited, 60, 0.5), Some(3_000));
+    }
+
+    // =========================================================
+    // normalize_retry_policy / effective_retry_policy
+    // =========================================================
+
+    #[test]
+    fn normalize_clamps_values() {
+        let policy = normalize_retry_policy(RetryPolicy {
+            max_attempts: 100,
+            base_delay_ms: 0,
+            max_delay_ms: 0,
+            jitter_ratio: f64::NAN,
+            retry_on: vec![],
+        });
+        assert_eq!(policy.max_attempts, RETRY_MAX_ATTEMPTS_LIMIT);
+        assert_eq!(policy.base_delay_ms, 1);
+        assert_eq!(policy.max_delay_ms, 1);
+        assert_eq!(policy.jitter_ratio, 0.0);
+    }
+
+    #[test]
+    fn effective_policy_defaults() {
+        let policy = effective_retry_policy(&PersistedSettings::default());
+        assert_eq!(policy.max_attempts, DEFAULT_RETRY_MAX_ATTEMPTS);
+        assert!(policy.retry_on.contains(&FailureKind::RateLimited));
+        assert!(policy.retry_on.contains(&FailureKind::Network));
+    }
+}
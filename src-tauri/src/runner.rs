@@ -0,0 +1,925 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use crate::failure::classify_failure;
+use crate::models::{FailureKind, JobKind, JobProcessState};
+use crate::{YTDLP_TITLE_WARNING, YTDLP_WARNING_RETRY_MAX, YTDLP_WARNING_RETRY_SLEEP_MS};
+
+/// プロセス終了の確認間隔
+const POLL_INTERVAL_MS: u64 = 100;
+/// 終了後に残りの出力を読み切るまでの猶予 (子プロセスがパイプを掴んだままの場合に備える)
+const DRAIN_TIMEOUT_MS: u64 = 2_000;
+/// 再試行を待つ間に停止要求を確認する間隔
+const RETRY_WAIT_STEP_MS: u64 = 250;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// 出力行を受け取った側の指示。Stop を返すとプロセスを終了させる
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LineAction {
+    Continue,
+    Stop,
+}
+
+/// yt-dlp を1回実行した結果
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RunOutput {
+    /// 終了コードが 0 だったか
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+    /// タイトル取得の警告 (YTDLP_TITLE_WARNING) が出力されたか
+    pub warning_seen: bool,
+    pub timed_out: bool,
+    /// 停止要求で終了したか
+    pub cancelled: bool,
+    /// on_line が Stop を返したため終了させたか
+    pub stopped: bool,
+}
+
+impl RunOutput {
+    fn push_line(&mut self, stream: OutputStream, line: &str) {
+        if line.contains(YTDLP_TITLE_WARNING) {
+            self.warning_seen = true;
+        }
+        let buf = match stream {
+            OutputStream::Stdout => &mut self.stdout,
+            OutputStream::Stderr => &mut self.stderr,
+        };
+        buf.push_str(line);
+        buf.push('\n');
+    }
+}
+
+pub(crate) type ChildRegistry = Mutex<HashMap<String, Arc<Mutex<Child>>>>;
+
+/// 実行中のプロセスを登録し、外部から停止できるようにする。
+/// 停止する側は cancelled に key を入れてからプロセスを kill する。
+pub(crate) struct ProcessHandle<'a> {
+    pub key: &'a str,
+    pub children: &'a ChildRegistry,
+    pub cancelled: &'a Mutex<HashSet<String>>,
+}
+
+impl ProcessHandle<'_> {
+    fn take_cancelled(&self) -> bool {
+        self.cancelled
+            .lock()
+            .map(|mut set| set.remove(self.key))
+            .unwrap_or(false)
+    }
+
+    fn cancel_requested(&self) -> bool {
+        self.cancelled
+            .lock()
+            .map(|set| set.contains(self.key))
+            .unwrap_or(false)
+    }
+}
+
+/// 同じ動画のメタデータとコメントは並行して取得することがあるため、種類ごとに分ける
+pub(crate) fn job_process_key(kind: JobKind, id: &str) -> String {
+    let kind = match kind {
+        JobKind::Video => "video",
+        JobKind::Metadata => "metadata",
+        JobKind::Comments => "comments",
+    };
+    format!("{}:{}", kind, id)
+}
+
+impl JobProcessState {
+    pub(crate) fn handle<'a>(&'a self, key: &'a str) -> ProcessHandle<'a> {
+        ProcessHandle {
+            key,
+            children: &self.children,
+            cancelled: &self.cancelled,
+        }
+    }
+
+    /// ジョブを実行中として登録する。戻り値を破棄すると登録と未処理の停止要求が解除される。
+    pub(crate) fn begin(&self, key: &str) -> ActiveJob {
+        if let Ok(mut set) = self.active.lock() {
+            set.insert(key.to_string());
+        }
+        ActiveJob {
+            state: self.clone(),
+            key: key.to_string(),
+        }
+    }
+
+    /// 実行中のプロセスを止め、以降の段階も実行しないようにする
+    pub(crate) fn stop(&self, key: &str) -> Result<(), String> {
+        let active = self
+            .active
+            .lock()
+            .map_err(|err| format!("停止処理に失敗しました: {}", err))?
+            .contains(key);
+        if !active {
+            return Err("停止対象のジョブが見つかりませんでした。".to_string());
+        }
+        if let Ok(mut set) = self.cancelled.lock() {
+            set.insert(key.to_string());
+        }
+        let child = match self.children.lock() {
+            Ok(map) => map.get(key).cloned(),
+            Err(err) => return Err(format!("停止処理に失敗しました: {}", err)),
+        };
+        // 段階の合間で子プロセスがいない場合は、次の起動前に停止要求を検知する
+        if let Some(child) = child {
+            let mut guard = child
+                .lock()
+                .map_err(|err| format!("停止処理に失敗しました: {}", err))?;
+            guard
+                .kill()
+                .map_err(|err| format!("プロセスの停止に失敗しました: {}", err))?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct ActiveJob {
+    state: JobProcessState,
+    key: String,
+}
+
+impl Drop for ActiveJob {
+    fn drop(&mut self) {
+        if let Ok(mut set) = self.state.active.lock() {
+            set.remove(&self.key);
+        }
+        if let Ok(mut set) = self.state.cancelled.lock() {
+            set.remove(&self.key);
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct RunOptions<'a> {
+    pub timeout: Option<Duration>,
+    pub process: Option<ProcessHandle<'a>>,
+}
+
+/// yt-dlp の起動から終了までを担う。テストでは記録済みの出力を返す FakeRunner に差し替える。
+pub(crate) trait YtDlpRunner: Send + Sync {
+    /// 1回分を実行して終了まで待つ。起動・待機に失敗した場合は Err。
+    fn run(
+        &self,
+        command: Command,
+        options: &RunOptions,
+        on_line: &mut dyn FnMut(OutputStream, &str) -> LineAction,
+    ) -> Result<RunOutput, String>;
+
+    /// 再試行までの待機
+    fn sleep(&self, delay: Duration) {
+        std::thread::sleep(delay);
+    }
+}
+
+/// 実際に yt-dlp を起動する Runner
+pub(crate) struct ProcessRunner;
+
+fn spawn_line_reader(stream: impl Read + Send + 'static, kind: OutputStream, tx: Sender<(OutputStream, String)>) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if tx.send((kind, line)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+impl YtDlpRunner for ProcessRunner {
+    fn run(
+        &self,
+        mut command: Command,
+        options: &RunOptions,
+        on_line: &mut dyn FnMut(OutputStream, &str) -> LineAction,
+    ) -> Result<RunOutput, String> {
+        // 前の段階の後に停止された場合は起動しない
+        if let Some(process) = options.process.as_ref().filter(|process| process.cancel_requested()) {
+            return Ok(RunOutput {
+                cancelled: process.take_cancelled(),
+                ..Default::default()
+            });
+        }
+        #[cfg(windows)]
+        command.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = command
+            .spawn()
+            .map_err(|e| format!("yt-dlpの起動に失敗しました: {}", e))?;
+
+        let (tx, rx) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            spawn_line_reader(stdout, OutputStream::Stdout, tx.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            spawn_line_reader(stderr, OutputStream::Stderr, tx.clone());
+        }
+        drop(tx);
+
+        let child = Arc::new(Mutex::new(child));
+        if let Some(process) = &options.process {
+            if let Ok(mut map) = process.children.lock() {
+                map.insert(process.key.to_string(), child.clone());
+            }
+            // 登録前に届いた停止要求はここで反映する
+            if process.cancel_requested() {
+                if let Ok(mut guard) = child.lock() {
+                    let _ = guard.kill();
+                }
+            }
+        }
+        let unregister = || {
+            if let Some(process) = &options.process {
+                if let Ok(mut map) = process.children.lock() {
+                    map.remove(process.key);
+                }
+            }
+        };
+        let kill = || {
+            if let Ok(mut guard) = child.lock() {
+                let _ = guard.kill();
+            }
+        };
+
+        let mut output = RunOutput::default();
+        let started = Instant::now();
+        let mut handle_line = |output: &mut RunOutput, stream: OutputStream, line: String| {
+            output.push_line(stream, &line);
+            if on_line(stream, &line) == LineAction::Stop && !output.stopped {
+                output.stopped = true;
+                kill();
+            }
+        };
+
+        let status = loop {
+            // 出力が続いている間は recv_timeout が待機を兼ねる
+            match rx.recv_timeout(Duration::from_millis(POLL_INTERVAL_MS)) {
+                Ok((stream, line)) => handle_line(&mut output, stream, line),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    std::thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                }
+            }
+            if let Some(timeout) = options.timeout {
+                if !output.timed_out && started.elapsed() > timeout {
+                    output.timed_out = true;
+                    kill();
+                }
+            }
+            let status = match child.lock() {
+                Ok(mut guard) => guard.try_wait(),
+                Err(err) => {
+                    unregister();
+                    return Err(format!("yt-dlpの制御に失敗しました: {}", err));
+                }
+            };
+            match status {
+                Ok(Some(status)) => break status,
+                Ok(None) => {}
+                Err(err) => {
+                    unregister();
+                    return Err(format!("yt-dlpの実行に失敗しました: {}", err));
+                }
+            }
+        };
+
+        let drain_deadline = Instant::now() + Duration::from_millis(DRAIN_TIMEOUT_MS);
+        while let Some(remaining) = drain_deadline.checked_duration_since(Instant::now()) {
+            match rx.recv_timeout(remaining) {
+                Ok((stream, line)) => handle_line(&mut output, stream, line),
+                Err(_) => break,
+            }
+        }
+
+        unregister();
+        output.success = status.success();
+        output.cancelled = options
+            .process
+            .as_ref()
+            .is_some_and(ProcessHandle::take_cancelled);
+        Ok(output)
+    }
+}
+
+/// 試行結果に対するジョブ側の判断
+pub(crate) enum Verdict {
+    /// 警告・失敗の種類に応じた既定の再試行判定に任せる
+    Default,
+    Finish,
+    Retry(Duration, String),
+}
+
+/// 起動に失敗した場合の内容と、それまでの試行回数
+#[derive(Debug)]
+pub(crate) struct RunFailure {
+    pub message: String,
+    pub attempts: u32,
+}
+
+#[derive(Debug)]
+pub(crate) struct JobRun {
+    /// 最後の試行の結果
+    pub output: RunOutput,
+    pub attempts: u32,
+}
+
+/// ダウンロード・メタデータ・コメントの各ジョブが実装する。
+/// 再試行のたびに command が呼ばれ、結果を inspect で判定する。
+pub(crate) trait YtDlpJob {
+    fn command(&mut self) -> Command;
+
+    fn on_line(&mut self, _stream: OutputStream, _line: &str) -> LineAction {
+        LineAction::Continue
+    }
+
+    fn inspect(&mut self, _attempt: u32, _output: &RunOutput) -> Verdict {
+        Verdict::Default
+    }
+
+    /// 失敗の種類に応じた再試行 (設定の再試行ポリシー)
+    fn plan_failure_retry(&mut self, kind: FailureKind, retries_done: u32) -> Option<(Duration, String)>;
+
+    /// 再試行の前に進捗として表示する
+    fn notify(&mut self, message: &str);
+}
+
+/// ジョブを再試行込みで実行する。
+/// 停止された場合は再試行しない。ジョブの判断 → タイトル警告 → 失敗の種類 の順に再試行を決める。
+pub(crate) fn run_job(
+    runner: &dyn YtDlpRunner,
+    options: &RunOptions,
+    job: &mut dyn YtDlpJob,
+) -> Result<JobRun, RunFailure> {
+    let mut attempt: u32 = 0;
+    let mut failure_retries: u32 = 0;
+    loop {
+        attempt += 1;
+        let command = job.command();
+        let output = runner
+            .run(command, options, &mut |stream, line| job.on_line(stream, line))
+            .map_err(|message| RunFailure { message, attempts: attempt })?;
+
+        let retry = match job.inspect(attempt, &output) {
+            Verdict::Finish => None,
+            Verdict::Retry(delay, message) => Some((delay, message)),
+            Verdict::Default if output.cancelled => None,
+            Verdict::Default if output.warning_seen && (attempt as usize) < YTDLP_WARNING_RETRY_MAX => Some((
+                Duration::from_millis(YTDLP_WARNING_RETRY_SLEEP_MS),
+                format!("警告を検知したためリトライします ({}/{})", attempt, YTDLP_WARNING_RETRY_MAX),
+            )),
+            Verdict::Default if !output.success => {
+                let kind = classify_failure(&output.stdout, &output.stderr);
+                let retry = job.plan_failure_retry(kind, failure_retries);
+                if retry.is_some() {
+                    failure_retries += 1;
+                }
+                retry
+            }
+            Verdict::Default => None,
+        };
+
+        match retry {
+            Some((delay, message)) => {
+                job.notify(&message);
+                if wait_before_retry(runner, delay, options.process.as_ref()) {
+                    return Ok(JobRun {
+                        output: RunOutput {
+                            success: false,
+                            cancelled: true,
+                            ..output
+                        },
+                        attempts: attempt,
+                    });
+                }
+            }
+            None => return Ok(JobRun { output, attempts: attempt }),
+        }
+    }
+}
+
+/// 再試行までの待機。待機中は子プロセスがいないため、停止要求を区切りごとに確認する。
+/// 停止された場合は true。
+fn wait_before_retry(runner: &dyn YtDlpRunner, delay: Duration, process: Option<&ProcessHandle>) -> bool {
+    let Some(process) = process else {
+        runner.sleep(delay);
+        return false;
+    };
+    let step = Duration::from_millis(RETRY_WAIT_STEP_MS);
+    let mut remaining = delay;
+    loop {
+        if process.cancel_requested() {
+            return process.take_cancelled();
+        }
+        if remaining.is_zero() {
+            return false;
+        }
+        let slice = remaining.min(step);
+        runner.sleep(slice);
+        remaining -= slice;
+    }
+}
+
+/// 記録済みの yt-dlp 出力を順に返すテスト用の Runner
+#[cfg(test)]
+pub(crate) mod fake {
+    use super::*;
+    use std::borrow::Cow;
+    use std::collections::VecDeque;
+
+    /// 1回分の実行結果。出力は固定文字列でも、テスト内で組み立てた文字列でも渡せる。
+    #[derive(Clone, Debug, Default)]
+    pub(crate) struct FakeRun {
+        pub stdout: Cow<'static, str>,
+        pub stderr: Cow<'static, str>,
+        pub success: bool,
+        pub cancelled: bool,
+        pub timed_out: bool,
+        /// 起動に失敗させる場合のエラー
+        pub spawn_error: Option<Cow<'static, str>>,
+    }
+
+    impl FakeRun {
+        pub(crate) fn ok(stdout: impl Into<Cow<'static, str>>) -> Self {
+            Self {
+                stdout: stdout.into(),
+                success: true,
+                ..Default::default()
+            }
+        }
+
+        pub(crate) fn failed(stderr: impl Into<Cow<'static, str>>) -> Self {
+            Self {
+                stderr: stderr.into(),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[derive(Default)]
+    pub(crate) struct FakeRunner {
+        runs: Mutex<VecDeque<FakeRun>>,
+        pub commands: Mutex<Vec<Vec<String>>>,
+        pub sleeps: Mutex<Vec<Duration>>,
+    }
+
+    impl FakeRunner {
+        pub(crate) fn new(runs: Vec<FakeRun>) -> Self {
+            Self {
+                runs: Mutex::new(runs.into()),
+                ..Default::default()
+            }
+        }
+
+        pub(crate) fn command_args(&self, index: usize) -> Vec<String> {
+            self.commands.lock().unwrap()[index].clone()
+        }
+
+        pub(crate) fn run_count(&self) -> usize {
+            self.commands.lock().unwrap().len()
+        }
+    }
+
+    impl YtDlpRunner for FakeRunner {
+        fn run(
+            &self,
+            command: Command,
+            options: &RunOptions,
+            on_line: &mut dyn FnMut(OutputStream, &str) -> LineAction,
+        ) -> Result<RunOutput, String> {
+            if let Some(process) = options.process.as_ref().filter(|process| process.cancel_requested()) {
+                return Ok(RunOutput {
+                    cancelled: process.take_cancelled(),
+                    ..Default::default()
+                });
+            }
+            self.commands.lock().unwrap().push(
+                command
+                    .get_args()
+                    .map(|arg| arg.to_string_lossy().to_string())
+                    .collect(),
+            );
+            let run = self
+                .runs
+                .lock()
+                .unwrap()
+                .pop_front()
+                .ok_or_else(|| "FakeRunner: 実行結果が用意されていません".to_string())?;
+            if let Some(err) = run.spawn_error {
+                return Err(err.to_string());
+            }
+            let mut output = RunOutput::default();
+            let lines = run
+                .stdout
+                .lines()
+                .map(|line| (OutputStream::Stdout, line))
+                .chain(run.stderr.lines().map(|line| (OutputStream::Stderr, line)));
+            for (stream, line) in lines {
+                output.push_line(stream, line);
+                if on_line(stream, line) == LineAction::Stop {
+                    output.stopped = true;
+                    break;
+                }
+            }
+            output.success = run.success && !output.stopped && !run.cancelled && !run.timed_out;
+            output.timed_out = run.timed_out;
+            output.cancelled = run.cancelled
+                || options
+                    .process
+                    .as_ref()
+                    .is_some_and(ProcessHandle::take_cancelled);
+            Ok(output)
+        }
+
+        fn sleep(&self, delay: Duration) {
+            self.sleeps.lock().unwrap().push(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::{FakeRun, FakeRunner};
+    use super::*;
+
+    const RATE_LIMITED: &str =
+        "ERROR: [youtube] abc: This content isn't available, try again later. Your account has been rate-limited by YouTube for up to an hour.";
+    const PRIVATE: &str =
+        "ERROR: [youtube] abc: Private video. Sign in if you've been granted access to this video";
+
+    /// 失敗時は1秒待って再試行する (レート制限のみ)
+    struct TestJob {
+        notes: Vec<String>,
+        stop_on: Option<&'static str>,
+        verdict_on_first: Option<fn() -> Verdict>,
+        inspected: u32,
+    }
+
+    impl TestJob {
+        fn new() -> Self {
+            Self {
+                notes: Vec::new(),
+                stop_on: None,
+                verdict_on_first: None,
+                inspected: 0,
+            }
+        }
+    }
+
+    impl YtDlpJob for TestJob {
+        fn command(&mut self) -> Command {
+            let mut command = Command::new("yt-dlp");
+            command.arg("--no-playlist").arg("https://example.com/watch?v=abc");
+            command
+        }
+
+        fn on_line(&mut self, _stream: OutputStream, line: &str) -> LineAction {
+            match self.stop_on {
+                Some(pattern) if line.contains(pattern) => LineAction::Stop,
+                _ => LineAction::Continue,
+            }
+        }
+
+        fn inspect(&mut self, _attempt: u32, _output: &RunOutput) -> Verdict {
+            self.inspected += 1;
+            match self.verdict_on_first.take() {
+                Some(verdict) => verdict(),
+                None => Verdict::Default,
+            }
+        }
+
+        fn plan_failure_retry(&mut self, kind: FailureKind, retries_done: u32) -> Option<(Duration, String)> {
+            (kind == FailureKind::RateLimited && retries_done < 2)
+                .then(|| (Duration::from_secs(1), format!("retry {}", retries_done + 1)))
+        }
+
+        fn notify(&mut self, message: &str) {
+            self.notes.push(message.to_string());
+        }
+    }
+
+    // =========================================================
+    // run_job
+    // =========================================================
+
+    #[test]
+    fn job_succeeds_first_try() {
+        let runner = FakeRunner::new(vec![FakeRun::ok("[download] 100%\n")]);
+        let mut job = TestJob::new();
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert!(run.output.success);
+        assert_eq!(run.attempts, 1);
+        assert_eq!(run.output.stdout, "[download] 100%\n");
+        assert_eq!(
+            runner.command_args(0),
+            vec!["--no-playlist", "https://example.com/watch?v=abc"]
+        );
+        assert!(job.notes.is_empty());
+    }
+
+    #[test]
+    fn job_retries_on_title_warning() {
+        let warning = format!("WARNING: [youtube] abc: {}", YTDLP_TITLE_WARNING);
+        let runner = FakeRunner::new(vec![
+            FakeRun { stderr: warning.into(), success: true, ..Default::default() },
+            FakeRun::ok("done"),
+        ]);
+        let mut job = TestJob::new();
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert_eq!(run.attempts, 2);
+        assert!(!run.output.warning_seen);
+        assert_eq!(job.notes, vec![format!("警告を検知したためリトライします (1/{})", YTDLP_WARNING_RETRY_MAX)]);
+        assert_eq!(*runner.sleeps.lock().unwrap(), vec![Duration::from_millis(YTDLP_WARNING_RETRY_SLEEP_MS)]);
+    }
+
+    #[test]
+    fn job_retries_by_failure_kind_until_policy_gives_up() {
+        let runner = FakeRunner::new(vec![
+            FakeRun::failed(RATE_LIMITED),
+            FakeRun::failed(RATE_LIMITED),
+            FakeRun::failed(RATE_LIMITED),
+        ]);
+        let mut job = TestJob::new();
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert_eq!(run.attempts, 3);
+        assert!(!run.output.success);
+        assert_eq!(job.notes, vec!["retry 1", "retry 2"]);
+        assert_eq!(runner.sleeps.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn job_stop_during_retry_wait_ends_without_rerun() {
+        let runner = FakeRunner::new(vec![FakeRun::failed(RATE_LIMITED), FakeRun::ok("done")]);
+        let children = ChildRegistry::default();
+        let cancelled = Mutex::new(HashSet::new());
+        let options = RunOptions {
+            timeout: None,
+            process: Some(ProcessHandle {
+                key: "abc",
+                children: &children,
+                cancelled: &cancelled,
+            }),
+        };
+
+        /// 再試行の通知と同時に停止要求を出す
+        struct StopOnNotify<'a> {
+            inner: TestJob,
+            cancelled: &'a Mutex<HashSet<String>>,
+        }
+        impl YtDlpJob for StopOnNotify<'_> {
+            fn command(&mut self) -> Command {
+                self.inner.command()
+            }
+            fn plan_failure_retry(&mut self, kind: FailureKind, retries_done: u32) -> Option<(Duration, String)> {
+                self.inner.plan_failure_retry(kind, retries_done)
+            }
+            fn notify(&mut self, message: &str) {
+                self.inner.notify(message);
+                self.cancelled.lock().unwrap().insert("abc".to_string());
+            }
+        }
+
+        let mut job = StopOnNotify {
+            inner: TestJob::new(),
+            cancelled: &cancelled,
+        };
+        let run = run_job(&runner, &options, &mut job).unwrap();
+        assert_eq!(run.attempts, 1);
+        assert!(run.output.cancelled);
+        assert!(!run.output.success);
+        assert_eq!(runner.run_count(), 1);
+        assert!(runner.sleeps.lock().unwrap().is_empty());
+        assert!(cancelled.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn retry_wait_with_process_sleeps_in_steps() {
+        let runner = FakeRunner::new(vec![FakeRun::failed(RATE_LIMITED), FakeRun::ok("done")]);
+        let children = ChildRegistry::default();
+        let cancelled = Mutex::new(HashSet::new());
+        let options = RunOptions {
+            timeout: None,
+            process: Some(ProcessHandle {
+                key: "abc",
+                children: &children,
+                cancelled: &cancelled,
+            }),
+        };
+        let run = run_job(&runner, &options, &mut TestJob::new()).unwrap();
+        assert!(run.output.success);
+        let sleeps = runner.sleeps.lock().unwrap();
+        assert_eq!(sleeps.len(), 4);
+        assert_eq!(sleeps.iter().sum::<Duration>(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn job_does_not_retry_permanent_failure() {
+        let runner = FakeRunner::new(vec![FakeRun::failed(PRIVATE)]);
+        let run = run_job(&runner, &RunOptions::default(), &mut TestJob::new()).unwrap();
+        assert_eq!(run.attempts, 1);
+        assert!(run.output.stderr.contains("Private video"));
+    }
+
+    #[test]
+    fn job_cancelled_is_not_retried() {
+        let runner = FakeRunner::new(vec![FakeRun {
+            stderr: YTDLP_TITLE_WARNING.into(),
+            cancelled: true,
+            ..Default::default()
+        }]);
+        let run = run_job(&runner, &RunOptions::default(), &mut TestJob::new()).unwrap();
+        assert_eq!(run.attempts, 1);
+        assert!(run.output.cancelled);
+        assert!(!run.output.success);
+    }
+
+    #[test]
+    fn job_cancel_request_via_process_handle() {
+        let children = ChildRegistry::default();
+        let cancelled = Mutex::new(HashSet::from(["abc".to_string()]));
+        let options = RunOptions {
+            timeout: None,
+            process: Some(ProcessHandle { key: "abc", children: &children, cancelled: &cancelled }),
+        };
+        let runner = FakeRunner::new(vec![FakeRun::failed(RATE_LIMITED)]);
+        let run = run_job(&runner, &options, &mut TestJob::new()).unwrap();
+        assert!(run.output.cancelled);
+        assert_eq!(run.attempts, 1);
+        assert_eq!(runner.run_count(), 0);
+        assert!(cancelled.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn job_stop_from_line_handler() {
+        let runner = FakeRunner::new(vec![FakeRun::ok("line1\nlive_broadcast\nline3\n")]);
+        let mut job = TestJob::new();
+        job.stop_on = Some("live_broadcast");
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert!(run.output.stopped);
+        assert!(!run.output.stdout.contains("line3"));
+    }
+
+    #[test]
+    fn job_verdict_overrides_default() {
+        let runner = FakeRunner::new(vec![FakeRun::failed(RATE_LIMITED)]);
+        let mut job = TestJob::new();
+        job.verdict_on_first = Some(|| Verdict::Finish);
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert_eq!(run.attempts, 1);
+
+        let runner = FakeRunner::new(vec![FakeRun::failed(PRIVATE), FakeRun::ok("")]);
+        let mut job = TestJob::new();
+        job.verdict_on_first = Some(|| Verdict::Retry(Duration::from_secs(3), "custom".to_string()));
+        let run = run_job(&runner, &RunOptions::default(), &mut job).unwrap();
+        assert_eq!(run.attempts, 2);
+        assert_eq!(job.notes, vec!["custom"]);
+        assert_eq!(job.inspected, 2);
+    }
+
+    #[test]
+    fn job_spawn_failure_reports_attempts() {
+        let runner = FakeRunner::new(vec![
+            FakeRun::failed(RATE_LIMITED),
+            FakeRun { spawn_error: Some("yt-dlpの起動に失敗しました: not found".into()), ..Default::default() },
+        ]);
+        let err = run_job(&runner, &RunOptions::default(), &mut TestJob::new()).unwrap_err();
+        assert_eq!(err.attempts, 2);
+        assert!(err.message.contains("起動に失敗"));
+    }
+
+    // =========================================================
+    // JobProcessState
+    // =========================================================
+
+    #[test]
+    fn job_process_key_separates_kinds() {
+        assert_eq!(job_process_key(JobKind::Metadata, "abc"), "metadata:abc");
+        assert_ne!(
+            job_process_key(JobKind::Metadata, "abc"),
+            job_process_key(JobKind::Comments, "abc")
+        );
+    }
+
+    #[test]
+    fn stop_requires_active_job() {
+        let state = JobProcessState::default();
+        assert!(state.stop("metadata:abc").is_err());
+        assert!(state.cancelled.lock().unwrap().is_empty());
+
+        let active = state.begin("metadata:abc");
+        assert!(state.stop("metadata:abc").is_ok());
+        assert!(state.cancelled.lock().unwrap().contains("metadata:abc"));
+        drop(active);
+        assert!(state.active.lock().unwrap().is_empty());
+        assert!(state.cancelled.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stop_between_steps_skips_next_run() {
+        let state = JobProcessState::default();
+        let _active = state.begin("comments:abc");
+        state.stop("comments:abc").unwrap();
+        let options = RunOptions {
+            timeout: None,
+            process: Some(state.handle("comments:abc")),
+        };
+        let output = ProcessRunner
+            .run(Command::new("/nonexistent/yt-dlp-binary"), &options, &mut |_, _| LineAction::Continue)
+            .unwrap();
+        assert!(output.cancelled);
+        assert!(!output.success);
+    }
+
+    // =========================================================
+    // ProcessRunner (実プロセス)
+    // =========================================================
+
+    #[cfg(unix)]
+    #[test]
+    fn process_runner_collects_both_streams() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo out1; echo err1 >&2; echo out2; exit 3");
+        let mut lines = Vec::new();
+        let output = ProcessRunner
+            .run(command, &RunOptions::default(), &mut |stream, line| {
+                lines.push((stream, line.to_string()));
+                LineAction::Continue
+            })
+            .unwrap();
+        assert!(!output.success);
+        assert_eq!(output.stdout, "out1\nout2\n");
+        assert_eq!(output.stderr, "err1\n");
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn process_runner_timeout_and_registry() {
+        let children = ChildRegistry::default();
+        let cancelled = Mutex::new(HashSet::new());
+        let options = RunOptions {
+            timeout: Some(Duration::from_millis(200)),
+            process: Some(ProcessHandle { key: "job", children: &children, cancelled: &cancelled }),
+        };
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("sleep 5");
+        let output = ProcessRunner
+            .run(command, &options, &mut |_, _| LineAction::Continue)
+            .unwrap();
+        assert!(output.timed_out);
+        assert!(!output.success);
+        assert!(!output.cancelled);
+        assert!(children.lock().unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn process_runner_stopped_from_another_thread() {
+        let state = JobProcessState::default();
+        let _active = state.begin("metadata:abc");
+        let stopper = {
+            let state = state.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    std::thread::sleep(Duration::from_millis(50));
+                    if state.children.lock().unwrap().contains_key("metadata:abc") {
+                        return state.stop("metadata:abc");
+                    }
+                }
+                Err("not started".to_string())
+            })
+        };
+        let options = RunOptions {
+            timeout: Some(Duration::from_secs(10)),
+            process: Some(state.handle("metadata:abc")),
+        };
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("exec sleep 5");
+        let output = ProcessRunner
+            .run(command, &options, &mut |_, _| LineAction::Continue)
+            .unwrap();
+        assert!(stopper.join().unwrap().is_ok());
+        assert!(output.cancelled);
+        assert!(!output.timed_out);
+        assert!(!output.success);
+    }
+
+    #[test]
+    fn process_runner_spawn_error() {
+        let command = Command::new("/nonexistent/yt-dlp-binary");
+        let err = ProcessRunner
+            .run(command, &RunOptions::default(), &mut |_, _| LineAction::Continue)
+            .unwrap_err();
+        assert!(err.starts_with("yt-dlpの起動に失敗しました"));
+    }
+}
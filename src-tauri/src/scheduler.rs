@@ -0,0 +1,497 @@
+use std::collections::HashSet;
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager, State};
+use crate::models::{
+    DownloadRequest, PersistedSchedule, RecordingEvent, RecordingScheduleState,
+    ScheduledRecording, ScheduledRecordingStatus, VersionedSchedule,
+};
+use crate::paths::{atomic_write, schedule_file_path};
+use crate::state::{read_settings, write_settings};
+use crate::queue::{queue_download, queued_download_ids};
+use crate::{SCHEDULE_SCHEMA_VERSION, RECORDING_LEAD_SECS, RECORDING_QUEUE_PRIORITY, RECORDING_SCHEDULER_POLL_SECS};
+
+pub(crate) fn parse_versioned_schedule(content: &str) -> PersistedSchedule {
+    if let Ok(wrapper) = serde_json::from_str::<VersionedSchedule>(content) {
+        if wrapper.version <= SCHEDULE_SCHEMA_VERSION {
+            return wrapper.data;
+        }
+        return PersistedSchedule::default();
+    }
+    serde_json::from_str::<PersistedSchedule>(content).unwrap_or_default()
+}
+
+/// yt-dlp の "This live event will begin in 3 hours." から開始予定時刻（UNIX秒）を推定する
+pub(crate) fn parse_upcoming_start(message: &str, now_secs: i64) -> Option<i64> {
+    let lower = message.to_lowercase();
+    let rest = &lower[lower.find("will begin in ")? + "will begin in ".len()..];
+    if rest.starts_with("a few moments") {
+        return Some(now_secs);
+    }
+    let mut words = rest.split_whitespace();
+    let amount = match words.next()? {
+        "a" | "an" => 1,
+        value => value.parse::<i64>().ok()?,
+    };
+    let unit = words.next()?.trim_end_matches(|c: char| !c.is_ascii_alphabetic());
+    let seconds = match unit {
+        "second" | "seconds" => 1,
+        "minute" | "minutes" => 60,
+        "hour" | "hours" => 60 * 60,
+        "day" | "days" => 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(now_secs + amount * seconds)
+}
+
+/// 開始時刻の RECORDING_LEAD_SECS 前を過ぎた待機中の予約を返す。
+/// 開始時刻が不明な予約は yt-dlp の --wait-for-video に待機を任せてすぐ開始する。
+pub(crate) fn due_recording_ids(items: &[ScheduledRecording], now_secs: i64) -> Vec<String> {
+    items
+        .iter()
+        .filter(|item| item.status == ScheduledRecordingStatus::Waiting)
+        .filter(|item| {
+            item.release_timestamp
+                .map(|ts| ts - RECORDING_LEAD_SECS <= now_secs)
+                .unwrap_or(true)
+        })
+        .map(|item| item.request.id.clone())
+        .collect()
+}
+
+pub(crate) fn upsert_recording(items: &mut Vec<ScheduledRecording>, recording: ScheduledRecording) -> Result<(), String> {
+    if let Some(existing) = items.iter_mut().find(|item| item.request.id == recording.request.id) {
+        if existing.status == ScheduledRecordingStatus::Recording {
+            return Err("この配信は既に録画中です。".to_string());
+        }
+        *existing = recording;
+        return Ok(());
+    }
+    items.push(recording);
+    Ok(())
+}
+
+fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn persist_schedule(app: &AppHandle, items: &[ScheduledRecording]) {
+    let Ok(path) = schedule_file_path(app) else {
+        return;
+    };
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let wrapper = VersionedSchedule {
+        version: SCHEDULE_SCHEMA_VERSION,
+        data: PersistedSchedule {
+            items: items.to_vec(),
+        },
+    };
+    if let Ok(content) = serde_json::to_string_pretty(&wrapper) {
+        let _ = atomic_write(&path, content.as_bytes());
+    }
+}
+
+fn snapshot(state: &RecordingScheduleState) -> Vec<ScheduledRecording> {
+    state.items.lock().map(|items| items.clone()).unwrap_or_default()
+}
+
+fn commit_schedule(app: &AppHandle, state: &RecordingScheduleState) -> Vec<ScheduledRecording> {
+    let items = snapshot(state);
+    persist_schedule(app, &items);
+    let _ = app.emit("recording-schedule-updated", items.clone());
+    items
+}
+
+fn mark_failed(app: &AppHandle, id: &str, error: String) {
+    let schedule = app.state::<RecordingScheduleState>();
+    if let Ok(mut items) = schedule.items.lock() {
+        if let Some(item) = items.iter_mut().find(|item| item.request.id == id) {
+            item.status = ScheduledRecordingStatus::Failed;
+            item.last_error = Some(error.clone());
+        }
+    }
+    commit_schedule(app, &schedule);
+    let _ = app.emit(
+        "recording-failed",
+        RecordingEvent {
+            id: id.to_string(),
+            error: Some(error),
+        },
+    );
+}
+
+/// 開始時刻が近づいた予約の録画を開始する
+pub(crate) fn start_due_recordings(app: &AppHandle) {
+    let schedule = app.state::<RecordingScheduleState>();
+    let due: Vec<DownloadRequest> = match schedule.items.lock() {
+        Ok(mut items) => {
+            let ids = due_recording_ids(&items, now_secs());
+            items
+                .iter_mut()
+                .filter(|item| ids.contains(&item.request.id))
+                .map(|item| {
+                    item.status = ScheduledRecordingStatus::Recording;
+                    item.last_error = None;
+                    item.request.clone()
+                })
+                .collect()
+        }
+        Err(_) => return,
+    };
+    if due.is_empty() {
+        return;
+    }
+    commit_schedule(app, &schedule);
+
+    // 同時実行数の上限を守るため、キューの先頭に割り込ませて空き枠ができしだい開始する。
+    // 開始の通知 (recording-started) は実際に起動したときに spawn_download から送る。
+    let queued = queued_download_ids(app);
+    for mut request in due {
+        // 再起動前から続いている録画など、既にキューにあるものは二重に追加しない
+        if queued.contains(&request.id) {
+            continue;
+        }
+        request.is_live = Some(true);
+        let id = request.id.clone();
+        if let Err(err) = queue_download(app, request, RECORDING_QUEUE_PRIORITY) {
+            mark_failed(app, &id, err);
+        }
+    }
+}
+
+/// ダウンロードが実際に起動したときに呼ばれる。予約録画であれば録画開始を通知する。
+pub(crate) fn on_recording_started(app: &AppHandle, id: &str) {
+    let Some(schedule) = app.try_state::<RecordingScheduleState>() else {
+        return;
+    };
+    let recording = schedule
+        .items
+        .lock()
+        .map(|items| {
+            items.iter().any(|item| {
+                item.request.id == id && item.status == ScheduledRecordingStatus::Recording
+            })
+        })
+        .unwrap_or(false);
+    if recording {
+        let _ = app.emit(
+            "recording-started",
+            RecordingEvent {
+                id: id.to_string(),
+                error: None,
+            },
+        );
+    }
+}
+
+/// ダウンロード終了時に呼ばれる。予約録画であれば成功時に予約から外し、失敗時は失敗として残す。
+pub(crate) fn on_recording_finished(app: &AppHandle, id: &str, success: bool) {
+    let Some(schedule) = app.try_state::<RecordingScheduleState>() else {
+        return;
+    };
+    let recording = schedule
+        .items
+        .lock()
+        .map(|items| {
+            items.iter().any(|item| {
+                item.request.id == id && item.status == ScheduledRecordingStatus::Recording
+            })
+        })
+        .unwrap_or(false);
+    if !recording {
+        return;
+    }
+    if success {
+        if let Ok(mut items) = schedule.items.lock() {
+            items.retain(|item| item.request.id != id);
+        }
+        commit_schedule(app, &schedule);
+    } else {
+        mark_failed(app, id, "録画に失敗しました。".to_string());
+    }
+}
+
+/// 配信予定を検出したときに自動録画が有効なら予約に追加する
+pub(crate) fn schedule_upcoming_if_enabled(
+    app: &AppHandle,
+    mut request: DownloadRequest,
+    release_timestamp: Option<i64>,
+) {
+    let settings = read_settings(app);
+    if !settings.auto_record_upcoming.unwrap_or(false) {
+        return;
+    }
+    if request.quality.is_none() {
+        request.quality = settings.download_quality;
+    }
+    let schedule = app.state::<RecordingScheduleState>();
+    let added = match schedule.items.lock() {
+        Ok(mut items) => {
+            // 既に予約済みなら開始時刻だけ更新する
+            if let Some(existing) = items.iter_mut().find(|item| item.request.id == request.id) {
+                if release_timestamp.is_some() {
+                    existing.release_timestamp = release_timestamp;
+                }
+                true
+            } else {
+                upsert_recording(
+                    &mut items,
+                    ScheduledRecording {
+                        request,
+                        title: None,
+                        release_timestamp,
+                        status: ScheduledRecordingStatus::Waiting,
+                        last_error: None,
+                    },
+                )
+                .is_ok()
+            }
+        }
+        Err(_) => false,
+    };
+    if added {
+        commit_schedule(app, &schedule);
+    }
+}
+
+/// 保存されていた予約を起動時の状態に戻す。
+/// 録画中のまま終了した予約は、ダウンロードキューに残っていればキュー側で再開されるため録画中のまま、
+/// 残っていなければ待機中に戻して再度開始する。
+pub(crate) fn restore_recordings(items: Vec<ScheduledRecording>, queued_ids: &HashSet<String>) -> Vec<ScheduledRecording> {
+    items
+        .into_iter()
+        .map(|mut item| {
+            if item.status == ScheduledRecordingStatus::Recording && !queued_ids.contains(&item.request.id) {
+                item.status = ScheduledRecordingStatus::Waiting;
+            }
+            item
+        })
+        .collect()
+}
+
+/// 起動時に予約を復元し、定期的に開始判定を行うスレッドを起動する。
+/// ダウンロードキューの復元 (restore_download_queue) の後に呼ぶ。
+pub(crate) fn start_recording_scheduler(app: &AppHandle) {
+    let schedule = app.state::<RecordingScheduleState>();
+    let persisted = schedule_file_path(app)
+        .ok()
+        .filter(|path| path.exists())
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|content| parse_versioned_schedule(&content))
+        .unwrap_or_default();
+    let restored = restore_recordings(persisted.items, &queued_download_ids(app));
+    if let Ok(mut items) = schedule.items.lock() {
+        *items = restored;
+    }
+
+    let app = app.clone();
+    std::thread::spawn(move || loop {
+        start_due_recordings(&app);
+        std::thread::sleep(Duration::from_secs(RECORDING_SCHEDULER_POLL_SECS));
+    });
+}
+
+#[tauri::command]
+pub fn schedule_recording(
+    app: AppHandle,
+    state: State<RecordingScheduleState>,
+    request: DownloadRequest,
+    release_timestamp: Option<i64>,
+    title: Option<String>,
+) -> Result<Vec<ScheduledRecording>, String> {
+    {
+        let mut items = state
+            .items
+            .lock()
+            .map_err(|e| format!("録画予約の更新に失敗しました: {}", e))?;
+        upsert_recording(
+            &mut items,
+            ScheduledRecording {
+                request,
+                title,
+                release_timestamp,
+                status: ScheduledRecordingStatus::Waiting,
+                last_error: None,
+            },
+        )?;
+    }
+    let items = commit_schedule(&app, &state);
+    start_due_recordings(&app);
+    Ok(items)
+}
+
+#[tauri::command]
+pub fn cancel_scheduled_recording(
+    app: AppHandle,
+    state: State<RecordingScheduleState>,
+    id: String,
+) -> Result<Vec<ScheduledRecording>, String> {
+    {
+        let mut items = state
+            .items
+            .lock()
+            .map_err(|e| format!("録画予約の更新に失敗しました: {}", e))?;
+        let Some(index) = items.iter().position(|item| item.request.id == id) else {
+            return Err("録画予約が見つかりませんでした。".to_string());
+        };
+        if items[index].status == ScheduledRecordingStatus::Recording {
+            return Err("録画中の予約は取り消せません。ダウンロードを停止してください。".to_string());
+        }
+        items.remove(index);
+    }
+    Ok(commit_schedule(&app, &state))
+}
+
+#[tauri::command]
+pub fn get_scheduled_recordings(state: State<RecordingScheduleState>) -> Result<Vec<ScheduledRecording>, String> {
+    Ok(snapshot(&state))
+}
+
+#[tauri::command]
+pub fn set_auto_record_upcoming(app: AppHandle, enabled: bool) -> Result<(), String> {
+    let mut settings = read_settings(&app);
+    settings.auto_record_upcoming = Some(enabled);
+    write_settings(&app, settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn recording(id: &str, release_timestamp: Option<i64>, status: ScheduledRecordingStatus) -> ScheduledRecording {
+        ScheduledRecording {
+            request: DownloadRequest {
+                id: id.to_string(),
+                url: format!("https://www.youtube.com/watch?v={}", id),
+                output_dir: "/library".to_string(),
+                cookies_file: None,
+                cookies_source: None,
+                cookies_browser: None,
+                remote_components: None,
+                yt_dlp_path: None,
+                ffmpeg_path: None,
+                quality: None,
+                is_live: Some(true),
+                format_preset: None,
+                audio_format: None,
+                resume: None,
+            },
+            title: None,
+            release_timestamp,
+            status,
+            last_error: None,
+        }
+    }
+
+    // =========================================================
+    // parse_upcoming_start
+    // =========================================================
+
+    #[test]
+    fn upcoming_hours() {
+        let msg = "ERROR: [youtube] abc: This live event will begin in 3 hours.";
+        assert_eq!(parse_upcoming_start(msg, 1_000), Some(1_000 + 3 * 3600));
+    }
+
+    #[test]
+    fn upcoming_minutes_and_days() {
+        assert_eq!(parse_upcoming_start("This live event will begin in 45 minutes.", 0), Some(2700));
+        assert_eq!(parse_upcoming_start("This live event will begin in a day.", 0), Some(86_400));
+    }
+
+    #[test]
+    fn upcoming_few_moments() {
+        assert_eq!(parse_upcoming_start("This live event will begin in a few moments.", 50), Some(50));
+    }
+
+    #[test]
+    fn upcoming_unparseable() {
+        assert_eq!(parse_upcoming_start("This live event will begin soon", 0), None);
+        assert_eq!(parse_upcoming_start("Video unavailable", 0), None);
+    }
+
+    // =========================================================
+    // due_recording_ids / upsert_recording
+    // =========================================================
+
+    #[test]
+    fn due_respects_lead_time() {
+        let items = vec![
+            recording("soon", Some(1_000 + RECORDING_LEAD_SECS - 1), ScheduledRecordingStatus::Waiting),
+            recording("later", Some(1_000 + RECORDING_LEAD_SECS + 60), ScheduledRecordingStatus::Waiting),
+            recording("unknown", None, ScheduledRecordingStatus::Waiting),
+            recording("failed", Some(0), ScheduledRecordingStatus::Failed),
+            recording("running", Some(0), ScheduledRecordingStatus::Recording),
+        ];
+        assert_eq!(due_recording_ids(&items, 1_000), vec!["soon", "unknown"]);
+    }
+
+    #[test]
+    fn upsert_replaces_failed_but_not_recording() {
+        let mut items = vec![
+            recording("a", Some(1), ScheduledRecordingStatus::Failed),
+            recording("b", Some(1), ScheduledRecordingStatus::Recording),
+        ];
+        assert!(upsert_recording(&mut items, recording("a", Some(5), ScheduledRecordingStatus::Waiting)).is_ok());
+        assert_eq!(items[0].status, ScheduledRecordingStatus::Waiting);
+        assert_eq!(items[0].release_timestamp, Some(5));
+        assert!(upsert_recording(&mut items, recording("b", None, ScheduledRecordingStatus::Waiting)).is_err());
+        assert!(upsert_recording(&mut items, recording("c", None, ScheduledRecordingStatus::Waiting)).is_ok());
+        assert_eq!(items.len(), 3);
+    }
+
+    // =========================================================
+    // restore_recordings
+    // =========================================================
+
+    #[test]
+    fn restore_keeps_recording_still_in_queue() {
+        let items = vec![
+            recording("queued", Some(0), ScheduledRecordingStatus::Recording),
+            recording("lost", Some(0), ScheduledRecordingStatus::Recording),
+            recording("failed", Some(0), ScheduledRecordingStatus::Failed),
+        ];
+        let queued: HashSet<String> = ["queued".to_string()].into_iter().collect();
+        let restored = restore_recordings(items, &queued);
+        assert_eq!(restored[0].status, ScheduledRecordingStatus::Recording);
+        assert_eq!(restored[1].status, ScheduledRecordingStatus::Waiting);
+        assert_eq!(restored[2].status, ScheduledRecordingStatus::Failed);
+        // 復元直後の判定では、キューで再開される録画を二重に開始しない
+        assert_eq!(due_recording_ids(&restored, 1_000), vec!["lost"]);
+    }
+
+    // =========================================================
+    // parse_versioned_schedule
+    // =========================================================
+
+    #[test]
+    fn parse_schedule_versioned_v1() {
+        let content = serde_json::to_string(&json!({
+            "version": 1,
+            "data": {
+                "items": [{
+                    "request": { "id": "live1", "url": "u", "outputDir": "/lib", "isLive": true },
+                    "releaseTimestamp": 1700000000,
+                    "status": "waiting"
+                }]
+            }
+        })).unwrap();
+        let schedule = parse_versioned_schedule(&content);
+        assert_eq!(schedule.items.len(), 1);
+        assert_eq!(schedule.items[0].release_timestamp, Some(1_700_000_000));
+        assert_eq!(schedule.items[0].status, ScheduledRecordingStatus::Waiting);
+    }
+
+    #[test]
+    fn parse_schedule_future_version_or_invalid() {
+        let content = serde_json::to_string(&json!({ "version": 99, "data": { "items": [] } })).unwrap();
+        assert!(parse_versioned_schedule(&content).items.is_empty());
+        assert!(parse_versioned_schedule("broken").items.is_empty());
+    }
+}
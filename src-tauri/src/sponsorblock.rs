@@ -0,0 +1,273 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::AppHandle;
+use crate::models::{NetworkSettings, PersistedSettings, SponsorBlockSettings, SponsorSegment};
+use crate::paths::{atomic_write, library_metadata_dir};
+use crate::files::{find_info_json, info_base_name};
+use crate::state::{read_settings, write_settings};
+use crate::network::{apply_network_client, network_settings};
+use crate::{DEFAULT_SPONSORBLOCK_API_URL, DEFAULT_SPONSORBLOCK_CATEGORIES, SPONSORBLOCK_TIMEOUT_SECS};
+
+pub(crate) fn effective_sponsorblock_settings(settings: &PersistedSettings) -> SponsorBlockSettings {
+    let api_url = settings
+        .sponsorblock_api_url
+        .as_deref()
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .unwrap_or(DEFAULT_SPONSORBLOCK_API_URL)
+        .to_string();
+    let categories = settings
+        .sponsorblock_categories
+        .clone()
+        .filter(|categories| !categories.is_empty())
+        .unwrap_or_else(|| {
+            DEFAULT_SPONSORBLOCK_CATEGORIES
+                .iter()
+                .map(|category| category.to_string())
+                .collect()
+        });
+    SponsorBlockSettings {
+        enabled: settings.sponsorblock_enabled.unwrap_or(false),
+        api_url,
+        categories,
+    }
+}
+
+pub(crate) fn build_skip_segments_url(
+    api_url: &str,
+    id: &str,
+    categories: &[String],
+) -> Result<String, String> {
+    let base = format!("{}/", api_url.trim_end_matches('/'));
+    let mut url = url::Url::parse(&base)
+        .and_then(|base| base.join("api/skipSegments"))
+        .map_err(|e| format!("SponsorBlock APIのURLが不正です: {}", e))?;
+    let categories = serde_json::to_string(categories)
+        .map_err(|e| format!("カテゴリの整形に失敗しました: {}", e))?;
+    url.query_pairs_mut()
+        .append_pair("videoID", id)
+        .append_pair("categories", &categories);
+    Ok(url.to_string())
+}
+
+pub(crate) fn parse_segments_response(body: &str) -> Vec<SponsorSegment> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(body) else {
+        return Vec::new();
+    };
+    let Some(items) = value.as_array() else {
+        return Vec::new();
+    };
+    let mut segments: Vec<SponsorSegment> = items
+        .iter()
+        .filter_map(|item| {
+            let range = item.get("segment")?.as_array()?;
+            let start_time = range.first()?.as_f64()?;
+            let end_time = range.get(1)?.as_f64()?;
+            Some(SponsorSegment {
+                uuid: item.get("UUID").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+                category: item.get("category").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+                action_type: item
+                    .get("actionType")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("skip")
+                    .to_string(),
+                start_time,
+                end_time,
+            })
+        })
+        .collect();
+    segments.sort_by(|a, b| a.start_time.total_cmp(&b.start_time));
+    segments
+}
+
+/// info.json と同じ場所に "<base>.sponsorblock.json" として保存する
+pub(crate) fn sponsorblock_file_path(info_path: &Path) -> Option<PathBuf> {
+    let base = info_base_name(info_path)?;
+    Some(info_path.with_file_name(format!("{}.sponsorblock.json", base)))
+}
+
+async fn fetch_segments(
+    api_url: &str,
+    id: &str,
+    categories: &[String],
+    network: &NetworkSettings,
+) -> Result<Vec<SponsorSegment>, String> {
+    let url = build_skip_segments_url(api_url, id, categories)?;
+    let builder = reqwest::Client::builder().timeout(Duration::from_secs(SPONSORBLOCK_TIMEOUT_SECS));
+    let client = apply_network_client(builder, network)?
+        .build()
+        .map_err(|e| format!("HTTPクライアント作成失敗: {}", e))?;
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("SponsorBlockの取得に失敗しました: {}", e))?;
+    // 区間が登録されていない動画は 404 が返る
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(Vec::new());
+    }
+    if !response.status().is_success() {
+        return Err(format!("HTTPエラー: {}", response.status()));
+    }
+    let body = response
+        .text()
+        .await
+        .map_err(|e| format!("SponsorBlockの取得に失敗しました: {}", e))?;
+    Ok(parse_segments_response(&body))
+}
+
+async fn fetch_and_store(
+    settings: &SponsorBlockSettings,
+    network: &NetworkSettings,
+    output_dir: &str,
+    id: &str,
+) -> Result<Vec<SponsorSegment>, String> {
+    let dir = library_metadata_dir(output_dir);
+    let info_path = find_info_json(&dir, id)
+        .ok_or_else(|| "info.jsonが見つかりません。".to_string())?;
+    let path = sponsorblock_file_path(&info_path)
+        .ok_or_else(|| "保存先の決定に失敗しました。".to_string())?;
+    let segments = fetch_segments(&settings.api_url, id, &settings.categories, network).await?;
+    let content = serde_json::to_string_pretty(&segments)
+        .map_err(|e| format!("SponsorBlockデータの整形に失敗しました: {}", e))?;
+    atomic_write(&path, content.as_bytes())?;
+    Ok(segments)
+}
+
+/// メタデータ取得スレッドから呼ぶ。設定で無効な場合は何もしない。
+pub(crate) fn store_sponsor_segments_blocking(app: &AppHandle, output_dir: &str, id: &str) -> Result<(), String> {
+    let settings = effective_sponsorblock_settings(&read_settings(app));
+    if !settings.enabled {
+        return Ok(());
+    }
+    tauri::async_runtime::block_on(fetch_and_store(&settings, &network_settings(app), output_dir, id)).map(|_| ())
+}
+
+#[tauri::command]
+pub fn get_sponsorblock_segments(id: String, output_dir: String) -> Result<Vec<SponsorSegment>, String> {
+    let dir = library_metadata_dir(&output_dir);
+    let Some(path) = find_info_json(&dir, &id).and_then(|info| sponsorblock_file_path(&info)) else {
+        return Ok(Vec::new());
+    };
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format!("SponsorBlockデータの読み込みに失敗しました: {}", e))?;
+    Ok(serde_json::from_str::<Vec<SponsorSegment>>(&content).unwrap_or_default())
+}
+
+#[tauri::command]
+pub async fn fetch_sponsorblock_segments(
+    app: AppHandle,
+    id: String,
+    output_dir: String,
+) -> Result<Vec<SponsorSegment>, String> {
+    let settings = effective_sponsorblock_settings(&read_settings(&app));
+    fetch_and_store(&settings, &network_settings(&app), &output_dir, &id).await
+}
+
+#[tauri::command]
+pub fn get_sponsorblock_settings(app: AppHandle) -> Result<SponsorBlockSettings, String> {
+    Ok(effective_sponsorblock_settings(&read_settings(&app)))
+}
+
+#[tauri::command]
+pub fn save_sponsorblock_settings(app: AppHandle, settings: SponsorBlockSettings) -> Result<(), String> {
+    let api_url = settings.api_url.trim().to_string();
+    if !api_url.is_empty() {
+        build_skip_segments_url(&api_url, "test", &settings.categories)?;
+    }
+    let mut persisted = read_settings(&app);
+    persisted.sponsorblock_enabled = Some(settings.enabled);
+    persisted.sponsorblock_api_url = Some(api_url).filter(|url| !url.is_empty());
+    persisted.sponsorblock_categories = Some(settings.categories).filter(|c| !c.is_empty());
+    write_settings(&app, persisted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // =========================================================
+    // effective_sponsorblock_settings
+    // =========================================================
+
+    #[test]
+    fn settings_defaults() {
+        let settings = effective_sponsorblock_settings(&PersistedSettings::default());
+        assert!(!settings.enabled);
+        assert_eq!(settings.api_url, DEFAULT_SPONSORBLOCK_API_URL);
+        assert!(settings.categories.contains(&"intro".to_string()));
+    }
+
+    #[test]
+    fn settings_custom_url() {
+        let persisted = PersistedSettings {
+            sponsorblock_enabled: Some(true),
+            sponsorblock_api_url: Some(" http://127.0.0.1:8080 ".to_string()),
+            sponsorblock_categories: Some(vec!["filler".to_string()]),
+            ..Default::default()
+        };
+        let settings = effective_sponsorblock_settings(&persisted);
+        assert!(settings.enabled);
+        assert_eq!(settings.api_url, "http://127.0.0.1:8080");
+        assert_eq!(settings.categories, vec!["filler".to_string()]);
+    }
+
+    // =========================================================
+    // build_skip_segments_url
+    // =========================================================
+
+    #[test]
+    fn skip_segments_url_encodes_query() {
+        let url = build_skip_segments_url("http://localhost:9999/", "abc", &["intro".to_string()]).unwrap();
+        assert_eq!(
+            url,
+            "http://localhost:9999/api/skipSegments?videoID=abc&categories=%5B%22intro%22%5D"
+        );
+    }
+
+    #[test]
+    fn skip_segments_url_keeps_base_path() {
+        let url = build_skip_segments_url("https://example.com/sb", "abc", &[]).unwrap();
+        assert!(url.starts_with("https://example.com/sb/api/skipSegments?"));
+    }
+
+    #[test]
+    fn skip_segments_url_invalid_base() {
+        assert!(build_skip_segments_url("not a url", "abc", &[]).is_err());
+    }
+
+    // =========================================================
+    // parse_segments_response / sponsorblock_file_path
+    // =========================================================
+
+    #[test]
+    fn parse_segments_sorted() {
+        let body = r#"[
+            {"segment": [300.5, 320.0], "UUID": "b", "category": "sponsor", "actionType": "skip", "videoDuration": 600},
+            {"segment": [0, 95.2], "UUID": "a", "category": "intro"},
+            {"segment": [10], "UUID": "broken", "category": "intro"}
+        ]"#;
+        let segments = parse_segments_response(body);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].uuid, "a");
+        assert_eq!(segments[0].action_type, "skip");
+        assert_eq!(segments[0].end_time, 95.2);
+        assert_eq!(segments[1].category, "sponsor");
+    }
+
+    #[test]
+    fn parse_segments_invalid() {
+        assert!(parse_segments_response("Not Found").is_empty());
+        assert!(parse_segments_response("{}").is_empty());
+    }
+
+    #[test]
+    fn sponsorblock_path_next_to_info_json() {
+        let path = sponsorblock_file_path(Path::new("/lib/metadata/ch/Title [abc].info.json")).unwrap();
+        assert_eq!(path, Path::new("/lib/metadata/ch/Title [abc].sponsorblock.json"));
+    }
+}
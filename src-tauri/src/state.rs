@@ -4,7 +4,7 @@ use tauri::{AppHandle, Manager};
 use zip::write::FileOptions;
 use zip::{ZipArchive, ZipWriter};
 use serde::{Deserialize, Serialize};
-use crate::models::{PersistedState, PersistedSettings, PersistedVideos, VersionedSettings, VersionedVideos};
+use crate::models::{FormatPreset, FormatPresetSettings, PersistedState, PersistedSettings, PersistedVideos, VersionedSettings, VersionedVideos};
 use crate::paths::{atomic_write, settings_file_path, videos_file_path};
 use crate::paths::{library_videos_dir, library_metadata_dir, library_comments_dir, library_thumbnails_dir};
 use crate::{SETTINGS_SCHEMA_VERSION, VIDEOS_SCHEMA_VERSION, BACKUP_SCHEMA_VERSION};
@@ -44,6 +44,20 @@ pub(crate) fn read_settings(app: &AppHandle) -> PersistedSettings {
     parse_versioned_settings(&content)
 }
 
+pub(crate) fn write_settings(app: &AppHandle, settings: PersistedSettings) -> Result<(), String> {
+    let settings_path = settings_file_path(app)?;
+    if let Some(parent) = settings_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("設定フォルダの作成に失敗しました: {}", e))?;
+    }
+    let content = serde_json::to_string_pretty(&VersionedSettings {
+        version: SETTINGS_SCHEMA_VERSION,
+        data: settings,
+    })
+    .map_err(|e| format!("設定データの整形に失敗しました: {}", e))?;
+    atomic_write(&settings_path, content.as_bytes())
+}
+
 #[tauri::command]
 pub fn load_state(app: AppHandle) -> Result<PersistedState, String> {
     let settings_path = settings_file_path(&app)?;
@@ -93,6 +107,8 @@ pub fn save_state(app: AppHandle, state: PersistedState) -> Result<(), String> {
             .map_err(|e| format!("インデックスフォルダの作成に失敗しました: {}", e))?;
     }
 
+    // フロントエンドが扱わないバックエンド管理の設定値は既存ファイルから引き継ぐ
+    let existing = read_settings(&app);
     let settings = VersionedSettings {
         version: SETTINGS_SCHEMA_VERSION,
         data: PersistedSettings {
@@ -105,6 +121,7 @@ pub fn save_state(app: AppHandle, state: PersistedState) -> Result<(), String> {
             ffmpeg_path: state.ffmpeg_path,
             ffprobe_path: state.ffprobe_path,
             download_quality: state.download_quality,
+            ..existing
         },
     };
     let settings_content = serde_json::to_string_pretty(&settings)
@@ -123,6 +140,57 @@ pub fn save_state(app: AppHandle, state: PersistedState) -> Result<(), String> {
     Ok(())
 }
 
+pub(crate) fn validate_format_presets(
+    presets: &[FormatPreset],
+    default_preset: Option<&str>,
+) -> Result<(), String> {
+    let mut names = std::collections::HashSet::new();
+    for preset in presets {
+        let name = preset.name.trim();
+        if name.is_empty() {
+            return Err("プリセット名を入力してください。".to_string());
+        }
+        if !names.insert(name) {
+            return Err(format!("プリセット名が重複しています: {}", name));
+        }
+    }
+    if let Some(default_preset) = default_preset {
+        if !names.contains(default_preset) {
+            return Err(format!("既定のプリセットが見つかりません: {}", default_preset));
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_format_presets(app: AppHandle) -> Result<FormatPresetSettings, String> {
+    let settings = read_settings(&app);
+    Ok(FormatPresetSettings {
+        presets: settings.format_presets,
+        default_preset: settings.default_format_preset,
+    })
+}
+
+#[tauri::command]
+pub fn save_format_presets(
+    app: AppHandle,
+    presets: Vec<FormatPreset>,
+    default_preset: Option<String>,
+) -> Result<(), String> {
+    let presets: Vec<FormatPreset> = presets
+        .into_iter()
+        .map(|mut preset| {
+            preset.name = preset.name.trim().to_string();
+            preset
+        })
+        .collect();
+    validate_format_presets(&presets, default_preset.as_deref())?;
+    let mut settings = read_settings(&app);
+    settings.format_presets = presets;
+    settings.default_format_preset = default_preset;
+    write_settings(&app, settings)
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct ExportManifest {
@@ -410,4 +478,48 @@ mod tests {
         let videos = parse_versioned_videos("");
         assert!(videos.videos.is_empty());
     }
+
+    // =========================================================
+    // validate_format_presets
+    // =========================================================
+
+    fn named(name: &str) -> FormatPreset {
+        FormatPreset {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn presets_valid() {
+        let presets = vec![named("4K"), named("720p")];
+        assert!(validate_format_presets(&presets, Some("4K")).is_ok());
+        assert!(validate_format_presets(&presets, None).is_ok());
+    }
+
+    #[test]
+    fn presets_duplicate_or_empty_name() {
+        assert!(validate_format_presets(&[named("a"), named("a")], None).is_err());
+        assert!(validate_format_presets(&[named("  ")], None).is_err());
+    }
+
+    #[test]
+    fn presets_unknown_default() {
+        assert!(validate_format_presets(&[named("a")], Some("b")).is_err());
+    }
+
+    #[test]
+    fn parse_settings_keeps_format_presets() {
+        let content = serde_json::to_string(&json!({
+            "version": 1,
+            "data": {
+                "formatPresets": [{ "name": "4K", "codec": "vp9", "maxHeight": 2160, "maxFps": 60 }],
+                "defaultFormatPreset": "4K"
+            }
+        })).unwrap();
+        let settings = parse_versioned_settings(&content);
+        assert_eq!(settings.format_presets.len(), 1);
+        assert_eq!(settings.format_presets[0].max_height, Some(2160));
+        assert_eq!(settings.default_format_preset.as_deref(), Some("4K"));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,612 @@
+use std::collections::HashSet;
+use std::fs;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State};
+use crate::models::{
+    ChannelSubscription, ChannelVideoItem, DownloadQueueState, DownloadRequest, JobProcessState, NewVideosFound,
+    PersistedSettings, PersistedSubscriptions, RecordingScheduleState, SubscriptionAction, SubscriptionSettings,
+    SubscriptionState, VersionedSubscriptions,
+};
+use crate::journal::now_ms;
+use crate::metadata::{list_channel_videos, normalize_channel_base_url, spawn_metadata_download};
+use crate::paths::{atomic_write, subscriptions_file_path, videos_file_path, write_error_log};
+use crate::queue::enqueue_download;
+use crate::scheduler::schedule_recording;
+use crate::state::{parse_versioned_videos, read_settings};
+use crate::{
+    DEFAULT_SUBSCRIPTION_POLL_INTERVAL_SECS, MIN_SUBSCRIPTION_POLL_INTERVAL_SECS, SUBSCRIPTIONS_SCHEMA_VERSION,
+    SUBSCRIPTION_FETCH_LIMIT, SUBSCRIPTION_KNOWN_IDS_LIMIT, SUBSCRIPTION_POLLER_TICK_SECS,
+};
+
+impl Default for SubscriptionSettings {
+    fn default() -> Self {
+        Self {
+            poll_interval_secs: DEFAULT_SUBSCRIPTION_POLL_INTERVAL_SECS,
+            action: SubscriptionAction::Notify,
+            quality: None,
+            format_preset: None,
+            paused: false,
+        }
+    }
+}
+
+pub(crate) fn parse_versioned_subscriptions(content: &str) -> PersistedSubscriptions {
+    if let Ok(wrapper) = serde_json::from_str::<VersionedSubscriptions>(content) {
+        if wrapper.version <= SUBSCRIPTIONS_SCHEMA_VERSION {
+            return wrapper.data;
+        }
+        return PersistedSubscriptions::default();
+    }
+    serde_json::from_str::<PersistedSubscriptions>(content).unwrap_or_default()
+}
+
+/// 巡回間隔を下限以上に丸め、空欄の画質・プリセットは未指定として扱う
+pub(crate) fn normalize_subscription_settings(settings: SubscriptionSettings) -> SubscriptionSettings {
+    let trimmed = |value: Option<String>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    SubscriptionSettings {
+        poll_interval_secs: settings.poll_interval_secs.max(MIN_SUBSCRIPTION_POLL_INTERVAL_SECS),
+        action: settings.action,
+        quality: trimmed(settings.quality),
+        format_preset: trimmed(settings.format_preset),
+        paused: settings.paused,
+    }
+}
+
+/// 前回の巡回から間隔が過ぎたチャンネルを返す。一度も巡回していないチャンネルはすぐ対象になる。
+pub(crate) fn due_subscription_urls(items: &[ChannelSubscription], now_ms: u64) -> Vec<String> {
+    items
+        .iter()
+        .filter(|item| !item.settings.paused)
+        .filter(|item| {
+            item.last_checked_ms
+                .map(|checked| checked.saturating_add(item.settings.poll_interval_secs * 1000) <= now_ms)
+                .unwrap_or(true)
+        })
+        .map(|item| item.channel_url.clone())
+        .collect()
+}
+
+/// 購読で見たことがなく、ライブラリにもない動画を返す。
+/// 既知のIDが無い初回は現在の一覧を基準にするだけで、新着として扱わない。
+pub(crate) fn find_new_videos(
+    videos: &[ChannelVideoItem],
+    library_ids: &HashSet<String>,
+    subscription: &ChannelSubscription,
+) -> Vec<ChannelVideoItem> {
+    if subscription.known_ids.is_empty() {
+        return Vec::new();
+    }
+    let known: HashSet<&str> = subscription.known_ids.iter().map(String::as_str).collect();
+    videos
+        .iter()
+        .filter(|video| !known.contains(video.id.as_str()) && !library_ids.contains(&video.id))
+        .cloned()
+        .collect()
+}
+
+/// 一覧で見た動画IDを既知に加える。古いものから SUBSCRIPTION_KNOWN_IDS_LIMIT 件を超えた分を捨てる。
+pub(crate) fn remember_video_ids(known_ids: &mut Vec<String>, videos: &[ChannelVideoItem]) {
+    let existing: HashSet<String> = known_ids.iter().cloned().collect();
+    // 一覧は新しい順なので、古いものから追加して末尾が最新になるようにする
+    for video in videos.iter().rev() {
+        if !existing.contains(&video.id) {
+            known_ids.push(video.id.clone());
+        }
+    }
+    if known_ids.len() > SUBSCRIPTION_KNOWN_IDS_LIMIT {
+        let overflow = known_ids.len() - SUBSCRIPTION_KNOWN_IDS_LIMIT;
+        known_ids.drain(..overflow);
+    }
+}
+
+/// ライブラリ（videos.json）に登録済みの動画ID
+fn read_library_video_ids(app: &AppHandle) -> HashSet<String> {
+    videos_file_path(app)
+        .ok()
+        .filter(|path| path.exists())
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|content| parse_versioned_videos(&content).videos)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|video| video.get("id").and_then(|id| id.as_str()))
+        .map(|id| id.to_string())
+        .collect()
+}
+
+fn persist_subscriptions(app: &AppHandle, items: &[ChannelSubscription]) {
+    let Ok(path) = subscriptions_file_path(app) else {
+        return;
+    };
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let wrapper = VersionedSubscriptions {
+        version: SUBSCRIPTIONS_SCHEMA_VERSION,
+        data: PersistedSubscriptions {
+            items: items.to_vec(),
+        },
+    };
+    if let Ok(content) = serde_json::to_string_pretty(&wrapper) {
+        let _ = atomic_write(&path, content.as_bytes());
+    }
+}
+
+fn snapshot(state: &SubscriptionState) -> Vec<ChannelSubscription> {
+    state.items.lock().map(|items| items.clone()).unwrap_or_default()
+}
+
+fn commit_subscriptions(app: &AppHandle, state: &SubscriptionState) -> Vec<ChannelSubscription> {
+    let items = snapshot(state);
+    persist_subscriptions(app, &items);
+    let _ = app.emit("subscriptions-updated", items.clone());
+    items
+}
+
+fn download_request(
+    settings: &PersistedSettings,
+    subscription: &ChannelSubscription,
+    video: &ChannelVideoItem,
+    output_dir: &str,
+) -> DownloadRequest {
+    DownloadRequest {
+        id: video.id.clone(),
+        url: video.url.clone(),
+        output_dir: output_dir.to_string(),
+        cookies_file: settings.cookies_file.clone(),
+        cookies_source: settings.cookies_source.clone(),
+        cookies_browser: settings.cookies_browser.clone(),
+        remote_components: settings.remote_components.clone(),
+        yt_dlp_path: settings.yt_dlp_path.clone(),
+        ffmpeg_path: settings.ffmpeg_path.clone(),
+        quality: subscription
+            .settings
+            .quality
+            .clone()
+            .or_else(|| settings.download_quality.clone()),
+        is_live: None,
+        format_preset: subscription.settings.format_preset.clone(),
+        audio_format: None,
+        resume: None,
+    }
+}
+
+/// 新着動画のメタデータをバックグラウンドで1件ずつ順に取得する。
+/// 新着が多い場合や複数チャンネルの巡回が重なった場合でも、同時に取得するのは1件までにする。
+fn fetch_new_video_metadata(
+    app: &AppHandle,
+    settings: PersistedSettings,
+    videos: Vec<ChannelVideoItem>,
+    output_dir: String,
+) {
+    let app = app.clone();
+    std::thread::spawn(move || {
+        let state = app.state::<SubscriptionState>();
+        let _fetching = state
+            .metadata_fetch
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for video in videos {
+            let handle = spawn_metadata_download(
+                app.clone(),
+                app.state::<JobProcessState>().inner(),
+                video.id.clone(),
+                video.url.clone(),
+                output_dir.clone(),
+                settings.cookies_file.clone(),
+                settings.cookies_source.clone(),
+                settings.cookies_browser.clone(),
+                settings.remote_components.clone(),
+                settings.yt_dlp_path.clone(),
+                settings.ffmpeg_path.clone(),
+                None,
+                None,
+            );
+            if handle.join().is_err() {
+                let _ = write_error_log(&app, "subscription", &video.id, "", "メタデータ取得が異常終了しました。");
+            }
+        }
+    });
+}
+
+/// チャンネルごとの設定に従って新着を取り込む
+fn apply_subscription_action(app: &AppHandle, subscription: &ChannelSubscription, videos: &[ChannelVideoItem]) {
+    if subscription.settings.action == SubscriptionAction::Notify {
+        return;
+    }
+    let settings = read_settings(app);
+    let Some(output_dir) = settings.download_dir.clone().filter(|dir| !dir.trim().is_empty()) else {
+        let _ = write_error_log(
+            app,
+            "subscription",
+            &subscription.channel_url,
+            "",
+            "保存先フォルダが設定されていないため、新着を取り込めませんでした。",
+        );
+        return;
+    };
+    if subscription.settings.action == SubscriptionAction::Metadata {
+        fetch_new_video_metadata(app, settings, videos.to_vec(), output_dir);
+        return;
+    }
+    for video in videos {
+        let result = match subscription.settings.action {
+            SubscriptionAction::Notify | SubscriptionAction::Metadata => Ok(()),
+            SubscriptionAction::Download => {
+                let request = download_request(&settings, subscription, video, &output_dir);
+                // 配信予定・配信中の枠はキューではなく録画予約で扱う
+                match video.live_status.as_deref() {
+                    Some("is_upcoming") | Some("is_live") => schedule_recording(
+                        app.clone(),
+                        app.state::<RecordingScheduleState>(),
+                        request,
+                        video.release_timestamp,
+                        Some(video.title.clone()),
+                    )
+                    .map(|_| ()),
+                    _ => enqueue_download(app.clone(), app.state::<DownloadQueueState>(), request, None).map(|_| ()),
+                }
+            }
+        };
+        if let Err(err) = result {
+            let _ = write_error_log(app, "subscription", &video.id, "", &err);
+        }
+    }
+}
+
+/// 1チャンネル分を巡回して新着を返す。巡回中の場合は何もしない。
+pub(crate) fn poll_subscription(app: &AppHandle, channel_url: &str) -> Result<Vec<ChannelVideoItem>, String> {
+    let state = app.state::<SubscriptionState>();
+    let Some(subscription) = snapshot(&state)
+        .into_iter()
+        .find(|item| item.channel_url == channel_url)
+    else {
+        return Err("購読中のチャンネルが見つかりませんでした。".to_string());
+    };
+    {
+        let mut polling = state
+            .polling
+            .lock()
+            .map_err(|e| format!("購読の巡回に失敗しました: {}", e))?;
+        if !polling.insert(channel_url.to_string()) {
+            return Ok(Vec::new());
+        }
+    }
+
+    let settings = read_settings(app);
+    let fetched = list_channel_videos(
+        app.clone(),
+        subscription.channel_url.clone(),
+        settings.cookies_file.clone(),
+        settings.cookies_source.clone(),
+        settings.cookies_browser.clone(),
+        settings.remote_components.clone(),
+        settings.yt_dlp_path.clone(),
+        Some(SUBSCRIPTION_FETCH_LIMIT),
+        Some(subscription.known_ids.clone()),
+    );
+    let (videos, error) = match fetched {
+        Ok(videos) if videos.is_empty() => (videos, Some("動画一覧が取得できませんでした。".to_string())),
+        Ok(videos) => (videos, None),
+        Err(err) => (Vec::new(), Some(err)),
+    };
+    let new_videos = find_new_videos(&videos, &read_library_video_ids(app), &subscription);
+
+    if let Ok(mut items) = state.items.lock() {
+        if let Some(item) = items.iter_mut().find(|item| item.channel_url == channel_url) {
+            remember_video_ids(&mut item.known_ids, &videos);
+            item.last_checked_ms = Some(now_ms());
+            item.last_error = error.clone();
+            if item.title.is_none() {
+                item.title = videos.iter().find_map(|video| video.channel.clone());
+            }
+        }
+    }
+    commit_subscriptions(app, &state);
+    if let Ok(mut polling) = state.polling.lock() {
+        polling.remove(channel_url);
+    }
+
+    if let Some(err) = error {
+        return Err(err);
+    }
+    if !new_videos.is_empty() {
+        let _ = app.emit(
+            "new-videos-found",
+            NewVideosFound {
+                channel_url: subscription.channel_url.clone(),
+                title: subscription.title.clone(),
+                videos: new_videos.clone(),
+            },
+        );
+        apply_subscription_action(app, &subscription, &new_videos);
+    }
+    Ok(new_videos)
+}
+
+/// 起動時に購読を復元し、巡回間隔が過ぎたチャンネルを順に取得するスレッドを起動する
+pub(crate) fn start_subscription_poller(app: &AppHandle) {
+    let state = app.state::<SubscriptionState>();
+    let persisted = subscriptions_file_path(app)
+        .ok()
+        .filter(|path| path.exists())
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|content| parse_versioned_subscriptions(&content))
+        .unwrap_or_default();
+    if let Ok(mut items) = state.items.lock() {
+        *items = persisted.items;
+    }
+
+    let app = app.clone();
+    std::thread::spawn(move || loop {
+        let due = due_subscription_urls(&snapshot(&app.state::<SubscriptionState>()), now_ms());
+        for channel_url in due {
+            if let Err(err) = poll_subscription(&app, &channel_url) {
+                let _ = write_error_log(&app, "subscription", &channel_url, "", &err);
+            }
+        }
+        std::thread::sleep(Duration::from_secs(SUBSCRIPTION_POLLER_TICK_SECS));
+    });
+}
+
+#[tauri::command]
+pub fn subscribe_channel(
+    app: AppHandle,
+    state: State<SubscriptionState>,
+    url: String,
+    title: Option<String>,
+    settings: Option<SubscriptionSettings>,
+) -> Result<Vec<ChannelSubscription>, String> {
+    let channel_url = normalize_channel_base_url(url.trim());
+    if channel_url.is_empty() {
+        return Err("チャンネルのURLを入力してください。".to_string());
+    }
+    let settings = normalize_subscription_settings(settings.unwrap_or_default());
+    {
+        let mut items = state
+            .items
+            .lock()
+            .map_err(|e| format!("購読の更新に失敗しました: {}", e))?;
+        // 既に購読済みなら既知のIDを残したまま設定だけ更新する
+        if let Some(existing) = items.iter_mut().find(|item| item.channel_url == channel_url) {
+            existing.settings = settings;
+            if title.is_some() {
+                existing.title = title;
+            }
+        } else {
+            items.push(ChannelSubscription {
+                channel_url,
+                title,
+                settings,
+                known_ids: Vec::new(),
+                last_checked_ms: None,
+                last_error: None,
+            });
+        }
+    }
+    Ok(commit_subscriptions(&app, &state))
+}
+
+#[tauri::command]
+pub fn update_subscription(
+    app: AppHandle,
+    state: State<SubscriptionState>,
+    channel_url: String,
+    settings: SubscriptionSettings,
+) -> Result<Vec<ChannelSubscription>, String> {
+    {
+        let mut items = state
+            .items
+            .lock()
+            .map_err(|e| format!("購読の更新に失敗しました: {}", e))?;
+        let Some(item) = items.iter_mut().find(|item| item.channel_url == channel_url) else {
+            return Err("購読中のチャンネルが見つかりませんでした。".to_string());
+        };
+        item.settings = normalize_subscription_settings(settings);
+    }
+    Ok(commit_subscriptions(&app, &state))
+}
+
+#[tauri::command]
+pub fn unsubscribe_channel(
+    app: AppHandle,
+    state: State<SubscriptionState>,
+    channel_url: String,
+) -> Result<Vec<ChannelSubscription>, String> {
+    {
+        let mut items = state
+            .items
+            .lock()
+            .map_err(|e| format!("購読の更新に失敗しました: {}", e))?;
+        let Some(index) = items.iter().position(|item| item.channel_url == channel_url) else {
+            return Err("購読中のチャンネルが見つかりませんでした。".to_string());
+        };
+        items.remove(index);
+    }
+    Ok(commit_subscriptions(&app, &state))
+}
+
+#[tauri::command]
+pub fn get_subscriptions(state: State<SubscriptionState>) -> Result<Vec<ChannelSubscription>, String> {
+    Ok(snapshot(&state))
+}
+
+/// 巡回間隔を待たずにすぐ取得する。結果は subscriptions-updated / new-videos-found で通知する。
+#[tauri::command]
+pub fn check_subscription_now(app: AppHandle, channel_url: String) -> Result<(), String> {
+    std::thread::spawn(move || {
+        if let Err(err) = poll_subscription(&app, &channel_url) {
+            let _ = write_error_log(&app, "subscription", &channel_url, "", &err);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn video(id: &str) -> ChannelVideoItem {
+        ChannelVideoItem {
+            id: id.to_string(),
+            title: format!("title {}", id),
+            channel: Some("ch".to_string()),
+            url: format!("https://www.youtube.com/watch?v={}", id),
+            thumbnail: None,
+            webpage_url: None,
+            duration_sec: None,
+            upload_date: None,
+            release_timestamp: None,
+            timestamp: None,
+            live_status: None,
+            is_live: None,
+            was_live: None,
+            view_count: None,
+            like_count: None,
+            comment_count: None,
+            tags: None,
+            categories: None,
+            description: None,
+            channel_id: None,
+            uploader_id: None,
+            channel_url: None,
+            uploader_url: None,
+            availability: None,
+            language: None,
+            audio_language: None,
+            age_limit: None,
+        }
+    }
+
+    fn subscription(url: &str, known_ids: &[&str], last_checked_ms: Option<u64>) -> ChannelSubscription {
+        ChannelSubscription {
+            channel_url: url.to_string(),
+            title: None,
+            settings: SubscriptionSettings::default(),
+            known_ids: known_ids.iter().map(|id| id.to_string()).collect(),
+            last_checked_ms,
+            last_error: None,
+        }
+    }
+
+    // =========================================================
+    // find_new_videos / remember_video_ids
+    // =========================================================
+
+    #[test]
+    fn new_videos_exclude_known_and_library() {
+        let sub = subscription("https://www.youtube.com/@ch", &["old"], Some(1));
+        let library: HashSet<String> = ["inlib".to_string()].into();
+        let videos = vec![video("new2"), video("new1"), video("inlib"), video("old")];
+        let ids: Vec<String> = find_new_videos(&videos, &library, &sub)
+            .into_iter()
+            .map(|video| video.id)
+            .collect();
+        assert_eq!(ids, vec!["new2", "new1"]);
+    }
+
+    #[test]
+    fn first_poll_is_baseline_only() {
+        let sub = subscription("https://www.youtube.com/@ch", &[], None);
+        assert!(find_new_videos(&[video("a"), video("b")], &HashSet::new(), &sub).is_empty());
+    }
+
+    #[test]
+    fn remember_ids_appends_oldest_first_and_caps() {
+        let mut known = vec!["a".to_string()];
+        remember_video_ids(&mut known, &[video("c"), video("b"), video("a")]);
+        assert_eq!(known, vec!["a", "b", "c"]);
+
+        let mut known: Vec<String> = (0..SUBSCRIPTION_KNOWN_IDS_LIMIT).map(|i| i.to_string()).collect();
+        remember_video_ids(&mut known, &[video("newest")]);
+        assert_eq!(known.len(), SUBSCRIPTION_KNOWN_IDS_LIMIT);
+        assert_eq!(known.first().map(String::as_str), Some("1"));
+        assert_eq!(known.last().map(String::as_str), Some("newest"));
+    }
+
+    // =========================================================
+    // due_subscription_urls / normalize_subscription_settings
+    // =========================================================
+
+    #[test]
+    fn due_respects_interval_and_pause() {
+        let interval_ms = DEFAULT_SUBSCRIPTION_POLL_INTERVAL_SECS * 1000;
+        let mut paused = subscription("paused", &[], None);
+        paused.settings.paused = true;
+        let items = vec![
+            subscription("never", &[], None),
+            subscription("due", &["x"], Some(10_000)),
+            subscription("recent", &["x"], Some(10_000 + interval_ms)),
+            paused,
+        ];
+        assert_eq!(due_subscription_urls(&items, 10_000 + interval_ms), vec!["never", "due"]);
+    }
+
+    #[test]
+    fn normalize_clamps_interval_and_blanks() {
+        let settings = normalize_subscription_settings(SubscriptionSettings {
+            poll_interval_secs: 10,
+            action: SubscriptionAction::Download,
+            quality: Some(" ".to_string()),
+            format_preset: Some(" 4K ".to_string()),
+            paused: false,
+        });
+        assert_eq!(settings.poll_interval_secs, MIN_SUBSCRIPTION_POLL_INTERVAL_SECS);
+        assert_eq!(settings.quality, None);
+        assert_eq!(settings.format_preset.as_deref(), Some("4K"));
+    }
+
+    #[test]
+    fn download_request_uses_subscription_rules() {
+        let settings = PersistedSettings {
+            download_quality: Some("720p".to_string()),
+            yt_dlp_path: Some("/bin/yt-dlp".to_string()),
+            ..Default::default()
+        };
+        let mut sub = subscription("https://www.youtube.com/@ch", &[], None);
+        let request = download_request(&settings, &sub, &video("abc"), "/lib");
+        assert_eq!(request.quality.as_deref(), Some("720p"));
+        assert_eq!(request.url, "https://www.youtube.com/watch?v=abc");
+        assert_eq!(request.yt_dlp_path.as_deref(), Some("/bin/yt-dlp"));
+
+        sub.settings.quality = Some("1080p".to_string());
+        sub.settings.format_preset = Some("4K".to_string());
+        let request = download_request(&settings, &sub, &video("abc"), "/lib");
+        assert_eq!(request.quality.as_deref(), Some("1080p"));
+        assert_eq!(request.format_preset.as_deref(), Some("4K"));
+    }
+
+    // =========================================================
+    // parse_versioned_subscriptions
+    // =========================================================
+
+    #[test]
+    fn parse_subscriptions_versioned_and_defaults() {
+        let content = serde_json::to_string(&json!({
+            "version": 1,
+            "data": { "items": [
+                { "channelUrl": "https://www.youtube.com/@ch", "title": null },
+                { "channelUrl": "https://www.youtube.com/@b", "title": "B",
+                  "settings": { "pollIntervalSecs": 600, "action": "download" },
+                  "knownIds": ["x"], "lastCheckedMs": 5 }
+            ] }
+        }))
+        .unwrap();
+        let parsed = parse_versioned_subscriptions(&content);
+        assert_eq!(parsed.items.len(), 2);
+        assert_eq!(parsed.items[0].settings, SubscriptionSettings::default());
+        assert_eq!(parsed.items[1].settings.action, SubscriptionAction::Download);
+        assert_eq!(parsed.items[1].settings.poll_interval_secs, 600);
+        assert_eq!(parsed.items[1].known_ids, vec!["x"]);
+    }
+
+    #[test]
+    fn parse_subscriptions_future_version_or_invalid() {
+        assert!(parse_versioned_subscriptions(r#"{"version":99,"data":{"items":[]}}"#).items.is_empty());
+        assert!(parse_versioned_subscriptions("not json").items.is_empty());
+    }
+}
@@ -0,0 +1,349 @@
+use std::{fs, path::{Path, PathBuf}};
+use crate::models::{SubtitleCue, SubtitleTrack};
+use crate::paths::{library_metadata_dir, collect_files_recursive};
+use crate::SUBTITLE_FORMATS;
+
+/// 字幕取得用の yt-dlp 引数を組み立てる。言語指定が無い場合は None。
+/// live_chat はコメント取得側で扱うため、ここでは除外する。
+pub(crate) fn build_subtitle_args(langs: &[String], auto_generated: bool) -> Option<Vec<String>> {
+    let langs: Vec<&str> = langs
+        .iter()
+        .map(|lang| lang.trim())
+        .filter(|lang| !lang.is_empty() && *lang != "live_chat")
+        .collect();
+    if langs.is_empty() {
+        return None;
+    }
+    let mut args = vec!["--write-subs".to_string()];
+    if auto_generated {
+        args.push("--write-auto-subs".to_string());
+    }
+    args.push("--sub-langs".to_string());
+    args.push(langs.join(","));
+    args.push("--sub-format".to_string());
+    args.push(format!("{}/best", SUBTITLE_FORMATS.join("/")));
+    Some(args)
+}
+
+/// "Title [id].en.vtt" から ("en", "vtt") を取り出す
+pub(crate) fn subtitle_lang_and_format(name: &str) -> Option<(String, String)> {
+    let (rest, ext) = name.rsplit_once('.')?;
+    let ext = ext.to_lowercase();
+    if !SUBTITLE_FORMATS.contains(&ext.as_str()) {
+        return None;
+    }
+    let (_, lang) = rest.rsplit_once('.')?;
+    if lang.is_empty() || lang.eq_ignore_ascii_case("live_chat") || lang.contains(']') {
+        return None;
+    }
+    Some((lang.to_string(), ext))
+}
+
+pub(crate) fn find_subtitle_files(dir: &Path, id: &str) -> Vec<(String, String, PathBuf)> {
+    let id_marker = format!("[{}].", id.to_lowercase());
+    let mut out: Vec<(String, String, PathBuf)> = collect_files_recursive(dir)
+        .into_iter()
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?.to_string();
+            if !name.to_lowercase().contains(&id_marker) {
+                return None;
+            }
+            let (lang, format) = subtitle_lang_and_format(&name)?;
+            Some((lang, format, path))
+        })
+        .collect();
+    out.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+    out
+}
+
+fn parse_timestamp_ms(value: &str) -> Option<u64> {
+    let value = value.trim().replace(',', ".");
+    let (clock, millis) = value.split_once('.').unwrap_or((&value, "0"));
+    let mut seconds: u64 = 0;
+    for part in clock.split(':') {
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+    let millis: u64 = format!("{:0<3}", millis).get(..3)?.parse().ok()?;
+    Some(seconds * 1000 + millis)
+}
+
+fn strip_tags(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out
+}
+
+fn unescape_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+pub(crate) fn parse_vtt(content: &str) -> Vec<SubtitleCue> {
+    let mut cues = Vec::new();
+    let mut lines = content.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some((start, rest)) = line.split_once("-->") else {
+            continue;
+        };
+        let end = rest.split_whitespace().next().unwrap_or("");
+        let (Some(start_ms), Some(end_ms)) = (parse_timestamp_ms(start), parse_timestamp_ms(end)) else {
+            continue;
+        };
+        let mut text_lines = Vec::new();
+        while let Some(next) = lines.peek() {
+            if next.trim().is_empty() {
+                break;
+            }
+            let text = unescape_entities(&strip_tags(next)).trim().to_string();
+            if !text.is_empty() {
+                text_lines.push(text);
+            }
+            lines.next();
+        }
+        if !text_lines.is_empty() {
+            cues.push(SubtitleCue {
+                start_ms,
+                end_ms,
+                text: text_lines.join("\n"),
+            });
+        }
+    }
+    cues
+}
+
+fn xml_attr(tag: &str, name: &str) -> Option<String> {
+    let pattern = format!("{}=\"", name);
+    let start = tag
+        .match_indices(&pattern)
+        .find(|(index, _)| *index == 0 || tag[..*index].ends_with(char::is_whitespace))
+        .map(|(index, _)| index + pattern.len())?;
+    let end = tag[start..].find('"')? + start;
+    Some(tag[start..end].to_string())
+}
+
+pub(crate) fn parse_srv3(content: &str) -> Vec<SubtitleCue> {
+    let mut cues = Vec::new();
+    let mut rest = content;
+    while let Some(open) = rest.find("<p ") {
+        rest = &rest[open..];
+        let Some(tag_end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[3..tag_end];
+        let self_closing = tag.ends_with('/');
+        let (inner, next) = if self_closing {
+            ("", &rest[tag_end + 1..])
+        } else {
+            let body = &rest[tag_end + 1..];
+            match body.find("</p>") {
+                Some(close) => (&body[..close], &body[close + 4..]),
+                None => break,
+            }
+        };
+        rest = next;
+
+        let start_ms = xml_attr(tag, "t").and_then(|v| v.parse::<u64>().ok());
+        let duration_ms = xml_attr(tag, "d").and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+        let text = unescape_entities(&strip_tags(inner)).trim().to_string();
+        if let (Some(start_ms), false) = (start_ms, text.is_empty()) {
+            cues.push(SubtitleCue {
+                start_ms,
+                end_ms: start_ms + duration_ms,
+                text,
+            });
+        }
+    }
+    cues
+}
+
+pub(crate) fn parse_json3(content: &str) -> Vec<SubtitleCue> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(content) else {
+        return Vec::new();
+    };
+    let Some(events) = value.get("events").and_then(|v| v.as_array()) else {
+        return Vec::new();
+    };
+    events
+        .iter()
+        .filter_map(|event| {
+            let start_ms = event.get("tStartMs").and_then(|v| v.as_u64())?;
+            let duration_ms = event.get("dDurationMs").and_then(|v| v.as_u64()).unwrap_or(0);
+            let text: String = event
+                .get("segs")?
+                .as_array()?
+                .iter()
+                .filter_map(|seg| seg.get("utf8").and_then(|v| v.as_str()))
+                .collect();
+            let text = text.trim().to_string();
+            if text.is_empty() {
+                return None;
+            }
+            Some(SubtitleCue {
+                start_ms,
+                end_ms: start_ms + duration_ms,
+                text,
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn parse_subtitle_content(format: &str, content: &str) -> Vec<SubtitleCue> {
+    match format {
+        "vtt" => parse_vtt(content),
+        "srv3" => parse_srv3(content),
+        "json3" => parse_json3(content),
+        _ => Vec::new(),
+    }
+}
+
+#[tauri::command]
+pub fn get_subtitles(
+    id: String,
+    output_dir: String,
+    lang: Option<String>,
+) -> Result<Vec<SubtitleTrack>, String> {
+    let dir = library_metadata_dir(&output_dir);
+    let mut tracks: Vec<SubtitleTrack> = Vec::new();
+    for (file_lang, format, path) in find_subtitle_files(&dir, &id) {
+        if let Some(lang) = &lang {
+            if !file_lang.eq_ignore_ascii_case(lang) {
+                continue;
+            }
+        }
+        // 同じ言語が複数形式ある場合は先に見つかった形式（json3 > srv3 > vtt）を使う
+        if tracks.iter().any(|track| track.lang == file_lang) {
+            continue;
+        }
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("字幕ファイルの読み込みに失敗しました: {}", e))?;
+        tracks.push(SubtitleTrack {
+            cues: parse_subtitle_content(&format, &content),
+            lang: file_lang,
+            format,
+        });
+    }
+    Ok(tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // =========================================================
+    // build_subtitle_args
+    // =========================================================
+
+    #[test]
+    fn subtitle_args_with_auto() {
+        let args = build_subtitle_args(&["en".to_string(), " ja ".to_string()], true).unwrap();
+        assert_eq!(
+            args,
+            vec!["--write-subs", "--write-auto-subs", "--sub-langs", "en,ja", "--sub-format", "json3/srv3/vtt/best"]
+        );
+    }
+
+    #[test]
+    fn subtitle_args_empty_or_live_chat_only() {
+        assert!(build_subtitle_args(&[], false).is_none());
+        assert!(build_subtitle_args(&["live_chat".to_string(), "".to_string()], false).is_none());
+    }
+
+    // =========================================================
+    // subtitle_lang_and_format / find_subtitle_files
+    // =========================================================
+
+    #[test]
+    fn lang_and_format_from_name() {
+        assert_eq!(
+            subtitle_lang_and_format("Title [abc].en-US.vtt"),
+            Some(("en-US".to_string(), "vtt".to_string()))
+        );
+        assert_eq!(subtitle_lang_and_format("Title [abc].live_chat.json"), None);
+        assert_eq!(subtitle_lang_and_format("Title [abc].mp4"), None);
+        assert_eq!(subtitle_lang_and_format("Title [abc].vtt"), None);
+    }
+
+    #[test]
+    fn find_subtitle_files_matches_id() {
+        let dir = std::env::temp_dir().join("ylv_test_find_subtitles");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("ch")).unwrap();
+        fs::write(dir.join("ch/Title [abc].ja.json3"), "{}").unwrap();
+        fs::write(dir.join("ch/Title [abc].en.vtt"), "WEBVTT").unwrap();
+        fs::write(dir.join("ch/Other [xyz].en.vtt"), "WEBVTT").unwrap();
+        fs::write(dir.join("ch/Title [abc].info.json"), "{}").unwrap();
+        let found = find_subtitle_files(&dir, "abc");
+        let langs: Vec<&str> = found.iter().map(|(lang, _, _)| lang.as_str()).collect();
+        assert_eq!(langs, vec!["en", "ja"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    // =========================================================
+    // parsers
+    // =========================================================
+
+    #[test]
+    fn parse_vtt_basic() {
+        let content = "WEBVTT\nKind: captions\nLanguage: en\n\n00:00:01.000 --> 00:00:03.500 align:start position:0%\nHello <c>world</c>\n\n1\n00:01:02.250 --> 00:01:04.000\nline one\nline &amp; two\n";
+        let cues = parse_vtt(content);
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0].start_ms, 1000);
+        assert_eq!(cues[0].end_ms, 3500);
+        assert_eq!(cues[0].text, "Hello world");
+        assert_eq!(cues[1].start_ms, 62_250);
+        assert_eq!(cues[1].text, "line one\nline & two");
+    }
+
+    #[test]
+    fn parse_vtt_short_timestamps() {
+        let cues = parse_vtt("WEBVTT\n\n01.5 --> 02:03.25\ntext\n");
+        assert_eq!(cues[0].start_ms, 1500);
+        assert_eq!(cues[0].end_ms, 123_250);
+    }
+
+    #[test]
+    fn parse_srv3_basic() {
+        let content = r#"<?xml version="1.0" encoding="utf-8" ?><timedtext format="3"><body><p t="1200" d="800">Hi &amp; <s ac="0">there</s></p><p t="3000" d="500"></p><p t="4000" d="1000" w="1">next</p></body></timedtext>"#;
+        let cues = parse_srv3(content);
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0].start_ms, 1200);
+        assert_eq!(cues[0].end_ms, 2000);
+        assert_eq!(cues[0].text, "Hi & there");
+        assert_eq!(cues[1].start_ms, 4000);
+    }
+
+    #[test]
+    fn parse_json3_basic() {
+        let content = serde_json::to_string(&json!({
+            "events": [
+                { "tStartMs": 0, "dDurationMs": 5000, "id": 1 },
+                { "tStartMs": 100, "dDurationMs": 2000, "segs": [{ "utf8": "Hello" }, { "utf8": " world", "tOffsetMs": 500 }] },
+                { "tStartMs": 2100, "segs": [{ "utf8": "\n" }] }
+            ]
+        })).unwrap();
+        let cues = parse_json3(&content);
+        assert_eq!(cues.len(), 1);
+        assert_eq!(cues[0].start_ms, 100);
+        assert_eq!(cues[0].end_ms, 2100);
+        assert_eq!(cues[0].text, "Hello world");
+    }
+
+    #[test]
+    fn parse_json3_invalid() {
+        assert!(parse_json3("broken").is_empty());
+        assert!(parse_json3("{}").is_empty());
+    }
+}
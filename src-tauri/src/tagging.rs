@@ -0,0 +1,421 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use serde_json::Value;
+use tauri::{AppHandle, Emitter};
+use crate::audio::{escape_ffmetadata, image_mime_type, temp_path};
+use crate::files::{find_downloaded_video_file, find_info_json};
+use crate::models::{EmbedOptions, RetagFinished};
+use crate::paths::{library_metadata_dir, library_thumbnails_dir, library_videos_dir};
+use crate::thumbnails::find_existing_thumbnail;
+use crate::tooling::{resolve_ffmpeg, resolve_override};
+use crate::VIDEO_FILE_EXTENSIONS;
+
+impl EmbedOptions {
+    pub(crate) fn all() -> Self {
+        Self {
+            title: true,
+            description: true,
+            upload_date: true,
+            channel: true,
+            chapters: true,
+            thumbnail: true,
+        }
+    }
+
+    fn has_tags(&self) -> bool {
+        self.title || self.description || self.upload_date || self.channel
+    }
+}
+
+/// ダウンロード時に埋め込むための yt-dlp 引数。
+/// --embed-metadata は項目を選べないため、埋め込まない項目は空の meta_ フィールドで打ち消す。
+pub(crate) fn build_embed_args(options: &EmbedOptions) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    if options.has_tags() {
+        args.push("--embed-metadata".into());
+        let fields: [(bool, &[&str]); 4] = [
+            (options.title, &["meta_title"]),
+            (options.description, &["meta_description", "meta_synopsis"]),
+            (options.upload_date, &["meta_date"]),
+            (options.channel, &["meta_artist"]),
+        ];
+        for (enabled, names) in fields {
+            for name in names.iter().filter(|_| !enabled) {
+                args.push("--parse-metadata".into());
+                args.push(format!(":(?P<{}>)", name));
+            }
+        }
+        if options.channel {
+            // 既定ではアーティストに投稿者名が入るため、チャンネル名で上書きする
+            args.push("--parse-metadata".into());
+            args.push("%(channel,uploader)s:%(meta_artist)s".into());
+        }
+        // --embed-metadata はチャプターも埋め込むため、明示的に切り替える
+        if !options.chapters {
+            args.push("--no-embed-chapters".into());
+        }
+    }
+    if options.chapters {
+        args.push("--embed-chapters".into());
+    }
+    if options.thumbnail {
+        args.push("--embed-thumbnail".into());
+    }
+    args
+}
+
+/// info.json から ffmetadata 形式のタグとチャプターを組み立てる
+pub(crate) fn build_video_ffmetadata(info: &Value, options: &EmbedOptions) -> String {
+    let text = |key: &str| info.get(key).and_then(Value::as_str).filter(|s| !s.is_empty());
+    let mut lines = vec![";FFMETADATA1".to_string()];
+    let description = text("description").filter(|_| options.description);
+    let tags = [
+        ("title", text("title").filter(|_| options.title)),
+        ("description", description),
+        ("synopsis", description),
+        ("date", text("upload_date").filter(|_| options.upload_date)),
+        (
+            "artist",
+            text("channel").or_else(|| text("uploader")).filter(|_| options.channel),
+        ),
+    ];
+    for (key, value) in tags {
+        if let Some(value) = value {
+            lines.push(format!("{}={}", key, escape_ffmetadata(value)));
+        }
+    }
+
+    let chapters = info
+        .get("chapters")
+        .and_then(Value::as_array)
+        .filter(|_| options.chapters)
+        .cloned()
+        .unwrap_or_default();
+    let duration = info.get("duration").and_then(Value::as_f64);
+    for (index, chapter) in chapters.iter().enumerate() {
+        let Some(start) = chapter.get("start_time").and_then(Value::as_f64) else {
+            continue;
+        };
+        let end = chapter
+            .get("end_time")
+            .and_then(Value::as_f64)
+            .or_else(|| {
+                chapters
+                    .get(index + 1)
+                    .and_then(|next| next.get("start_time"))
+                    .and_then(Value::as_f64)
+            })
+            .or(duration)
+            .unwrap_or(start);
+        lines.push("[CHAPTER]".to_string());
+        lines.push("TIMEBASE=1/1000".to_string());
+        lines.push(format!("START={}", (start * 1000.0).round() as u64));
+        lines.push(format!("END={}", (end.max(start) * 1000.0).round() as u64));
+        if let Some(title) = chapter.get("title").and_then(Value::as_str) {
+            lines.push(format!("title={}", escape_ffmetadata(title)));
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// 既存ファイルのタグ・チャプター・カバー画像を書き直す ffmpeg 引数。
+/// mp4 は attached_pic、mkv は添付ファイルとしてカバー画像を入れる (webm は非対応のため入れない)。
+/// 既存のカバー画像は取り除き、映像・音声・字幕はそのままコピーする。
+pub(crate) fn build_retag_args(
+    input: &Path,
+    metadata: &Path,
+    cover: Option<&Path>,
+    options: &EmbedOptions,
+    output: &Path,
+) -> Vec<String> {
+    let extension = input
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+    let cover = cover.filter(|_| options.thumbnail);
+    let mut args: Vec<String> = vec!["-y".into(), "-v".into(), "error".into(), "-i".into()];
+    args.push(input.to_string_lossy().to_string());
+    args.extend(["-f".into(), "ffmetadata".into(), "-i".into()]);
+    args.push(metadata.to_string_lossy().to_string());
+    let attached_pic = cover.filter(|_| matches!(extension.as_str(), "mp4" | "m4v"));
+    if let Some(cover) = attached_pic {
+        args.push("-i".into());
+        args.push(cover.to_string_lossy().to_string());
+    }
+    args.extend([
+        "-map".into(),
+        "0:V?".into(),
+        "-map".into(),
+        "0:a?".into(),
+        "-map".into(),
+        "0:s?".into(),
+        "-map_metadata".into(),
+        "1".into(),
+        "-map_chapters".into(),
+        if options.chapters { "1" } else { "-1" }.into(),
+        "-c".into(),
+        "copy".into(),
+    ]);
+    if attached_pic.is_some() {
+        // 本編の映像は 1 本 (v:0) の前提で、カバー画像を v:1 として追加する
+        args.extend([
+            "-map".into(),
+            "2:v".into(),
+            "-c:v:1".into(),
+            "mjpeg".into(),
+            "-disposition:v:1".into(),
+            "attached_pic".into(),
+        ]);
+    } else if let Some(cover) = cover.filter(|_| extension == "mkv") {
+        let cover_ext = cover.extension().and_then(|e| e.to_str()).unwrap_or("jpg");
+        args.push("-attach".into());
+        args.push(cover.to_string_lossy().to_string());
+        args.extend([
+            "-metadata:s:t:0".into(),
+            format!("mimetype={}", image_mime_type(cover)),
+            "-metadata:s:t:0".into(),
+            format!("filename=cover.{}", cover_ext),
+        ]);
+    }
+    args.push(output.to_string_lossy().to_string());
+    args
+}
+
+/// 保存済みの動画ファイルに、ローカルの info.json とサムネイルからタグ・チャプター・カバー画像を埋め込み直す。
+/// options を省略した場合はすべて埋め込む。
+/// ffmpeg は別スレッドで実行し、終了時に "retag-finished" を通知する。
+#[tauri::command]
+pub async fn retag_video_file(
+    app: AppHandle,
+    id: String,
+    output_dir: String,
+    ffmpeg_path: Option<String>,
+    options: Option<EmbedOptions>,
+) -> Result<(), String> {
+    let options = options.unwrap_or_else(EmbedOptions::all);
+    let task_id = id.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        retag_video_file_blocking(&task_id, &output_dir, ffmpeg_path, &options)
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("メタデータの埋め込みに失敗しました: {}", e)));
+    let _ = app.emit(
+        "retag-finished",
+        RetagFinished {
+            id,
+            success: result.is_ok(),
+            error: result.as_ref().err().cloned(),
+        },
+    );
+    result
+}
+
+fn retag_video_file_blocking(
+    id: &str,
+    output_dir: &str,
+    ffmpeg_path: Option<String>,
+    options: &EmbedOptions,
+) -> Result<(), String> {
+    let video = find_downloaded_video_file(&library_videos_dir(output_dir), id)
+        .ok_or_else(|| "動画ファイルが見つかりません。".to_string())?;
+    let extension = video
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+    if !VIDEO_FILE_EXTENSIONS.contains(&extension.as_str()) {
+        return Err("動画ファイル以外には埋め込めません。".to_string());
+    }
+    let info_path = find_info_json(&library_metadata_dir(output_dir), id)
+        .ok_or_else(|| "info.json が見つかりません。".to_string())?;
+    let content = fs::read_to_string(&info_path)
+        .map_err(|e| format!("info.json の読み込みに失敗しました: {}", e))?;
+    let info: Value = serde_json::from_str(&content)
+        .map_err(|e| format!("info.json の解析に失敗しました: {}", e))?;
+    let cover = find_existing_thumbnail(&library_thumbnails_dir(output_dir), id);
+
+    let metadata_path = temp_path(&video, "ffmeta.txt");
+    fs::write(&metadata_path, build_video_ffmetadata(&info, options))
+        .map_err(|e| format!("メタデータファイルの作成に失敗しました: {}", e))?;
+    // 拡張子で出力形式が決まるため、一時ファイルも同じ拡張子にする
+    let output = temp_path(&video, &format!("retag.{}", extension));
+
+    let ffmpeg = resolve_override(ffmpeg_path).unwrap_or_else(resolve_ffmpeg);
+    let mut command = Command::new(ffmpeg);
+    #[cfg(windows)]
+    command.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    let result = command
+        .args(build_retag_args(&video, &metadata_path, cover.as_deref(), options, &output))
+        .output();
+    let _ = fs::remove_file(&metadata_path);
+    let output_status = result.map_err(|e| format!("ffmpegの起動に失敗しました: {}", e))?;
+    if !output_status.status.success() {
+        let _ = fs::remove_file(&output);
+        return Err(format!(
+            "メタデータの埋め込みに失敗しました: {}",
+            String::from_utf8_lossy(&output_status.stderr).trim()
+        ));
+    }
+    fs::rename(&output, &video).map_err(|e| {
+        let _ = fs::remove_file(&output);
+        format!("動画ファイルの置き換えに失敗しました: {}", e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // =========================================================
+    // build_embed_args
+    // =========================================================
+
+    #[test]
+    fn embed_args_all() {
+        let args = build_embed_args(&EmbedOptions::all());
+        assert_eq!(args[0], "--embed-metadata");
+        assert!(!args.iter().any(|arg| arg.starts_with(":(?P<")));
+        assert!(args.contains(&"--embed-chapters".to_string()));
+        assert!(args.contains(&"--embed-thumbnail".to_string()));
+        assert!(args.contains(&"%(channel,uploader)s:%(meta_artist)s".to_string()));
+    }
+
+    #[test]
+    fn embed_args_blank_disabled_fields() {
+        let args = build_embed_args(&EmbedOptions {
+            title: true,
+            upload_date: true,
+            ..Default::default()
+        });
+        let joined = args.join(" ");
+        assert!(joined.contains("--parse-metadata :(?P<meta_description>)"));
+        assert!(joined.contains("--parse-metadata :(?P<meta_synopsis>)"));
+        assert!(joined.contains("--parse-metadata :(?P<meta_artist>)"));
+        assert!(!joined.contains("meta_title"));
+        assert!(args.contains(&"--no-embed-chapters".to_string()));
+        assert!(!args.contains(&"--embed-thumbnail".to_string()));
+    }
+
+    #[test]
+    fn embed_args_chapters_only() {
+        let args = build_embed_args(&EmbedOptions {
+            chapters: true,
+            ..Default::default()
+        });
+        assert_eq!(args, vec!["--embed-chapters".to_string()]);
+        assert!(build_embed_args(&EmbedOptions::default()).is_empty());
+    }
+
+    // =========================================================
+    // build_video_ffmetadata
+    // =========================================================
+
+    fn info() -> Value {
+        json!({
+            "title": "Title; #1",
+            "description": "line1\nline2",
+            "upload_date": "20240102",
+            "uploader": "uploader",
+            "channel": "Channel",
+            "duration": 90.0,
+            "chapters": [
+                { "start_time": 0.0, "end_time": 30.5, "title": "Intro" },
+                { "start_time": 30.5, "title": "Main=Part" }
+            ]
+        })
+    }
+
+    #[test]
+    fn ffmetadata_tags_and_chapters() {
+        let content = build_video_ffmetadata(&info(), &EmbedOptions::all());
+        assert!(content.starts_with(";FFMETADATA1\n"));
+        assert!(content.contains("title=Title\\; \\#1\n"));
+        assert!(content.contains("description=line1\\\nline2\n"));
+        assert!(content.contains("date=20240102\n"));
+        assert!(content.contains("artist=Channel\n"));
+        assert!(content.contains("[CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=30500\ntitle=Intro\n"));
+        // 終了時刻が無い最後のチャプターは動画の長さまで
+        assert!(content.contains("START=30500\nEND=90000\ntitle=Main\\=Part\n"));
+    }
+
+    #[test]
+    fn ffmetadata_respects_options() {
+        let content = build_video_ffmetadata(
+            &info(),
+            &EmbedOptions {
+                title: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(content, ";FFMETADATA1\ntitle=Title\\; \\#1\n");
+    }
+
+    // =========================================================
+    // build_retag_args
+    // =========================================================
+
+    #[test]
+    fn retag_args_mp4_with_cover() {
+        let args = build_retag_args(
+            Path::new("/v/a [x].mp4"),
+            Path::new("/v/meta.txt"),
+            Some(Path::new("/t/x.webp")),
+            &EmbedOptions::all(),
+            Path::new("/v/out.mp4"),
+        );
+        let joined = args.join(" ");
+        assert!(joined.contains("-f ffmetadata -i /v/meta.txt -i /t/x.webp"));
+        assert!(joined.contains("-map 0:V? -map 0:a? -map 0:s?"));
+        assert!(joined.contains("-map_metadata 1 -map_chapters 1 -c copy"));
+        assert!(joined.contains("-map 2:v -c:v:1 mjpeg -disposition:v:1 attached_pic"));
+        assert_eq!(args.last().unwrap(), "/v/out.mp4");
+    }
+
+    #[test]
+    fn retag_args_mkv_attaches_cover() {
+        let args = build_retag_args(
+            Path::new("/v/a.mkv"),
+            Path::new("/v/meta.txt"),
+            Some(Path::new("/t/x.png")),
+            &EmbedOptions::all(),
+            Path::new("/v/out.mkv"),
+        );
+        let joined = args.join(" ");
+        assert!(joined.contains("-attach /t/x.png -metadata:s:t:0 mimetype=image/png"));
+        assert!(!joined.contains("attached_pic"));
+    }
+
+    #[test]
+    fn retag_args_without_cover_or_chapters() {
+        let options = EmbedOptions {
+            title: true,
+            thumbnail: false,
+            ..Default::default()
+        };
+        let args = build_retag_args(
+            Path::new("/v/a.mp4"),
+            Path::new("/v/meta.txt"),
+            Some(Path::new("/t/x.jpg")),
+            &options,
+            Path::new("/v/out.mp4"),
+        );
+        let joined = args.join(" ");
+        assert!(!joined.contains("/t/x.jpg"));
+        assert!(joined.contains("-map_chapters -1"));
+
+        // webm はカバー画像を入れられない
+        let args = build_retag_args(
+            Path::new("/v/a.webm"),
+            Path::new("/v/meta.txt"),
+            Some(Path::new("/t/x.jpg")),
+            &EmbedOptions::all(),
+            Path::new("/v/out.webm"),
+        );
+        assert!(!args.join(" ").contains("/t/x.jpg"));
+    }
+}
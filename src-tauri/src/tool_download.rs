@@ -6,6 +6,8 @@ use std::path::PathBuf;
 use futures_util::StreamExt;
 use tauri::{AppHandle, Emitter};
 
+use crate::network::{apply_network_client, network_settings};
+
 #[derive(Clone, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 struct ToolDownloadProgress {
@@ -50,8 +52,8 @@ async fn download_file_with_progress(
 ) -> Result<(), String> {
     emit_progress(app, tool_name, "downloading", 0, None, "ダウンロード開始...");
 
-    let client = reqwest::Client::builder()
-        .redirect(reqwest::redirect::Policy::limited(10))
+    let builder = reqwest::Client::builder().redirect(reqwest::redirect::Policy::limited(10));
+    let client = apply_network_client(builder, &network_settings(app))?
         .build()
         .map_err(|e| format!("HTTPクライアント作成失敗: {}", e))?;
 
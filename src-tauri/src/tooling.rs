@@ -6,6 +6,7 @@ use std::os::windows::process::CommandExt;
 use tauri::{AppHandle, Emitter};
 use crate::models::{ToolingCheckResult, ToolingCheckStatus};
 use crate::state::load_state;
+use crate::network::{apply_network_args, network_settings};
 
 pub(crate) fn apply_cookies_args(
     command: &mut Command,
@@ -369,6 +370,7 @@ pub(crate) fn update_yt_dlp_if_available(app: &AppHandle, yt_dlp: String) {
     let mut command = Command::new(&yt_dlp);
     #[cfg(windows)]
     command.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    apply_network_args(&mut command, &network_settings(app));
     let output = command.arg("-U").output();
     let output = match output {
         Ok(output) => output,
@@ -216,6 +216,42 @@ describe("useDownloadActions — startDownloadNow", () => {
     expect(params.setVideoErrors).toHaveBeenCalled();
     expect(params.onStartFailedRef.current).toHaveBeenCalledWith("v1");
   });
+
+  it("invoke失敗 (other) → バックエンドのメッセージを表示", async () => {
+    mockInvoke.mockRejectedValue({ kind: "other", message: "未対応の音声形式です: wav" });
+    const params = defaultParams();
+    const { result } = renderHook(() => useDownloadActions(params));
+
+    await act(async () => {
+      await result.current.startDownload(makeVideo());
+    });
+
+    const updater = params.setVideoErrors.mock.calls[0][0];
+    const errors = typeof updater === "function" ? updater({}) : updater;
+    expect(errors.v1).toBe("未対応の音声形式です: wav");
+  });
+
+  it("invoke失敗 (空き容量不足) → 必要量と空き容量を表示", async () => {
+    const mb = 1024 * 1024;
+    mockInvoke.mockRejectedValue({
+      kind: "insufficientDiskSpace",
+      requiredBytes: 900 * mb,
+      availableBytes: 500 * mb,
+      marginBytes: 100 * mb,
+    });
+    const params = defaultParams();
+    const { result } = renderHook(() => useDownloadActions(params));
+
+    await act(async () => {
+      await result.current.startDownload(makeVideo());
+    });
+
+    const updater = params.setVideoErrors.mock.calls[0][0];
+    const errors = typeof updater === "function" ? updater({}) : updater;
+    expect(errors.v1).toContain("1000");
+    expect(errors.v1).toContain("500");
+    expect(params.onStartFailedRef.current).toHaveBeenCalledWith("v1");
+  });
 });
 
 // ============================================================
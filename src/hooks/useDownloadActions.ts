@@ -47,6 +47,38 @@ type UseDownloadActionsParams<TVideo extends VideoLike> = {
   }) => void;
 };
 
+// start_download が返すエラー。kind で種類を判別する
+type DownloadStartError =
+  | {
+      kind: "insufficientDiskSpace";
+      requiredBytes: number;
+      availableBytes: number;
+      marginBytes: number;
+    }
+  | { kind: "other"; message: string };
+
+const BYTES_PER_MB = 1024 * 1024;
+
+function describeStartError(err: unknown): string {
+  if (typeof err === "string") {
+    return err || i18n.t('errors.ytdlpExecFailed');
+  }
+  if (err instanceof Error) {
+    return err.message || i18n.t('errors.ytdlpExecFailed');
+  }
+  const startError = err as Partial<DownloadStartError> | null;
+  if (startError?.kind === "insufficientDiskSpace") {
+    return i18n.t('errors.insufficientDiskSpace', {
+      required: Math.ceil(((startError.requiredBytes ?? 0) + (startError.marginBytes ?? 0)) / BYTES_PER_MB),
+      available: Math.floor((startError.availableBytes ?? 0) / BYTES_PER_MB),
+    });
+  }
+  if (startError?.kind === "other" && startError.message) {
+    return startError.message;
+  }
+  return i18n.t('errors.ytdlpExecFailed');
+}
+
 export function useDownloadActions<TVideo extends VideoLike>({
   downloadDirRef,
   videosRef,
@@ -122,7 +154,8 @@ export function useDownloadActions<TVideo extends VideoLike>({
           quality: downloadQuality || null,
           isLive: null,
         });
-      } catch {
+      } catch (err) {
+        const detail = describeStartError(err);
         setVideos((prev) =>
           prev.map((v) =>
             v.id === video.id ? { ...v, downloadStatus: "failed" } : v
@@ -130,11 +163,11 @@ export function useDownloadActions<TVideo extends VideoLike>({
         );
         setVideoErrors((prev) => ({
           ...prev,
-          [video.id]: i18n.t('errors.ytdlpExecFailed'),
+          [video.id]: detail,
         }));
         setProgressLines((prev) => ({
           ...prev,
-          [video.id]: i18n.t('errors.ytdlpExecFailed'),
+          [video.id]: detail,
         }));
         setErrorMessage(i18n.t('errors.downloadFailedDetails'));
         setDownloadingIds((prev) => prev.filter((id) => id !== video.id));